@@ -4,49 +4,141 @@ use std::fmt;
 use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
-use anyhow::{anyhow, bail, Context};
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use bytes::{BufMut, BytesMut};
 use chrono::{NaiveTime, Timelike};
 use clap::ValueEnum;
 use derivative::Derivative;
-use glow_effects::effects::shine::Shine;
-use glow_effects::util::color_point::{ColorPointContainer, RgbPoint};
-use glow_effects::util::effect::Effect;
-use glow_effects::util::point::Point;
 use log::debug;
 use palette::{FromColor, Hsl, IntoColor, Srgb};
 
-use reqwest::{Client, StatusCode};
-use serde::{Deserialize, Deserializer, Serialize};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::json;
 use tokio::net::UdpSocket;
 use tokio::time::{sleep, Instant};
 use uuid::Uuid;
 
+use crate::error::{Error, Result};
+use crate::led::animation::{from_fn, ShineAnimation, Solid};
+use crate::led::rgbw;
+use crate::led::rgbw::WhiteExtraction;
 use crate::util::auth::Auth;
 use crate::util::discovery::DeviceIdentifier;
+use crate::util::layout::Layout;
 use crate::util::movie::Movie;
 use crate::util::traits;
 use crate::util::traits::{ResponseCode, ResponseCodeTrait};
 
-/// Twinkly hardware version.
+mod realtime_session;
+mod trace;
+
+pub use realtime_session::{RealtimeSession, KEEPALIVE_INTERVAL};
+pub use trace::{HttpExchange, TraceHook};
+
+use trace::HttpClient;
+
+/// Twinkly hardware version, which determines the real-time protocol a device understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum HardwareVersion {
+    /// The LED count is sent in a single header byte, so at most 255 LEDs can be addressed.
+    #[value(name = "v1")]
     Version1,
+    /// Like version 1, without the LED count.
+    #[value(name = "v2")]
     Version2,
+    /// The frame is split into fragments of 900 bytes.
+    #[value(name = "v3")]
     Version3,
 }
 
+impl HardwareVersion {
+    /**
+    Picks the real-time protocol from the device's gestalt data and, if known, its firmware version.
+
+    Firmware before 2.4.14 only understands version 1, firmware before 2.4.22 version 2.
+    Without a firmware version, generation 1 devices (firmware family `D`) get version 1,
+    generation 2 devices (firmware family `F`) version 2, and everything else version 3.
+    Version 1 is never picked for more than 255 LEDs, since it can't address them.
+     */
+    pub fn detect(device_info: &DeviceInfoResponse, firmware_version: Option<&str>) -> Self {
+        let version = match firmware_version.and_then(parse_firmware_version) {
+            Some(firmware) if firmware < (2, 4, 14) => HardwareVersion::Version1,
+            Some(firmware) if firmware < (2, 4, 22) => HardwareVersion::Version2,
+            Some(_) => HardwareVersion::Version3,
+            None => match device_info.fw_family.as_str() {
+                "D" => HardwareVersion::Version1,
+                "F" => HardwareVersion::Version2,
+                _ => HardwareVersion::Version3,
+            },
+        };
+        if version == HardwareVersion::Version1 && device_info.number_of_led > u8::MAX as usize {
+            HardwareVersion::Version2
+        } else {
+            version
+        }
+    }
+}
+
+/// Parses a firmware version like `2.8.18` into a comparable tuple.
+fn parse_firmware_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version
+        .trim()
+        .split('.')
+        .map(|part| part.parse::<u32>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    let patch = parts.next().unwrap_or(Some(0))?;
+    Some((major, minor, patch))
+}
+
+/// The UDP port Twinkly devices listen on for real-time frames.
+pub const RT_PORT: u16 = 7777;
+
+/// Tokens are renewed this long before they expire, or after 90% of their lifetime if that's shorter.
+const TOKEN_RENEWAL_MARGIN: Duration = Duration::from_secs(300);
+
+/// An authentication token, shared by all clones of a [ControlInterface].
+#[derive(Debug, Clone)]
+struct AuthToken {
+    token: String,
+    /// When to renew the token, `None` if its lifetime is unknown.
+    renew_at: Option<Instant>,
+}
+
+impl AuthToken {
+    fn new(token: String, expires_in: Option<Duration>) -> Self {
+        let renew_at = expires_in.map(|expires_in| {
+            Instant::now() + expires_in - TOKEN_RENEWAL_MARGIN.min(expires_in / 10)
+        });
+        AuthToken { token, renew_at }
+    }
+
+    fn needs_renewal(&self) -> bool {
+        self.renew_at
+            .is_some_and(|renew_at| Instant::now() >= renew_at)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ControlInterface {
+    /// The device address, optionally followed by `:port` if the HTTP API isn't served on port 80.
     pub host: String,
     hw_address: String,
-    pub(crate) auth_token: String,
-    client: Client,
+    auth: Arc<RwLock<AuthToken>>,
+    /// Serializes renewals, so concurrent requests hitting an expired token authenticate only once.
+    renewal: Arc<tokio::sync::Mutex<()>>,
+    client: HttpClient,
     device_info: DeviceInfoResponse,
+    firmware_version: Option<String>,
+    rt_port: u16,
+    rt_protocol: HardwareVersion,
+    white_extraction: WhiteExtraction,
 }
 
 /**
@@ -128,7 +220,7 @@ impl ResponseCodeTrait for BrightnessResponse {
 }
 
 impl FromStr for DeviceMode {
-    type Err = anyhow::Error;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
@@ -139,7 +231,7 @@ impl FromStr for DeviceMode {
             "effect" => Ok(DeviceMode::Effect),
             "color" => Ok(DeviceMode::Color),
             "off" => Ok(DeviceMode::Off),
-            _ => Err(anyhow!("Invalid mode")),
+            _ => Err(Error::InvalidArgument(format!("Invalid mode: {}", s))),
         }
     }
 }
@@ -164,46 +256,205 @@ impl ControlInterface {
         host: &str,
         hw_address: &str,
         existing_auth_token: Option<String>,
-    ) -> anyhow::Result<Self> {
-        let client = Client::new();
+    ) -> Result<Self> {
+        ControlInterface::new_with_trace_hook(host, hw_address, existing_auth_token, None).await
+    }
 
-        let auth_token: String = if let Some(given_auth_token) = existing_auth_token {
-            given_auth_token
+    /**
+    Like [Self::new], passing every HTTP exchange to `trace_hook`,
+    including the authentication and the gestalt request made while connecting.
+     */
+    pub async fn new_with_trace_hook(
+        host: &str,
+        hw_address: &str,
+        existing_auth_token: Option<String>,
+        trace_hook: Option<TraceHook>,
+    ) -> Result<Self> {
+        let client = HttpClient::new(trace_hook);
+
+        let auth = if let Some(given_auth_token) = existing_auth_token {
+            AuthToken::new(given_auth_token, None)
         } else {
             ControlInterface::authenticate(&client, host, hw_address).await?
         };
+        let auth_token = auth.token.clone();
 
         // Fetch the device information
         let device_info = ControlInterface::fetch_device_info(&client, host, &auth_token).await?;
 
+        // Old firmware may not report its version, the protocol is then detected from gestalt alone
+        let firmware_version =
+            match ControlInterface::fetch_firmware_version(&client, host, &auth_token).await {
+                Ok(version) => Some(version),
+                Err(e) => {
+                    debug!("Failed to fetch firmware version: {}", e);
+                    None
+                }
+            };
+        let rt_protocol = HardwareVersion::detect(&device_info, firmware_version.as_deref());
+
         Ok(ControlInterface {
             host: host.to_string(),
             hw_address: hw_address.to_string(),
-            auth_token,
+            auth: Arc::new(RwLock::new(auth)),
+            renewal: Arc::default(),
             client,
             device_info,
+            firmware_version,
+            rt_port: RT_PORT,
+            rt_protocol,
+            white_extraction: WhiteExtraction::default(),
         })
     }
 
-    pub async fn reauthenticate(&mut self) -> bool {
-        if let Ok(result) =
-            ControlInterface::authenticate(&self.client, &self.host, &self.hw_address).await
-        {
-            self.auth_token = result;
-            true
-        } else {
-            false
-        }
+    /**
+    Authenticates again, replacing the token for this interface and all its clones.
+
+    Usually not needed: tokens are renewed before they expire, and requests
+    rejected with `401 Unauthorized` are retried once with a new token.
+     */
+    pub async fn reauthenticate(&self) -> bool {
+        let stale = self.auth_token();
+        self.renew_token(&stale).await.is_ok()
     }
 
     /**
     Updates the authentication token, after a device re-authenticated.
      */
     pub fn with_auth_token(mut self, auth_token: String) -> Self {
-        self.auth_token = auth_token;
+        self.auth = Arc::new(RwLock::new(AuthToken::new(auth_token, None)));
         self
     }
 
+    /// The current authentication token.
+    pub fn auth_token(&self) -> String {
+        self.auth.read().expect("auth token poisoned").token.clone()
+    }
+
+    /// Returns the current token, renewing it first if it's about to expire.
+    async fn current_token(&self) -> Result<String> {
+        let auth = self.auth.read().expect("auth token poisoned").clone();
+        if auth.needs_renewal() {
+            debug!("Authentication token is about to expire, renewing it");
+            self.renew_token(&auth.token).await
+        } else {
+            Ok(auth.token)
+        }
+    }
+
+    /// Replaces the `stale` token, unless a concurrent renewal already did.
+    async fn renew_token(&self, stale: &str) -> Result<String> {
+        let _renewal = self.renewal.lock().await;
+        {
+            let auth = self.auth.read().expect("auth token poisoned");
+            if auth.token != stale && !auth.needs_renewal() {
+                return Ok(auth.token.clone());
+            }
+        }
+        let auth =
+            ControlInterface::authenticate(&self.client, &self.host, &self.hw_address).await?;
+        let token = auth.token.clone();
+        *self.auth.write().expect("auth token poisoned") = auth;
+        Ok(token)
+    }
+
+    /**
+    Sends a request built by `build` with the current token.
+    If the device answers `401 Unauthorized`, authenticates again and retries once.
+     */
+    async fn send_authenticated<F>(&self, build: F) -> Result<Response>
+    where
+        F: Fn(&HttpClient) -> RequestBuilder,
+    {
+        let token = self.current_token().await?;
+        let response = self
+            .client
+            .send(build(&self.client).header("X-Auth-Token", &token))
+            .await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        debug!("Device rejected the authentication token, authenticating again");
+        let token = self.renew_token(&token).await?;
+        self.client
+            .send(build(&self.client).header("X-Auth-Token", &token))
+            .await
+    }
+
+    /**
+    Passes every following HTTP exchange with the device to `trace_hook`, for debugging.
+    Applies to all clones made afterwards.
+     */
+    pub fn with_trace_hook<F>(mut self, trace_hook: F) -> Self
+    where
+        F: Fn(&HttpExchange) + Send + Sync + 'static,
+    {
+        self.client = HttpClient::new(Some(Arc::new(trace_hook)));
+        self
+    }
+
+    /**
+    Sets the UDP port real-time frames are sent to, instead of the default [RT_PORT].
+    Useful when talking to an emulated device, see the `emulator` module.
+     */
+    pub fn with_rt_port(mut self, rt_port: u16) -> Self {
+        self.rt_port = rt_port;
+        self
+    }
+
+    /**
+    Overrides the real-time protocol detected by [HardwareVersion::detect].
+     */
+    pub fn with_rt_protocol(mut self, rt_protocol: HardwareVersion) -> Self {
+        self.rt_protocol = rt_protocol;
+        self
+    }
+
+    /// The real-time protocol used for frames sent to this device.
+    pub fn rt_protocol(&self) -> HardwareVersion {
+        self.rt_protocol
+    }
+
+    /**
+    Sets how colors are split into color and white channels for RGBW devices,
+    in real-time frames and uploaded movies.
+     */
+    pub fn with_white_extraction(mut self, white_extraction: WhiteExtraction) -> Self {
+        self.white_extraction = white_extraction;
+        self
+    }
+
+    /// The strategy for the white channel of RGBW devices, see [Self::with_white_extraction].
+    pub fn white_extraction(&self) -> WhiteExtraction {
+        self.white_extraction
+    }
+
+    /// Packs RGB colors into the byte layout of the device, see [rgbw::pack_frame].
+    pub fn pack_frame(&self, frame: &[(u8, u8, u8)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        rgbw::pack_frame(
+            frame,
+            self.device_info.led_profile,
+            self.white_extraction,
+            &mut data,
+        );
+        data
+    }
+
+    /// The firmware version, if the device reported it.
+    pub fn get_firmware_version(&self) -> Option<&str> {
+        self.firmware_version.as_deref()
+    }
+
+    /// The host without the HTTP port, which is where the real-time UDP endpoint lives.
+    fn rt_host(&self) -> &str {
+        match self.host.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => host,
+            _ => &self.host,
+        }
+    }
+
     /**
     Creates a mock / demo [DeviceInfoResponse].
     A utility function for [Self::new_mock_control_interface].
@@ -255,21 +506,25 @@ impl ControlInterface {
         auth_token: String,
         device_info: DeviceInfoResponse,
     ) -> Self {
+        let rt_protocol = HardwareVersion::detect(&device_info, None);
         ControlInterface {
             host,
             hw_address,
-            auth_token,
-            client: Client::new(),
+            auth: Arc::new(RwLock::new(AuthToken::new(auth_token, None))),
+            renewal: Arc::default(),
+            client: HttpClient::default(),
             device_info,
+            firmware_version: None,
+            rt_port: RT_PORT,
+            rt_protocol,
+            white_extraction: WhiteExtraction::default(),
         }
     }
 
     /**
     Creates a [ControlInterface] by a [DeviceIdentifier].
     */
-    pub async fn from_device_identifier(
-        device_identifier: DeviceIdentifier,
-    ) -> anyhow::Result<Self> {
+    pub async fn from_device_identifier(device_identifier: DeviceIdentifier) -> Result<Self> {
         ControlInterface::new(
             device_identifier.ip_address.to_string().as_str(),
             device_identifier.mac_address.to_string().as_str(),
@@ -282,6 +537,11 @@ impl ControlInterface {
         self.hw_address.clone()
     }
 
+    /**
+    Shows LEDs glowing up and fading out at random in `colors`, see [ShineAnimation].
+
+    Runs until interrupted.
+     */
     pub async fn shine_leds(
         &self,
         time_between_glow_start: Duration,
@@ -290,81 +550,23 @@ impl ControlInterface {
         colors: HashSet<RGB>,
         frame_rate: f64,
         num_start_simultaneous: usize,
-    ) -> anyhow::Result<()> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        socket.connect((self.host.as_str(), 7777)).await?;
-        self.set_mode(DeviceMode::RealTime).await?;
-
-        let num_leds = self.device_info.number_of_led;
-        let leds = vec![
-            RgbPoint::new(
-                Point {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                glow_effects::util::color::RGB {
-                    red: 0,
-                    green: 0,
-                    blue: 0,
-                }
-            );
-            num_leds
-        ];
-        // convert colors to glow_effects::util::color::RGB
-        let colors = colors
-            .into_iter()
-            .map(|color| glow_effects::util::color::RGB {
-                red: color.red,
-                green: color.green,
-                blue: color.blue,
-            });
-
-        // colors should be HashSet
-        let colors: HashSet<glow_effects::util::color::RGB> = colors.into_iter().collect();
-        let milliseconds_between_frames = (1.0 / frame_rate * 1000.0) as u128;
-        let frames_between_glow_start =
-            (time_between_glow_start.as_millis() / milliseconds_between_frames) as u32;
-        let frames_to_max_glow =
-            (time_to_max_glow.as_millis() / milliseconds_between_frames) as u32;
-        let frames_to_fade = (time_to_fade.as_millis() / milliseconds_between_frames) as u32;
-        let mut effect = Shine::new(
-            leds,
+    ) -> Result<()> {
+        let mut shine = ShineAnimation::new(
+            self.device_info.number_of_led,
             colors,
-            frames_between_glow_start,
-            frames_to_max_glow,
-            frames_to_fade,
+            time_between_glow_start,
+            time_to_max_glow,
+            time_to_fade,
             num_start_simultaneous,
+            frame_rate,
         )?;
-
-        for frame in effect.iter() {
-            // convert frame to Vec<(u8, u8, u8)>
-            let frame = frame
-                .iter()
-                .map(|point| {
-                    let color = point.get_color_value();
-                    (color.red, color.green, color.blue)
-                })
-                .collect();
-            let flattened_frame = ControlInterface::flatten_rgb_vec(frame);
-            self.set_rt_frame_socket(&socket, &flattened_frame, HardwareVersion::Version3)
-                .await?;
-            sleep(Duration::from_secs_f64(1.0 / frame_rate)).await;
-        }
-        Ok(())
+        self.play_animation(&mut shine, frame_rate).await
     }
 
-    pub async fn show_solid_color(&self, rgb: RGB) -> anyhow::Result<()> {
-        let frame = vec![(rgb.red, rgb.green, rgb.blue); self.device_info.number_of_led];
-        let flattened_frame = ControlInterface::flatten_rgb_vec(frame);
-        self.set_mode(DeviceMode::RealTime).await?;
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        socket.connect((self.host.as_str(), 7777)).await?;
-        loop {
-            self.set_rt_frame_socket(&socket, &flattened_frame, HardwareVersion::Version3)
-                .await?;
-            sleep(Duration::from_millis(100)).await;
-        }
+    /// Shows one color on all LEDs until interrupted.
+    pub async fn show_solid_color(&self, rgb: RGB) -> Result<()> {
+        self.play_animation(&mut Solid::new((rgb.red, rgb.green, rgb.blue)), 10.0)
+            .await
     }
 
     pub async fn show_real_time_stdin_stream(
@@ -373,12 +575,12 @@ impl ControlInterface {
         error_mode: RtStdinErrorMode,
         leds_per_frame: u16,
         min_frame_time: Duration,
-    ) -> anyhow::Result<()> {
+    ) -> Result<()> {
         let stream = std::io::stdin();
         let mut reader = BufReader::new(stream);
         // number of LEDs
         let mut current_frame = vec![(0, 0, 0); self.device_info.number_of_led];
-        self.set_mode(DeviceMode::RealTime).await?;
+        let mut session = self.realtime_session().await?;
         loop {
             let mut leds_read: Vec<AddressableLed> = Vec::new();
             let time_at_last_frame = Instant::now();
@@ -398,11 +600,14 @@ impl ControlInterface {
                     }
                     RtStdinErrorMode::StopInvalidAddress => {
                         if led.address >= self.device_info.number_of_led as u16 {
-                            bail!("Invalid LED address: {:?}", led);
+                            return Err(Error::InvalidArgument(format!(
+                                "Invalid LED address: {:?}",
+                                led
+                            )));
                         }
                     }
                 }
-                println!("LED: {:?}", led);
+                debug!("LED: {:?}", led);
                 leds_read.push(led);
 
                 AddressableLed::merge_frame_array(&leds_read, &mut current_frame);
@@ -417,18 +622,14 @@ impl ControlInterface {
                 sleep(min_frame_time - time_since_last_frame).await;
             }
 
-            let network_frame = ControlInterface::flatten_rgb_vec(current_frame.clone().to_vec());
-            let socket = UdpSocket::bind("0.0.0.0:0").await?;
-            socket.connect((self.host.as_str(), 7777)).await?;
-            self.set_rt_frame_socket(&socket, &network_frame, HardwareVersion::Version3)
-                .await?;
+            session.send_frame(&self.pack_frame(&current_frame)).await?;
         }
     }
 
     async fn show_real_time_stdin_stream_binary(
         &self,
         reader: &mut BufReader<impl Read>,
-    ) -> anyhow::Result<AddressableLed> {
+    ) -> Result<AddressableLed> {
         let mut buffer = [0; 5];
         reader.read_exact(&mut buffer)?;
 
@@ -450,7 +651,7 @@ impl ControlInterface {
     async fn show_real_time_stdin_stream_jsonl(
         &self,
         reader: &mut BufReader<impl Read>,
-    ) -> anyhow::Result<AddressableLed> {
+    ) -> Result<AddressableLed> {
         // Read a line from the input stream
         let mut line = String::new();
         reader.read_line(&mut line)?;
@@ -462,33 +663,19 @@ impl ControlInterface {
         Ok(led)
     }
 
-    pub async fn show_real_time_test_color_wheel(
-        &self,
-        step: f64,
-        frame_rate: f64,
-    ) -> anyhow::Result<()> {
-        let interval = Duration::from_secs_f64(1.0 / frame_rate);
-        let mut offset = 0_f64;
-        self.set_mode(DeviceMode::RealTime).await?;
+    /**
+    Shows a rainbow along the Z axis of the layout, moving by `step` of its length per frame.
+
+    Runs until interrupted.
+     */
+    pub async fn show_real_time_test_color_wheel(&self, step: f64, frame_rate: f64) -> Result<()> {
         let layout = self.fetch_layout().await?;
-        loop {
-            // let gradient_frame = generate_color_wheel_gradient(self.device_info.number_of_led, offset as usize);
-            let gradient_frame =
-                generate_color_gradient_along_axis(&layout.coordinates, Axis::Z, offset);
-            let gradient_frame = ControlInterface::flatten_rgb_vec(gradient_frame);
-            let socket = UdpSocket::bind("0.0.0.0:0").await?;
-            socket.connect((self.host.as_str(), 7777)).await?;
-            self.set_rt_frame_socket(&socket, &gradient_frame, HardwareVersion::Version3)
-                .await?;
-
-            // Increment the offset for the next frame
-            offset = (offset + step) % 1.0;
-
-            // Sleep for the interval duration to maintain the frame rate
-            sleep(interval).await;
-
-            println!("Offset: {}", offset);
-        }
+        let mut wheel = from_fn(move |time, _| {
+            let offset = (time * frame_rate * step).rem_euclid(1.0);
+            debug!("Offset: {}", offset);
+            generate_color_gradient_along_axis(&layout.coordinates, Axis::Z, offset)
+        });
+        self.play_animation(&mut wheel, frame_rate).await
     }
 
     pub fn flatten_rgb_vec(rgb_vec: Vec<(u8, u8, u8)>) -> Vec<u8> {
@@ -499,32 +686,30 @@ impl ControlInterface {
     }
 
     /**
-    Set realtime frame with a provided socket.
+    Set realtime frame with a provided socket, using the protocol from [Self::rt_protocol].
 
     # Return
     Returns either the written bytes or an error.
      */
-    pub async fn set_rt_frame_socket(
-        &self,
-        socket: &UdpSocket,
-        frame: &[u8],
-        version: HardwareVersion,
-    ) -> anyhow::Result<usize> {
-        // Determine the protocol version from the device configuration
-        // let version = self.device_info.fw_version; // Assuming fw_version is a field in DeviceInfoResponse
-
+    pub async fn set_rt_frame_socket(&self, socket: &UdpSocket, frame: &[u8]) -> Result<usize> {
         // Decode the access token
         let access_token = STANDARD
-            .decode(&self.auth_token)
-            .context("Failed to decode access token")?;
+            .decode(self.current_token().await?)
+            .map_err(|e| Error::Authentication(format!("Failed to decode access token: {}", e)))?;
 
         // Prepare the packet based on the protocol version
         let mut packet = BytesMut::new();
-        match version {
+        match self.rt_protocol {
             HardwareVersion::Version1 => {
+                let number_of_led = u8::try_from(self.device_info.number_of_led).map_err(|_| {
+                    Error::InvalidArgument(format!(
+                        "Real-time protocol version 1 can't address {} LEDs",
+                        self.device_info.number_of_led
+                    ))
+                })?;
                 packet.put_u8(1); // Protocol version 1
                 packet.extend_from_slice(&access_token);
-                packet.put_u8(self.device_info.number_of_led as u8); // Number of LEDs
+                packet.put_u8(number_of_led); // Number of LEDs
                 packet.extend_from_slice(frame);
             }
             HardwareVersion::Version2 => {
@@ -546,12 +731,12 @@ impl ControlInterface {
                     packet.extend_from_slice(chunk);
                     let send_result: std::io::Result<usize> = socket.send(&packet).await;
 
-                    if let Ok(send_result) = send_result {
-                        written_bytes += send_result;
-                    } else if let Some(err) = send_result.err() {
-                        let err_string = format!("Failed to send frame {}: {:?}", i, err);
-                        debug!("{}", err_string);
-                        return Err(anyhow!(err_string));
+                    match send_result {
+                        Ok(send_result) => written_bytes += send_result,
+                        Err(err) => {
+                            debug!("Failed to send frame {}: {:?}", i, err);
+                            return Err(err.into());
+                        }
                     }
                 }
                 return Ok(written_bytes); // Early return for version 3
@@ -559,18 +744,35 @@ impl ControlInterface {
         }
 
         // Send the packet for versions 1 and 2
-        socket.send(&packet).await.map_err(|err| anyhow!(err))
+        Ok(socket.send(&packet).await?)
+    }
+
+    /**
+    Starts a [RealtimeSession], which keeps one socket open and paces frames for continuous animations.
+     */
+    pub async fn realtime_session(&self) -> Result<RealtimeSession> {
+        RealtimeSession::start(self, KEEPALIVE_INTERVAL).await
+    }
+
+    /**
+    Starts a [RealtimeSession] which repeats the last frame after `keepalive_interval` without frames.
+     */
+    pub async fn realtime_session_with_keepalive(
+        &self,
+        keepalive_interval: Duration,
+    ) -> Result<RealtimeSession> {
+        RealtimeSession::start(self, keepalive_interval).await
     }
 
     /**
     Ensures the mode is [`DeviceMode::RealTime`], creates a UDP socket, connect it and send a frame to it.
     For a continuous animation, without constant socket recreation and rebinding,
-    use [`Self::set_rt_frame_socket`] instead.
+    use [`Self::realtime_session`] instead.
 
     # Return
     Returns either the written bytes or an error.
      */
-    pub async fn show_rt_frame(&self, frame: &[u8]) -> anyhow::Result<usize> {
+    pub async fn show_rt_frame(&self, frame: &[u8]) -> Result<usize> {
         // Fetch the current mode from the device
         let mode_response = self.get_mode().await?;
         let current_mode = mode_response;
@@ -581,66 +783,80 @@ impl ControlInterface {
         }
 
         let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        socket.connect((self.host.as_str(), 7777)).await?;
+        socket.connect((self.rt_host(), self.rt_port)).await?;
         // Call the set_rt_frame_socket method to send the frame
-        self.set_rt_frame_socket(&socket, frame, HardwareVersion::Version3)
-            .await
-            .map_err(|err| anyhow!(err))
+        self.set_rt_frame_socket(&socket, frame).await
     }
 
     pub fn get_device_info(&self) -> &DeviceInfoResponse {
         &self.device_info
     }
     async fn fetch_device_info(
-        client: &Client,
+        client: &HttpClient,
         host: &str,
         auth_token: &str,
-    ) -> anyhow::Result<DeviceInfoResponse> {
+    ) -> Result<DeviceInfoResponse> {
         let url = format!("http://{}/xled/v1/gestalt", host);
-        let response = client
-            .get(&url)
-            .header("X-Auth-Token", auth_token)
-            .send()
-            .await
-            .map_err(|e| anyhow!("Failed to fetch layout: {}", e))?;
+        let request = client.get(&url).header("X-Auth-Token", auth_token);
+        let response = client.send(request).await?;
 
         if response.status() != reqwest::StatusCode::OK {
-            return Err(anyhow!(
-                "Failed to fetch device info with status: {}",
-                response.status()
-            ));
+            return Err(Error::HttpStatus {
+                operation: "fetch device info",
+                status: response.status(),
+            });
         }
-        let response = response.text().await?;
-        // println!("Response: {}", response);
-        let device_info: DeviceInfoResponse = serde_json::from_str(&response)?;
-        // let device_info = response
-        //     .json::<DeviceInfoResponse>()
-        //     .await
-        //     .map_err(|e| anyhow!("Failed to deserialize device info: {}", e))?;
+        read_checked_json::<DeviceInfoResponse>(response).await
+    }
 
-        Ok(device_info)
+    async fn fetch_firmware_version(
+        client: &HttpClient,
+        host: &str,
+        auth_token: &str,
+    ) -> Result<String> {
+        let url = format!("http://{}/xled/v1/fw/version", host);
+        let request = client.get(&url).header("X-Auth-Token", auth_token);
+        let response = client.send(request).await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(Error::HttpStatus {
+                operation: "fetch firmware version",
+                status: response.status(),
+            });
+        }
+        let firmware = read_checked_json::<FirmwareVersionResponse>(response).await?;
+        Ok(firmware.version)
     }
 
-    /// Uploads a new movie to the device.
+    /**
+    Sets the movie config for a movie file, and uploads it as the device's only movie.
+
+    The movie plays at `fps`, or at the fps from the movie file if `None`.
+    With `force`, the stored movies are cleared first instead of failing if the movie doesn't fit.
+
+    # Errors
+    [Error::MovieFormat] if the movie doesn't have one color per LED of the device,
+    or if `led_profile` doesn't match the device's bytes per LED.
+     */
     pub async fn upload_movie<P: AsRef<Path>>(
         &self,
         path: P,
         led_profile: LedProfile,
-        _fps: f64,
+        fps: Option<f64>,
         force: bool,
-    ) -> anyhow::Result<u32> {
-        let movie = Movie::load_movie(path, led_profile)?;
+    ) -> Result<u32> {
+        let movie = Movie::load(path, led_profile, self.white_extraction)?;
+        self.check_movie_matches_device(&movie, led_profile)?;
+        let frame_delay = frame_delay_for_fps(fps.unwrap_or(movie.fps))?;
         let num_frames = movie.frames.len();
-        let _num_leds = self.device_info.number_of_led;
-        let _bytes_per_led = match led_profile {
-            LedProfile::RGB => 3,
-            LedProfile::RGBW => 4,
-        };
 
         // Check if the movie fits in the remaining capacity
         let capacity = self.get_device_capacity().await?;
         if num_frames > capacity && !force {
-            return Err(anyhow!("Not enough capacity for the movie"));
+            return Err(Error::Capacity {
+                required: num_frames,
+                available: capacity,
+            });
         }
 
         // Clear existing movies if necessary
@@ -648,38 +864,103 @@ impl ControlInterface {
             self.clear_movies().await?;
         }
 
+        // The device splits the upload into frames by the config, so it's sent first
+        self.set_movie_config(frame_delay, self.device_info.number_of_led, num_frames)
+            .await?;
+
         // Convert the movie to the binary format expected by the device
-        let movie_data = Movie::to_movie(movie.frames, led_profile);
+        let movie_data = rgbw::pack_frames(&movie.frames, led_profile, self.white_extraction);
 
         // Upload the movie to the device
         let url = format!("http://{}/xled/v1/led/movie/full", self.host);
         let response = self
-            .client
-            .post(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .body(movie_data)
-            .send()
+            .send_authenticated(|client| {
+                client
+                    .post(&url)
+                    .header("Content-Type", "application/octet-stream")
+                    .body(movie_data.clone())
+            })
             .await?;
 
-        match response.status() {
+        let id = match response.status() {
             StatusCode::OK => {
-                let response_text = response.text().await?;
-                let response_json: serde_json::Value = serde_json::from_str(&response_text)?;
-                if let Some(id) = response_json["id"].as_u64() {
-                    Ok(id as u32)
-                } else {
-                    Err(anyhow!("Failed to get movie ID from response"))
+                let response_json = read_checked_value(response).await?;
+                match response_json["id"].as_u64() {
+                    Some(id) => id as u32,
+                    None => return Err(missing_field("id")),
                 }
             }
-            _ => Err(anyhow!(
-                "Failed to upload movie with status: {}",
-                response.status()
-            )),
+            _ => {
+                return Err(Error::HttpStatus {
+                    operation: "upload movie",
+                    status: response.status(),
+                })
+            }
+        };
+
+        Ok(id)
+    }
+
+    /// Fetches the config of the movie uploaded with [Self::upload_movie].
+    pub async fn get_movie_config(&self) -> Result<MovieConfigResponse> {
+        let url = format!("http://{}/xled/v1/led/movie/config", self.host);
+        let response = self.send_authenticated(|client| client.get(&url)).await?;
+        read_ok_json(response, "get movie config").await
+    }
+
+    /// Sets how the movie uploaded with [Self::upload_movie] is played.
+    pub async fn set_movie_config(
+        &self,
+        frame_delay: Duration,
+        leds_number: usize,
+        frames_number: usize,
+    ) -> Result<()> {
+        let url = format!("http://{}/xled/v1/led/movie/config", self.host);
+        let response = self
+            .send_authenticated(|client| {
+                client.post(&url).json(&json!({
+                    "frame_delay": frame_delay.as_millis() as u64,
+                    "leds_number": leds_number,
+                    "frames_number": frames_number,
+                }))
+            })
+            .await?;
+        read_ok_json::<VerifyResponse>(response, "set movie config").await?;
+        Ok(())
+    }
+
+    /// Fails with [Error::MovieFormat] unless `movie` has the device's LED count and byte layout.
+    fn check_movie_matches_device(&self, movie: &Movie, led_profile: LedProfile) -> Result<()> {
+        if movie.frames.is_empty() {
+            return Err(Error::MovieFormat("The movie has no frames".to_string()));
         }
+        let number_of_led = self.device_info.number_of_led;
+        if let Some((index, frame)) = movie
+            .frames
+            .iter()
+            .enumerate()
+            .find(|(_, frame)| frame.len() != number_of_led)
+        {
+            return Err(Error::MovieFormat(format!(
+                "Frame {} has {} LEDs, the device has {}",
+                index,
+                frame.len(),
+                number_of_led
+            )));
+        }
+        if led_profile.bytes_per_led() != self.device_info.bytes_per_led {
+            return Err(Error::MovieFormat(format!(
+                "The {:?} profile uses {} bytes per LED, the device uses {}",
+                led_profile,
+                led_profile.bytes_per_led(),
+                self.device_info.bytes_per_led
+            )));
+        }
+        Ok(())
     }
 
     /// Turns on the device by setting it to the last known mode or a default mode.
-    pub async fn turn_on(&self) -> anyhow::Result<VerifyResponse> {
+    pub async fn turn_on(&self) -> Result<VerifyResponse> {
         // Fetch the current mode from the device
         let mode_response: DeviceMode = self.get_mode().await?;
         let current_mode = mode_response;
@@ -697,31 +978,28 @@ impl ControlInterface {
     }
 
     /// Turns off the device and remembers the last non-real-time mode.
-    pub async fn turn_off(&self) -> anyhow::Result<VerifyResponse> {
+    pub async fn turn_off(&self) -> Result<VerifyResponse> {
         // Set the device mode to "off"
         self.set_mode(DeviceMode::Off).await
     }
 
     /// Helper method to set the device mode.
-    pub async fn set_mode(&self, mode: DeviceMode) -> anyhow::Result<VerifyResponse> {
+    pub async fn set_mode(&self, mode: DeviceMode) -> Result<VerifyResponse> {
         let url = format!("http://{}/xled/v1/led/mode", self.host);
         let response = self
-            .client
-            .post(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .json(&json!({ "mode": mode.to_string() }))
-            .send()
-            .await
-            .context("Failed to set mode")?;
+            .send_authenticated(|client| {
+                client.post(&url).json(&json!({ "mode": mode.to_string() }))
+            })
+            .await?;
 
         if response.status() == StatusCode::OK {
-            let mode_response = response.json::<VerifyResponse>().await?;
+            let mode_response = read_checked_json::<VerifyResponse>(response).await?;
             Ok(mode_response)
         } else {
-            Err(anyhow::anyhow!(
-                "Failed to set mode with status: {}",
-                response.status()
-            ))
+            Err(Error::HttpStatus {
+                operation: "set mode",
+                status: response.status(),
+            })
         }
     }
 
@@ -729,29 +1007,29 @@ impl ControlInterface {
     ///
     /// # Arguments
     /// - `brightness`: The brightness value to set.
-    ///                 Range is 0..100.
-    pub async fn set_brightness(&self, brightness: i32) -> anyhow::Result<()> {
+    ///   Range is 0..100.
+    pub async fn set_brightness(&self, brightness: i32) -> Result<()> {
         let url = format!("http://{}/xled/v1/led/out/brightness", self.host);
         let response = self
-            .client
-            .post(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .json(&json!({ "mode": "enabled", "type": "A", "value": brightness }))
-            .send()
-            .await
-            .context("Failed to set brightness")?;
+            .send_authenticated(|client| {
+                client
+                    .post(&url)
+                    .json(&json!({ "mode": "enabled", "type": "A", "value": brightness }))
+            })
+            .await?;
 
         if response.status() == StatusCode::OK {
+            read_checked_json::<VerifyResponse>(response).await?;
             Ok(())
         } else {
-            Err(anyhow::anyhow!(
-                "Failed to set the brightness with status: {}",
-                response.status()
-            ))
+            Err(Error::HttpStatus {
+                operation: "set brightness",
+                status: response.status(),
+            })
         }
     }
 
-    async fn authenticate(client: &Client, host: &str, hw_address: &str) -> anyhow::Result<String> {
+    async fn authenticate(client: &HttpClient, host: &str, hw_address: &str) -> Result<AuthToken> {
         // Generate a random challenge
         let challenge = Auth::generate_challenge();
 
@@ -770,93 +1048,79 @@ impl ControlInterface {
         )
         .await?;
 
-        Ok(challenge_response.authentication_token)
+        let expires_in = challenge_response
+            .authentication_token_expires_in
+            .and_then(|seconds| u64::try_from(seconds).ok())
+            .map(Duration::from_secs);
+        Ok(AuthToken::new(
+            challenge_response.authentication_token,
+            expires_in,
+        ))
     }
 
-    pub async fn get_mode(&self) -> anyhow::Result<DeviceMode> {
+    pub async fn get_mode(&self) -> Result<DeviceMode> {
         let url = format!("http://{}/xled/v1/led/mode", self.host);
-        let response = self
-            .client
-            .get(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .send()
-            .await
-            .context("Failed to get mode")?;
+        let response = self.send_authenticated(|client| client.get(&url)).await?;
 
         match response.status() {
             StatusCode::OK => {
-                let mode_response = response.json::<ModeResponse>().await?;
-                println!("Mode response: {:#?}", mode_response);
-                println!("Mode: {}", mode_response.mode);
-                let mode = DeviceMode::from_str(&mode_response.mode)
-                    .map_err(|e| anyhow!("Failed to parse mode: {}", e))?;
+                let mode_response = read_checked_json::<ModeResponse>(response).await?;
+                debug!("Mode response: {:?}", mode_response);
+                let mode = DeviceMode::from_str(&mode_response.mode)?;
                 Ok(mode)
             }
-            _ => Err(anyhow::anyhow!(
-                "Failed to get mode with status: {}",
-                response.status()
-            )),
+            _ => Err(Error::HttpStatus {
+                operation: "get mode",
+                status: response.status(),
+            }),
         }
     }
 
-    pub async fn get_brightness(&self) -> anyhow::Result<BrightnessResponse> {
+    pub async fn get_brightness(&self) -> Result<BrightnessResponse> {
         let url = format!("http://{}/xled/v1/led/out/brightness", self.host);
-        let response = self
-            .client
-            .get(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .send()
-            .await
-            .context("Failed to get brightness")?;
+        let response = self.send_authenticated(|client| client.get(&url)).await?;
 
         match response.status() {
             StatusCode::OK => {
-                let mode_response = response.json::<BrightnessResponse>().await?;
-                println!("Brightness response: {:#?}", mode_response);
-                println!("Brightness: {}", mode_response.value);
+                let mode_response = read_checked_json::<BrightnessResponse>(response).await?;
+                debug!("Brightness response: {:?}", mode_response);
                 Ok(mode_response)
             }
-            _ => Err(anyhow::anyhow!(
-                "Failed to get brightness with status: {}",
-                response.status()
-            )),
+            _ => Err(Error::HttpStatus {
+                operation: "get brightness",
+                status: response.status(),
+            }),
         }
     }
 
-    pub async fn get_timer(&self) -> anyhow::Result<TimerResponse> {
+    pub async fn get_timer(&self) -> Result<TimerResponse> {
         let url = format!("http://{}/xled/v1/timer", self.host);
-        let response = self
-            .client
-            .get(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .send()
-            .await
-            .context("Failed to get timer")?;
+        let response = self.send_authenticated(|client| client.get(&url)).await?;
 
         match response.status() {
             StatusCode::OK => {
-                let timer_response = response.json::<TimerResponse>().await?;
+                let timer_response = read_checked_json::<TimerResponse>(response).await?;
                 Ok(timer_response)
             }
-            _ => Err(anyhow::anyhow!(
-                "Failed to get timer with status: {}",
-                response.status()
-            )),
+            _ => Err(Error::HttpStatus {
+                operation: "get timer",
+                status: response.status(),
+            }),
         }
     }
 
-    pub async fn set_formatted_timer(
-        &self,
-        time_on_str: &str,
-        time_off_str: &str,
-    ) -> anyhow::Result<()> {
+    pub async fn set_formatted_timer(&self, time_on_str: &str, time_off_str: &str) -> Result<()> {
         // Parse the time strings into NaiveTime objects
         let time_on = NaiveTime::parse_from_str(time_on_str, "%H:%M:%S")
             .or_else(|_| NaiveTime::parse_from_str(time_on_str, "%H:%M"))
-            .context("Failed to parse time_on string")?;
+            .map_err(|e| {
+                Error::InvalidArgument(format!("Invalid time_on {}: {}", time_on_str, e))
+            })?;
         let time_off = NaiveTime::parse_from_str(time_off_str, "%H:%M:%S")
             .or_else(|_| NaiveTime::parse_from_str(time_off_str, "%H:%M"))
-            .context("Failed to parse time_off string")?;
+            .map_err(|e| {
+                Error::InvalidArgument(format!("Invalid time_off {}: {}", time_off_str, e))
+            })?;
 
         // Convert NaiveTime objects to seconds after midnight
         let time_on_seconds = time_on.num_seconds_from_midnight() as i32;
@@ -867,142 +1131,293 @@ impl ControlInterface {
 
         // Send the request to set the timer
         let response = self
-            .client
-            .post(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .json(&json!({
-                "time_on": time_on_seconds,
-                "time_off": time_off_seconds,
-            }))
-            .send()
-            .await
-            .context("Failed to set timer")?;
+            .send_authenticated(|client| {
+                client.post(&url).json(&json!({
+                    "time_on": time_on_seconds,
+                    "time_off": time_off_seconds,
+                }))
+            })
+            .await?;
 
         // Check the response status
         if response.status() == StatusCode::OK {
+            read_checked_json::<VerifyResponse>(response).await?;
             Ok(())
         } else {
-            Err(anyhow::anyhow!(
-                "Failed to set timer with status: {}",
-                response.status()
-            ))
+            Err(Error::HttpStatus {
+                operation: "set timer",
+                status: response.status(),
+            })
         }
     }
 
-    pub async fn get_playlist(&self) -> anyhow::Result<PlaylistResponse> {
+    pub async fn get_playlist(&self) -> Result<PlaylistResponse> {
         let url = format!("http://{}/xled/v1/playlist", self.host);
-        let response = self
-            .client
-            .get(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .send()
-            .await?;
+        let response = self.send_authenticated(|client| client.get(&url)).await?;
 
         match response.status() {
             StatusCode::OK => {
-                let response = response.text().await?;
-                println!("Response: {}", response);
-                let playlist_response: PlaylistResponse = serde_json::from_str(&response)?;
-                // let playlist_response = response.json::<PlaylistResponse>().await?;
+                let playlist_response = read_checked_json::<PlaylistResponse>(response).await?;
+                debug!("Playlist response: {:?}", playlist_response);
                 Ok(playlist_response)
             }
-            _ => Err(response.error_for_status().unwrap_err().into()),
+            _ => Err(Error::HttpStatus {
+                operation: "get playlist",
+                status: response.status(),
+            }),
         }
     }
 
+    /**
+    Replaces the playlist with `items`, played in [DeviceMode::Playlist] in the given order.
+
+    The movies must already be stored on the device, e.g. with [Self::upload_named_movie].
+    Durations are whole seconds, fractions are dropped.
+
+    # Return
+    The installed playlist, as returned by [Self::get_playlist].
+     */
+    pub async fn set_playlist(
+        &self,
+        name: &str,
+        items: &[PlaylistItem],
+    ) -> Result<PlaylistResponse> {
+        // The playlist refers to movies by their unique ID rather than by the ID
+        let movies = self.get_movies().await?.movies;
+        let entries = items
+            .iter()
+            .map(|item| {
+                let movie = movies
+                    .iter()
+                    .find(|movie| movie.id == item.movie_id)
+                    .ok_or_else(|| {
+                        Error::InvalidArgument(format!("No movie with ID {}", item.movie_id))
+                    })?;
+                let duration = item.duration.as_secs();
+                if duration == 0 {
+                    return Err(Error::InvalidArgument(format!(
+                        "Playlist entry for movie {} must last at least one second",
+                        item.movie_id
+                    )));
+                }
+                Ok(json!({ "unique_id": movie.unique_id, "duration": duration }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let url = format!("http://{}/xled/v1/playlist", self.host);
+        let response = self
+            .send_authenticated(|client| {
+                client.post(&url).json(&json!({
+                    "unique_id": Uuid::new_v4().to_string(),
+                    "name": name,
+                    "entries": entries,
+                }))
+            })
+            .await?;
+        read_ok_json::<VerifyResponse>(response, "set playlist").await?;
+        self.get_playlist().await
+    }
+
+    /// Deletes the playlist. The movies in it stay on the device.
+    pub async fn delete_playlist(&self) -> Result<()> {
+        let url = format!("http://{}/xled/v1/playlist", self.host);
+        let response = self
+            .send_authenticated(|client| client.delete(&url))
+            .await?;
+        read_ok_json::<VerifyResponse>(response, "delete playlist").await?;
+        Ok(())
+    }
+
+    /// Fetches the playlist entry currently played in [DeviceMode::Playlist].
+    pub async fn get_current_playlist_entry(&self) -> Result<CurrentPlaylistEntryResponse> {
+        let url = format!("http://{}/xled/v1/playlist/current", self.host);
+        let response = self.send_authenticated(|client| client.get(&url)).await?;
+        read_ok_json(response, "get current playlist entry").await
+    }
+
+    /// Switches the playlist to the entry with `id`, see [PlaylistEntry::id].
+    pub async fn set_current_playlist_entry(&self, id: u32) -> Result<()> {
+        let url = format!("http://{}/xled/v1/playlist/current", self.host);
+        let response = self
+            .send_authenticated(|client| client.post(&url).json(&json!({ "id": id })))
+            .await?;
+        read_ok_json::<VerifyResponse>(response, "set current playlist entry").await?;
+        Ok(())
+    }
+
     /// Fetches the LED layout from the device.
-    pub async fn fetch_layout(&self) -> anyhow::Result<LayoutResponse> {
+    pub async fn fetch_layout(&self) -> Result<LayoutResponse> {
         let url = format!("http://{}/xled/v1/led/layout/full", self.host);
-        let response = self
-            .client
-            .get(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .send()
-            .await
-            .context("Failed to fetch layout")?;
+        let response = self.send_authenticated(|client| client.get(&url)).await?;
 
         if response.status() == StatusCode::OK {
-            let layout_response = response
-                .json::<LayoutResponse>()
-                .await
-                .context("Failed to deserialize layout response")?;
+            let layout_response = read_checked_json::<LayoutResponse>(response).await?;
             Ok(layout_response)
         } else {
-            Err(anyhow::anyhow!(
-                "Failed to fetch layout with status: {}",
-                response.status()
-            ))
+            Err(Error::HttpStatus {
+                operation: "fetch layout",
+                status: response.status(),
+            })
         }
     }
 
-    pub async fn get_device_capacity(&self) -> anyhow::Result<usize> {
-        let url = format!("http://{}/xled/v1/led/movies", self.host);
+    /**
+    Replaces the LED layout of the device.
+
+    The layout is checked with [Layout::validate] first, so a layout for a different number of
+    LEDs or with coordinates out of range fails with [Error::LayoutFormat] without being sent.
+    The device assigns a new [LayoutResponse::uuid].
+     */
+    pub async fn upload_layout(&self, layout: &Layout) -> Result<()> {
+        layout.validate(Some(self.device_info.number_of_led))?;
+        let url = format!("http://{}/xled/v1/led/layout/full", self.host);
         let response = self
-            .client
-            .get(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .send()
+            .send_authenticated(|client| {
+                client.post(&url).json(&json!({
+                    "source": layout.source,
+                    "synthesized": false,
+                    "coordinates": layout.coordinates,
+                }))
+            })
             .await?;
+        read_ok_json::<VerifyResponse>(response, "upload layout").await?;
+        Ok(())
+    }
 
-        match response.status() {
-            StatusCode::OK => {
-                let response_json = response.json::<serde_json::Value>().await?;
-                if let Some(available_frames) = response_json["available_frames"].as_u64() {
-                    Ok(available_frames as usize)
-                } else {
-                    Err(anyhow!("Failed to get available frames from response"))
-                }
-            }
-            _ => Err(anyhow!(
-                "Failed to get device capacity with status: {}",
-                response.status()
-            )),
+    /// The number of frames still available for movie uploads.
+    pub async fn get_device_capacity(&self) -> Result<usize> {
+        Ok(self.get_movies().await?.available_frames)
+    }
+
+    /// Lists the movies stored on the device.
+    pub async fn get_movies(&self) -> Result<MoviesResponse> {
+        let url = format!("http://{}/xled/v1/movies", self.host);
+        let response = self.send_authenticated(|client| client.get(&url)).await?;
+        read_ok_json(response, "get movies").await
+    }
+
+    /// Fetches the movie played in [DeviceMode::Movie].
+    pub async fn get_current_movie(&self) -> Result<CurrentMovieResponse> {
+        let url = format!("http://{}/xled/v1/movies/current", self.host);
+        let response = self.send_authenticated(|client| client.get(&url)).await?;
+        read_ok_json(response, "get current movie").await
+    }
+
+    /**
+    Selects the movie played in [DeviceMode::Movie].
+    The mode isn't changed, use [Self::set_mode] to start playing it.
+     */
+    pub async fn set_current_movie(&self, id: u32) -> Result<()> {
+        let url = format!("http://{}/xled/v1/movies/current", self.host);
+        let response = self
+            .send_authenticated(|client| client.post(&url).json(&json!({ "id": id })))
+            .await?;
+        read_ok_json::<VerifyResponse>(response, "set current movie").await?;
+        Ok(())
+    }
+
+    /**
+    Uploads `movie` as a new movie named `name`, keeping the movies already stored on the device.
+
+    The movie plays with `frame_delay` between frames, or at the movie's own fps if `None`.
+
+    # Return
+    The stored movie, with the ID to select it by or to add it to a playlist.
+     */
+    pub async fn upload_named_movie(
+        &self,
+        movie: &Movie,
+        name: &str,
+        led_profile: LedProfile,
+        frame_delay: Option<Duration>,
+    ) -> Result<MovieInfo> {
+        self.check_movie_matches_device(movie, led_profile)?;
+        let frames_number = movie.frames.len();
+        let leds_per_frame = movie.frames.first().map_or(0, Vec::len);
+        let available_frames = self.get_device_capacity().await?;
+        if frames_number > available_frames {
+            return Err(Error::Capacity {
+                required: frames_number,
+                available: available_frames,
+            });
         }
+        let fps = match frame_delay {
+            Some(frame_delay) if !frame_delay.is_zero() => 1.0 / frame_delay.as_secs_f64(),
+            _ => movie.fps,
+        };
+
+        // The device takes the metadata first, then the frames of that movie
+        let unique_id = Uuid::new_v4().to_string();
+        let url = format!("http://{}/xled/v1/movies/new", self.host);
+        let response = self
+            .send_authenticated(|client| {
+                client.post(&url).json(&json!({
+                    "name": name,
+                    "unique_id": unique_id,
+                    "descriptor_type": led_profile.movie_descriptor_type(),
+                    "leds_per_frame": leds_per_frame,
+                    "frames_number": frames_number,
+                    "fps": fps.round().max(1.0) as u32,
+                }))
+            })
+            .await?;
+        read_ok_json::<VerifyResponse>(response, "create movie").await?;
+
+        let movie_data = rgbw::pack_frames(&movie.frames, led_profile, self.white_extraction);
+        let url = format!("http://{}/xled/v1/movies/full", self.host);
+        let response = self
+            .send_authenticated(|client| {
+                client
+                    .post(&url)
+                    .header("Content-Type", "application/octet-stream")
+                    .body(movie_data.clone())
+            })
+            .await?;
+        read_ok_json::<VerifyResponse>(response, "upload movie").await?;
+
+        self.get_movies()
+            .await?
+            .movies
+            .into_iter()
+            .find(|stored| stored.unique_id == unique_id)
+            .ok_or_else(|| Error::MovieFormat(format!("Movie {} wasn't stored", name)))
+    }
+
+    /// Deletes one movie from the device.
+    pub async fn delete_movie(&self, id: u32) -> Result<()> {
+        let url = format!("http://{}/xled/v1/movies", self.host);
+        let response = self
+            .send_authenticated(|client| client.delete(&url).json(&json!({ "id": id })))
+            .await?;
+        read_ok_json::<VerifyResponse>(response, "delete movie").await?;
+        Ok(())
     }
 
     /// Clears all uploaded movies from the device.
-    pub async fn clear_movies(&self) -> anyhow::Result<()> {
-        let url = format!("http://{}/xled/v1/led/movies", self.host);
+    pub async fn clear_movies(&self) -> Result<()> {
+        let url = format!("http://{}/xled/v1/movies", self.host);
         let response = self
-            .client
-            .delete(&url)
-            .header("X-Auth-Token", &self.auth_token)
-            .send()
+            .send_authenticated(|client| client.delete(&url))
             .await?;
 
         match response.status() {
+            StatusCode::OK => {
+                read_checked_json::<VerifyResponse>(response).await?;
+                Ok(())
+            }
+            // Some firmware answers without a body
             StatusCode::NO_CONTENT => Ok(()),
-            _ => Err(anyhow!(
-                "Failed to clear movies with status: {}",
-                response.status()
-            )),
+            _ => Err(Error::HttpStatus {
+                operation: "clear movies",
+                status: response.status(),
+            }),
         }
     }
 
     /// Converts a vector of frames into a binary movie format.
-    /// This function handles both RGB and RGBW LED profiles.
+    /// This function handles both RGB and RGBW LED profiles, see [Movie::to_movie].
     pub fn to_movie(frames: Vec<Vec<(u8, u8, u8)>>, led_profile: LedProfile) -> Vec<u8> {
-        let mut movie_data = Vec::new();
-        for frame in frames {
-            for &(r, g, b) in &frame {
-                match led_profile {
-                    LedProfile::RGB => {
-                        movie_data.push(r);
-                        movie_data.push(g);
-                        movie_data.push(b);
-                    }
-                    LedProfile::RGBW => {
-                        // Calculate the white component as the minimum of r, g, b
-                        let w = r.min(g).min(b);
-                        movie_data.push(r - w);
-                        movie_data.push(g - w);
-                        movie_data.push(b - w);
-                        movie_data.push(w);
-                    }
-                }
-            }
-        }
-        movie_data
+        Movie::to_movie(frames, led_profile)
     }
 
     // ... other methods ...
@@ -1011,7 +1426,7 @@ impl ControlInterface {
 /// Define a struct to deserialize information about the power usage of the device.
 #[derive(Derivative)]
 #[derivative(PartialEq)]
-#[derive(Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 #[allow(non_snake_case)]
 pub struct DevicePower {
     /// Power usage in Milliampere.
@@ -1032,7 +1447,7 @@ impl DevicePower {
 // Define a struct to deserialize the device information response
 #[derive(Derivative)]
 #[derivative(PartialEq)]
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DeviceInfoResponse {
     pub product_name: String,
     pub hardware_version: String,
@@ -1048,7 +1463,10 @@ pub struct DeviceInfoResponse {
     // Ignore uptime for partial-equal, it changes over time, while the device stays the same.
     #[derivative(PartialEq = "ignore")]
     // Uptime is now an unsigned 64-bit integer
-    #[serde(deserialize_with = "deserialize_duration_millis")]
+    #[serde(
+        deserialize_with = "deserialize_duration_millis",
+        serialize_with = "serialize_duration_millis"
+    )]
     pub uptime: Duration,
 
     pub mac: String,
@@ -1089,7 +1507,7 @@ impl ResponseCodeTrait for DeviceInfoResponse {
     }
 }
 
-fn deserialize_duration_millis<'de, D>(deserializer: D) -> anyhow::Result<Duration, D::Error>
+fn deserialize_duration_millis<'de, D>(deserializer: D) -> Result<Duration, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -1100,7 +1518,14 @@ where
         .map_err(serde::de::Error::custom)
 }
 
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+fn serialize_duration_millis<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&duration.as_millis().to_string())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ValueEnum)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum LedProfile {
     RGB,
@@ -1108,16 +1533,66 @@ pub enum LedProfile {
     // Add other LED profiles as needed
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl LedProfile {
+    /// The number of bytes per LED in movies and real-time frames.
+    pub fn bytes_per_led(&self) -> usize {
+        match self {
+            LedProfile::RGB => 3,
+            LedProfile::RGBW => 4,
+        }
+    }
+
+    /// The `descriptor_type` of movies stored in this profile.
+    pub fn movie_descriptor_type(&self) -> &'static str {
+        match self {
+            LedProfile::RGB => "rgb_raw",
+            LedProfile::RGBW => "rgbw_raw",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PlaylistEntry {
+    /// The position in the playlist, used to switch to the entry.
     pub id: u32,
+    /// The unique ID of the movie played.
     pub unique_id: String,
     pub name: String,
+    /// Seconds the movie plays before the next entry.
     pub duration: u32,
+    /// The ID of the movie played.
     pub handle: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// An entry of a playlist to install with [ControlInterface::set_playlist].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaylistItem {
+    /// The ID of a movie stored on the device, see [MovieInfo::id].
+    pub movie_id: u32,
+    /// How long the movie plays before the next entry.
+    pub duration: Duration,
+}
+
+/// The playlist entry played in [DeviceMode::Playlist].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CurrentPlaylistEntryResponse {
+    pub id: u32,
+    #[serde(default)]
+    pub unique_id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub duration: u32,
+    pub code: u32,
+}
+
+impl ResponseCodeTrait for CurrentPlaylistEntryResponse {
+    fn response_code(&self) -> ResponseCode {
+        Self::map_response_code(self.code)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PlaylistResponse {
     pub entries: Vec<PlaylistEntry>,
     pub unique_id: String,
@@ -1131,6 +1606,72 @@ impl ResponseCodeTrait for PlaylistResponse {
     }
 }
 
+/// A movie stored on the device.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MovieInfo {
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub unique_id: String,
+    /// Either `rgb_raw` or `rgbw_raw`, see [LedProfile::movie_descriptor_type].
+    #[serde(default)]
+    pub descriptor_type: String,
+    pub leds_per_frame: usize,
+    pub frames_number: usize,
+    pub fps: f64,
+}
+
+/// The movies stored on the device, and the space left for more.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MoviesResponse {
+    pub movies: Vec<MovieInfo>,
+    /// Frames still available for uploads.
+    pub available_frames: usize,
+    /// Frames available with no movies stored.
+    pub max_capacity: usize,
+    pub code: u32,
+}
+
+impl ResponseCodeTrait for MoviesResponse {
+    fn response_code(&self) -> ResponseCode {
+        Self::map_response_code(self.code)
+    }
+}
+
+/// The movie played in [DeviceMode::Movie].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CurrentMovieResponse {
+    pub id: u32,
+    #[serde(default)]
+    pub unique_id: String,
+    #[serde(default)]
+    pub name: String,
+    pub code: u32,
+}
+
+impl ResponseCodeTrait for CurrentMovieResponse {
+    fn response_code(&self) -> ResponseCode {
+        Self::map_response_code(self.code)
+    }
+}
+
+/// The config of the movie uploaded with [ControlInterface::upload_movie].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MovieConfigResponse {
+    /// Milliseconds between frames.
+    pub frame_delay: u32,
+    pub leds_number: usize,
+    pub frames_number: usize,
+    pub code: u32,
+}
+
+impl ResponseCodeTrait for MovieConfigResponse {
+    fn response_code(&self) -> ResponseCode {
+        Self::map_response_code(self.code)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ModeResponse {
     pub mode: String,
@@ -1143,6 +1684,18 @@ impl ResponseCodeTrait for ModeResponse {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FirmwareVersionResponse {
+    pub version: String,
+    pub code: u32,
+}
+
+impl ResponseCodeTrait for FirmwareVersionResponse {
+    fn response_code(&self) -> ResponseCode {
+        Self::map_response_code(self.code)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TimerResponse {
     pub time_now: i32,
@@ -1157,7 +1710,7 @@ impl ResponseCodeTrait for TimerResponse {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone)]
 pub struct LedCoordinate {
     pub x: f64,
     pub y: f64,
@@ -1165,7 +1718,7 @@ pub struct LedCoordinate {
 }
 
 // Define a struct to deserialize the layout response
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct LayoutResponse {
     pub source: String,
     pub synthesized: bool,
@@ -1180,15 +1733,44 @@ impl ResponseCodeTrait for LayoutResponse {
     }
 }
 
+/// How the coordinates of a layout were obtained, which decides the ranges they must be in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LayoutSource {
+    /// LEDs in a row, with only their order known.
+    #[serde(rename = "linear")]
+    #[value(name = "linear")]
+    Linear,
+    /// A flat layout, `x` in -1..=1, `y` in 0..=1 and `z` always 0.
+    #[serde(rename = "2d")]
+    #[value(name = "2d")]
+    TwoD,
+    /// A spatial layout, `x` and `z` in -1..=1, `y` in 0..=1.
+    #[serde(rename = "3d")]
+    #[value(name = "3d")]
+    ThreeD,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum Axis {
     X,
     Y,
     Z,
 }
 
+impl LedCoordinate {
+    /// The coordinate along `axis`.
+    pub fn along(&self, axis: Axis) -> f64 {
+        match axis {
+            Axis::X => self.x,
+            Axis::Y => self.y,
+            Axis::Z => self.z,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Challenge {
-    challenge: String,
+    pub(crate) challenge: String,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -1243,7 +1825,7 @@ pub enum CliColors {
 }
 
 impl FromStr for CliColors {
-    type Err = anyhow::Error;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
@@ -1263,7 +1845,7 @@ impl FromStr for CliColors {
             "beige" => Ok(CliColors::Beige),
             "maroon" => Ok(CliColors::Maroon),
             "mint" => Ok(CliColors::Mint),
-            _ => Err(anyhow!("Invalid color")),
+            _ => Err(Error::InvalidArgument(format!("Invalid color: {}", s))),
         }
     }
 }
@@ -1370,82 +1952,72 @@ impl From<CliColors> for RGB {
 }
 
 async fn send_verify(
-    client: &Client,
+    client: &HttpClient,
     ip: &str,
     auth_token: &str,
     challenge_response: &str,
-) -> anyhow::Result<()> {
+) -> Result<()> {
     let verify_url = format!("http://{}/xled/v1/verify", ip);
 
-    let response = client
+    let request = client
         .post(&verify_url)
         .header("X-Auth-Token", auth_token)
-        .json(&json!({ "challenge-response": challenge_response }))
-        .send()
-        .await
-        .context("Failed to send verification")?;
+        .json(&json!({ "challenge-response": challenge_response }));
+    let response = client.send(request).await?;
 
     match response.status() {
         StatusCode::OK => {
-            let verify_response = response
-                .json::<VerifyResponse>()
-                .await
-                .context("Failed to deserialize verify response")?;
-            if verify_response.response_code().is_ok() {
-                //   println!("Verify response code is 1000");
+            let verify_response = read_json::<VerifyResponse>(response).await?;
+            let code = verify_response.response_code();
+            if code.is_ok() {
                 Ok(())
             } else {
-                Err(anyhow::anyhow!(
-                    "Verification failed with code: {}",
-                    verify_response.code
-                ))
+                Err(Error::Authentication(format!(
+                    "Verification failed with code {}: {}",
+                    code.code, code.message
+                )))
             }
         }
-        _ => {
-            let error_msg = format!("Verification failed with status: {}", response.status());
-            Err(anyhow::anyhow!(error_msg))
-        }
+        status => Err(Error::Authentication(format!(
+            "Verification failed with status: {}",
+            status
+        ))),
     }
 }
 
+/// The response code in the response JSON, returned additionally to the returned HTTP Status code.
 #[derive(Serialize, Deserialize, Debug)]
-struct LoginResponse {
-    authentication_token: String,
-    #[serde(rename = "challenge-response")]
-    challenge_response: String,
+pub struct VerifyResponse {
     code: u32,
 }
 
-impl ResponseCodeTrait for LoginResponse {
+impl ResponseCodeTrait for VerifyResponse {
     fn response_code(&self) -> ResponseCode {
         Self::map_response_code(self.code)
     }
 }
 
-/// The response code in the response JSON, returned additionally to the returned HTTP Status code.
 #[derive(Serialize, Deserialize, Debug)]
-pub struct VerifyResponse {
-    code: u32,
+pub(crate) struct ChallengeResponse {
+    #[serde(rename = "challenge-response")]
+    pub(crate) challenge_response: String,
+    pub(crate) authentication_token: String,
+    /// The token lifetime in seconds, usually 14400 (4 hours).
+    pub(crate) authentication_token_expires_in: Option<i32>,
+    pub(crate) code: u32,
 }
 
-impl ResponseCodeTrait for VerifyResponse {
+impl ResponseCodeTrait for ChallengeResponse {
     fn response_code(&self) -> ResponseCode {
         Self::map_response_code(self.code)
     }
 }
 
+/// The body of a mode change request, as parsed by the emulator.
+#[cfg(feature = "emulator")]
 #[derive(Serialize, Deserialize, Debug)]
-struct ChallengeResponse {
-    #[serde(rename = "challenge-response")]
-    challenge_response: String,
-    authentication_token: String,
-    /// Seems to be always 1440. Since a Day has 1440 Minutes, this may mean: "10 Days"?
-    authentication_token_expires_in: Option<i32>,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct Mode {
-    mode: String,
+pub(crate) struct Mode {
+    pub(crate) mode: String,
 }
 
 pub fn generate_color_wheel_gradient(num_leds: usize, offset: usize) -> Vec<(u8, u8, u8)> {
@@ -1528,36 +2100,100 @@ fn generate_color_gradient_along_axis(
         .collect()
 }
 
+/// The delay between frames playing at `fps`, failing for rates the device can't play.
+fn frame_delay_for_fps(fps: f64) -> Result<Duration> {
+    if !fps.is_finite() || fps <= 0.0 {
+        return Err(Error::InvalidArgument(format!(
+            "Invalid frame rate: {}",
+            fps
+        )));
+    }
+    // The device counts the delay in whole milliseconds
+    Ok(Duration::from_millis((1000.0 / fps).round().max(1.0) as u64))
+}
+
+/// Reads a JSON body, reporting malformed JSON as [Error::Json] rather than as a transport error.
+async fn read_json<T: DeserializeOwned>(response: Response) -> Result<T> {
+    let body = response.bytes().await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/**
+Reads a JSON body, failing with [Error::Device] unless its `code` is OK.
+
+The code is checked before decoding into `T`, since error responses usually lack the other fields.
+ */
+async fn read_checked_json<T>(response: Response) -> Result<T>
+where
+    T: DeserializeOwned + ResponseCodeTrait,
+{
+    let body = read_checked_value(response).await?;
+    Ok(serde_json::from_value(body)?)
+}
+
+/// Reads the JSON body of a `200 OK` response, failing with [Error::Device] unless its `code` is OK.
+async fn read_ok_json<T>(response: Response, operation: &'static str) -> Result<T>
+where
+    T: DeserializeOwned + ResponseCodeTrait,
+{
+    match response.status() {
+        StatusCode::OK => read_checked_json(response).await,
+        status => Err(Error::HttpStatus { operation, status }),
+    }
+}
+
+/// Like [read_checked_json], for responses without a dedicated type.
+async fn read_checked_value(response: Response) -> Result<serde_json::Value> {
+    let body = read_json::<serde_json::Value>(response).await?;
+    let code = body["code"]
+        .as_u64()
+        .and_then(|code| u32::try_from(code).ok())
+        .ok_or_else(|| missing_field("code"))?;
+    check_response_code(ResponseCode::from_code(code))?;
+    Ok(body)
+}
+
+fn check_response_code(code: ResponseCode) -> Result<()> {
+    if code.is_ok() {
+        Ok(())
+    } else {
+        Err(Error::Device(code))
+    }
+}
+
+/// The error for a JSON response without a required field.
+fn missing_field(field: &'static str) -> Error {
+    Error::Json(serde::de::Error::missing_field(field))
+}
+
 async fn send_challenge(
-    client: &Client,
+    client: &HttpClient,
     ip: &str,
     challenge: &[u8],
-) -> anyhow::Result<ChallengeResponse> {
+) -> Result<ChallengeResponse> {
     let login_url = format!("http://{}/xled/v1/login", ip);
     let challenge_b64 = STANDARD.encode(challenge);
 
-    let response = client
-        .post(&login_url)
-        .json(&Challenge {
-            challenge: challenge_b64,
-        })
-        .send()
-        .await
-        .context("Failed to send authentication challenge")?;
+    let request = client.post(&login_url).json(&Challenge {
+        challenge: challenge_b64,
+    });
+    let response = client.send(request).await?;
 
     if response.status() != 200 {
-        anyhow::bail!(
+        return Err(Error::Authentication(format!(
             "Authentication challenge failed with status: {}",
             response.status()
-        );
+        )));
     }
 
-    // println!("Challenge response: {:?}", response);
-    let content = response.text().await?;
-    // println!("Challenge response content: {:?}", content);
-    let challenge_response: ChallengeResponse =
-        serde_json::from_str(&content).context("Failed to deserialize challenge response")?;
-
+    let challenge_response = read_json::<ChallengeResponse>(response).await?;
+    let code = challenge_response.response_code();
+    if code.is_error() {
+        return Err(Error::Authentication(format!(
+            "Authentication challenge failed with code {}: {}",
+            code.code, code.message
+        )));
+    }
     Ok(challenge_response)
 }
 
@@ -1630,3 +2266,51 @@ impl From<BinaryStreamFormat> for AddressableLed {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_info(fw_family: &str, number_of_led: usize) -> DeviceInfoResponse {
+        let mut device_info = ControlInterface::new_mock_device_info_response(
+            "000000".to_string(),
+            "Test".to_string(),
+            "02:00:00:00:00:01".to_string(),
+            number_of_led,
+        );
+        device_info.fw_family = fw_family.to_string();
+        device_info
+    }
+
+    #[test]
+    fn test_detect_rt_protocol_from_firmware() {
+        let info = device_info("T", 250);
+        let detect = |firmware| HardwareVersion::detect(&info, Some(firmware));
+        assert_eq!(detect("2.3.8"), HardwareVersion::Version1);
+        assert_eq!(detect("2.4.14"), HardwareVersion::Version2);
+        assert_eq!(detect("2.4.21"), HardwareVersion::Version2);
+        assert_eq!(detect("2.4.22"), HardwareVersion::Version3);
+        assert_eq!(detect("2.8.18"), HardwareVersion::Version3);
+    }
+
+    #[test]
+    fn test_detect_rt_protocol_from_gestalt() {
+        let detect = |family, leds| HardwareVersion::detect(&device_info(family, leds), None);
+        assert_eq!(detect("D", 250), HardwareVersion::Version1);
+        assert_eq!(detect("F", 250), HardwareVersion::Version2);
+        assert_eq!(detect("G", 250), HardwareVersion::Version3);
+        // Version 1 can't address more than 255 LEDs.
+        assert_eq!(detect("D", 600), HardwareVersion::Version2);
+        assert_eq!(
+            HardwareVersion::detect(&device_info("T", 600), Some("2.3.8")),
+            HardwareVersion::Version2
+        );
+    }
+
+    #[test]
+    fn test_parse_firmware_version() {
+        assert_eq!(parse_firmware_version("2.8.18"), Some((2, 8, 18)));
+        assert_eq!(parse_firmware_version("1.99"), Some((1, 99, 0)));
+        assert_eq!(parse_firmware_version("beta"), None);
+    }
+}
@@ -0,0 +1,208 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, error};
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, sleep_until, Instant};
+
+use crate::control_interface::{ControlInterface, DeviceMode};
+use crate::error::Result;
+
+/// How often the last frame is repeated while no new frames are sent.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+The last frame sent, shared with the keepalive task.
+
+The lock is held while a frame is sent, so a keepalive can't repeat a frame after a newer one
+was sent, and the fragments of two frames never interleave.
+ */
+struct LastFrame {
+    frame: Vec<u8>,
+    sent_at: Instant,
+}
+
+/**
+A real-time connection to a device, obtained by [ControlInterface::realtime_session].
+
+The session owns one connected UDP socket, switches the device into [DeviceMode::RealTime]
+once, and paces [Self::send_frame] to the rate the device can display. While no frames are
+sent, the last frame is repeated every [KEEPALIVE_INTERVAL], so the device doesn't fall back
+out of real-time mode.
+
+The previous mode is restored by [Self::close], or in the background when the session is dropped.
+ */
+pub struct RealtimeSession {
+    control: ControlInterface,
+    socket: Arc<UdpSocket>,
+    previous_mode: DeviceMode,
+    frame_interval: Duration,
+    min_frame_interval: Duration,
+    next_frame_at: Instant,
+    last_frame: Arc<Mutex<Option<LastFrame>>>,
+    keepalive: JoinHandle<()>,
+    restore_on_drop: bool,
+}
+
+impl RealtimeSession {
+    pub(crate) async fn start(
+        control: &ControlInterface,
+        keepalive_interval: Duration,
+    ) -> Result<Self> {
+        let control = control.clone();
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect((control.rt_host(), control.rt_port)).await?;
+        let socket = Arc::new(socket);
+
+        let previous_mode = control.get_mode().await?;
+        if previous_mode != DeviceMode::RealTime {
+            control.set_mode(DeviceMode::RealTime).await?;
+        }
+
+        let min_frame_interval = Self::device_frame_interval(&control);
+        let last_frame = Arc::new(Mutex::new(None));
+        let keepalive = tokio::spawn(Self::keepalive(
+            control.clone(),
+            socket.clone(),
+            last_frame.clone(),
+            keepalive_interval,
+        ));
+
+        Ok(RealtimeSession {
+            control,
+            socket,
+            previous_mode,
+            frame_interval: min_frame_interval,
+            min_frame_interval,
+            next_frame_at: Instant::now(),
+            last_frame,
+            keepalive,
+            restore_on_drop: previous_mode != DeviceMode::RealTime,
+        })
+    }
+
+    /**
+    The shortest time between frames the device can display, from the lower of
+    its nominal `frame_rate` and its `measured_frame_rate`.
+     */
+    fn device_frame_interval(control: &ControlInterface) -> Duration {
+        let device_info = control.get_device_info();
+        let frame_rate = [device_info.frame_rate, device_info.measured_frame_rate]
+            .into_iter()
+            .filter(|rate| rate.is_finite() && *rate > 0.0)
+            .fold(f64::INFINITY, f64::min);
+        if frame_rate.is_finite() {
+            Duration::from_secs_f64(1.0 / frame_rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Lowers the frame rate below the device's rate. Rates above the device's rate are capped.
+    pub fn set_frame_rate(&mut self, frame_rate: f64) {
+        let requested = if frame_rate > 0.0 {
+            Duration::from_secs_f64(1.0 / frame_rate)
+        } else {
+            Duration::ZERO
+        };
+        self.frame_interval = requested.max(self.min_frame_interval);
+    }
+
+    /// The time between two frames sent by [Self::send_frame].
+    pub fn frame_interval(&self) -> Duration {
+        self.frame_interval
+    }
+
+    /// The mode the device was in before the session started.
+    pub fn previous_mode(&self) -> DeviceMode {
+        self.previous_mode
+    }
+
+    /**
+    Sends a frame, waiting first if the previous frame was sent less than
+    [Self::frame_interval] ago.
+
+    # Return
+    Returns either the written bytes or an error.
+     */
+    pub async fn send_frame(&mut self, frame: &[u8]) -> Result<usize> {
+        sleep_until(self.next_frame_at).await;
+        let mut last_frame = self.last_frame.lock().await;
+        let written = self
+            .control
+            .set_rt_frame_socket(&self.socket, frame)
+            .await?;
+        let now = Instant::now();
+        // Don't try to catch up on frames which were sent late, that would only cause bursts.
+        let scheduled = self.next_frame_at + self.frame_interval;
+        self.next_frame_at = if scheduled < now {
+            now + self.frame_interval
+        } else {
+            scheduled
+        };
+        *last_frame = Some(LastFrame {
+            frame: frame.to_vec(),
+            sent_at: now,
+        });
+        Ok(written)
+    }
+
+    /// Ends the session and restores the mode the device was in before.
+    pub async fn close(mut self) -> Result<()> {
+        self.keepalive.abort();
+        self.restore_on_drop = false;
+        if self.previous_mode != DeviceMode::RealTime {
+            self.control.set_mode(self.previous_mode).await?;
+        }
+        Ok(())
+    }
+
+    async fn keepalive(
+        control: ControlInterface,
+        socket: Arc<UdpSocket>,
+        last_frame: Arc<Mutex<Option<LastFrame>>>,
+        interval: Duration,
+    ) {
+        loop {
+            sleep(interval).await;
+            let mut last_frame = last_frame.lock().await;
+            let Some(last) = last_frame
+                .as_mut()
+                .filter(|last| last.sent_at.elapsed() >= interval)
+            else {
+                continue;
+            };
+            debug!("Repeating the last real-time frame to keep the session alive");
+            if let Err(e) = control.set_rt_frame_socket(&socket, &last.frame).await {
+                error!("Failed to send real-time keepalive frame: {}", e);
+            }
+            last.sent_at = Instant::now();
+        }
+    }
+}
+
+impl Drop for RealtimeSession {
+    fn drop(&mut self) {
+        self.keepalive.abort();
+        if !self.restore_on_drop {
+            return;
+        }
+        let control = self.control.clone();
+        let previous_mode = self.previous_mode;
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if let Err(e) = control.set_mode(previous_mode).await {
+                        error!("Failed to restore mode {}: {}", previous_mode, e);
+                    }
+                });
+            }
+            Err(_) => error!(
+                "Real-time session dropped outside of a runtime, mode {} not restored",
+                previous_mode
+            ),
+        }
+    }
+}
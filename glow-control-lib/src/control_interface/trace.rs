@@ -0,0 +1,154 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use derivative::Derivative;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use tokio::time::Instant;
+
+use crate::error::Result;
+
+/**
+One HTTP request to a device and the device's answer, passed to a [TraceHook].
+
+Authentication tokens are sent as headers, which aren't part of the exchange. The
+`authentication_token` and `challenge-response` fields of the login and verify bodies are
+replaced with `<redacted>`, so an exchange contains nothing that allows to authenticate again.
+ */
+#[derive(Debug, Clone)]
+pub struct HttpExchange {
+    pub method: Method,
+    pub url: String,
+    /// The request body, `None` for requests without one.
+    pub request_body: Option<Bytes>,
+    /// The response status, `None` if no response was received.
+    pub status: Option<StatusCode>,
+    /// The response body, `None` if no response was received.
+    pub response_body: Option<Bytes>,
+    /// The time from sending the request to receiving the whole response.
+    pub elapsed: Duration,
+}
+
+/// The value replacing secrets in the bodies of an [HttpExchange].
+const REDACTED: &str = "<redacted>";
+
+/// The JSON fields in request and response bodies which allow to authenticate.
+const SECRET_FIELDS: [&str; 2] = ["authentication_token", "challenge-response"];
+
+/// A callback receiving every HTTP exchange with a device, see [super::ControlInterface::with_trace_hook].
+pub type TraceHook = Arc<dyn Fn(&HttpExchange) + Send + Sync>;
+
+/// A [Client] which passes every exchange to an optional [TraceHook].
+#[derive(Derivative, Clone, Default)]
+#[derivative(Debug)]
+pub(crate) struct HttpClient {
+    client: Client,
+    #[derivative(Debug = "ignore")]
+    trace_hook: Option<TraceHook>,
+}
+
+impl HttpClient {
+    pub(crate) fn new(trace_hook: Option<TraceHook>) -> Self {
+        HttpClient {
+            client: Client::new(),
+            trace_hook,
+        }
+    }
+
+    pub(crate) fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub(crate) fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub(crate) fn delete(&self, url: &str) -> RequestBuilder {
+        self.client.delete(url)
+    }
+
+    /**
+    Sends a request built with [Self::get], [Self::post] or [Self::delete].
+
+    With a trace hook, the whole response body is read before returning, so the hook can see it.
+     */
+    pub(crate) async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let request = request.build()?;
+        let Some(trace_hook) = &self.trace_hook else {
+            return Ok(self.client.execute(request).await?);
+        };
+
+        let mut exchange = HttpExchange {
+            method: request.method().clone(),
+            url: request.url().to_string(),
+            request_body: request.body().and_then(|body| body.as_bytes()).map(redact),
+            status: None,
+            response_body: None,
+            elapsed: Duration::ZERO,
+        };
+        let started = Instant::now();
+        let response = match self.client.execute(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                exchange.elapsed = started.elapsed();
+                trace_hook(&exchange);
+                return Err(e.into());
+            }
+        };
+
+        let status = response.status();
+        let version = response.version();
+        let headers = response.headers().clone();
+        let body = response.bytes().await;
+        exchange.elapsed = started.elapsed();
+        exchange.status = Some(status);
+        exchange.response_body = body.as_deref().ok().map(redact);
+        trace_hook(&exchange);
+
+        // Hand the already read body on, as if it hadn't been read yet.
+        let mut rebuilt = http::Response::new(body?);
+        *rebuilt.status_mut() = status;
+        *rebuilt.version_mut() = version;
+        *rebuilt.headers_mut() = headers;
+        Ok(Response::from(rebuilt))
+    }
+}
+
+/// Copies a body, replacing the values of the [SECRET_FIELDS] of a JSON object with [REDACTED].
+fn redact(body: &[u8]) -> Bytes {
+    let Ok(serde_json::Value::Object(mut object)) = serde_json::from_slice(body) else {
+        return Bytes::copy_from_slice(body);
+    };
+    let mut redacted = false;
+    for field in SECRET_FIELDS {
+        if let Some(value) = object.get_mut(field) {
+            *value = REDACTED.into();
+            redacted = true;
+        }
+    }
+    if !redacted {
+        return Bytes::copy_from_slice(body);
+    }
+    serde_json::to_vec(&object)
+        .map(Bytes::from)
+        .unwrap_or_else(|_| Bytes::copy_from_slice(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let login = br#"{"authentication_token":"secret","challenge-response":"abc","code":1000}"#;
+        let redacted: serde_json::Value = serde_json::from_slice(&redact(login)).unwrap();
+        assert_eq!(redacted["authentication_token"], REDACTED);
+        assert_eq!(redacted["challenge-response"], REDACTED);
+        assert_eq!(redacted["code"], 1000);
+
+        // Bodies without secrets are passed on unchanged
+        let mode = br#"{"mode":"color"}"#;
+        assert_eq!(redact(mode), &mode[..]);
+        assert_eq!(redact(b"\x00\x01"), &b"\x00\x01"[..]);
+    }
+}
@@ -0,0 +1,71 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use log::{debug, error};
+use tokio::net::UdpSocket;
+
+use crate::util::discovery::PING_MESSAGE;
+
+/// Derives the device ID the firmware reports, e.g. `Twinkly_C54ABC`, from the MAC address.
+pub(crate) fn device_id(mac: &str) -> String {
+    let suffix: String = mac.split(':').skip(3).collect();
+    format!("Twinkly_{}", suffix.to_uppercase())
+}
+
+/// Encodes a discovery reply: the IP address in reversed byte order, `OK`, the device ID and a zero byte.
+pub(crate) fn encode_reply(ip: Ipv4Addr, device_id: &str) -> Vec<u8> {
+    let mut reply: Vec<u8> = ip.octets().iter().rev().copied().collect();
+    reply.extend_from_slice(b"OK");
+    reply.extend_from_slice(device_id.as_bytes());
+    reply.push(0);
+    reply
+}
+
+/// Finds the local address used to reach `peer`, which is the address the peer can reach us on.
+async fn local_ip_towards(peer: SocketAddr) -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.ok()?;
+    socket.connect(peer).await.ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(_) => None,
+    }
+}
+
+pub(crate) async fn serve(socket: UdpSocket, device_id: String, advertised_ip: Option<Ipv4Addr>) {
+    let mut buffer = [0u8; 64];
+    loop {
+        let (number_of_bytes, peer) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                error!("Emulator discovery responder failed: {}", e);
+                return;
+            }
+        };
+        if &buffer[..number_of_bytes] != PING_MESSAGE {
+            continue;
+        }
+        let Some(ip) = advertised_ip.or(local_ip_towards(peer).await) else {
+            continue;
+        };
+        debug!("Answering discovery ping from {} as {}", peer, ip);
+        if let Err(e) = socket.send_to(&encode_reply(ip, &device_id), peer).await {
+            error!("Failed to answer discovery ping from {}: {}", peer, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::discovery::Discovery;
+
+    #[test]
+    fn test_reply_round_trip() {
+        let ip = Ipv4Addr::new(10, 10, 0, 42);
+        let reply = encode_reply(ip, &device_id("11:38:aa:c4:ab:55"));
+        let decoded = Discovery::decode_discovery_response(&reply).unwrap();
+        assert_eq!(
+            decoded,
+            crate::util::discovery::DiscoveryResponse::new(ip, "Twinkly_C4AB55".to_string())
+        );
+    }
+}
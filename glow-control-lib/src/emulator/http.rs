@@ -0,0 +1,658 @@
+use std::sync::{Arc, Mutex};
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{Local, Timelike};
+use log::{debug, error};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::control_interface::{
+    Challenge, ChallengeResponse, CurrentMovieResponse, CurrentPlaylistEntryResponse, DeviceMode,
+    LedCoordinate, Mode, MovieInfo, MoviesResponse, PlaylistEntry,
+};
+use crate::emulator::{EmulatedMovie, EmulatorState, IssuedToken};
+use crate::util::auth::Auth;
+use crate::util::traits;
+use crate::util::traits::ResponseCode;
+
+type SharedState = Arc<Mutex<EmulatorState>>;
+
+type HandlerResult = Result<Response, Rejection>;
+
+/// A rejected request, answered the way the firmware does.
+enum Rejection {
+    /// HTTP 401, for missing or invalid tokens.
+    Unauthorized,
+    /// HTTP 400 with an error code, for input that can't be parsed.
+    BadRequest(ResponseCode),
+    /// HTTP 200 with an error code, for well-formed but invalid requests.
+    Code(ResponseCode),
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        match self {
+            Rejection::Unauthorized => (StatusCode::UNAUTHORIZED, "Invalid Token").into_response(),
+            Rejection::BadRequest(code) => {
+                (StatusCode::BAD_REQUEST, Json(json!({ "code": code.code }))).into_response()
+            }
+            Rejection::Code(code) => Json(json!({ "code": code.code })).into_response(),
+        }
+    }
+}
+
+pub(crate) async fn serve(listener: TcpListener, state: SharedState) {
+    let router = Router::new()
+        .route("/xled/v1/login", post(login))
+        .route("/xled/v1/verify", post(verify))
+        .route("/xled/v1/gestalt", get(gestalt))
+        .route("/xled/v1/fw/version", get(firmware_version))
+        .route("/xled/v1/led/mode", get(get_mode).post(set_mode))
+        .route(
+            "/xled/v1/led/out/brightness",
+            get(get_brightness).post(set_brightness),
+        )
+        .route("/xled/v1/timer", get(get_timer).post(set_timer))
+        .route(
+            "/xled/v1/playlist",
+            get(get_playlist).post(set_playlist).delete(delete_playlist),
+        )
+        .route(
+            "/xled/v1/playlist/current",
+            get(get_current_playlist_entry).post(set_current_playlist_entry),
+        )
+        .route(
+            "/xled/v1/led/layout/full",
+            get(get_layout).post(upload_layout),
+        )
+        .route("/xled/v1/movies", get(get_movies).delete(delete_movies))
+        .route(
+            "/xled/v1/movies/current",
+            get(get_current_movie).post(set_current_movie),
+        )
+        .route("/xled/v1/movies/new", post(new_movie))
+        .route("/xled/v1/movies/full", post(upload_movie_frames))
+        .route("/xled/v1/led/movie/full", post(upload_movie))
+        .route(
+            "/xled/v1/led/movie/config",
+            get(get_movie_config).post(set_movie_config),
+        )
+        .with_state(state);
+
+    if let Err(e) = axum::serve(listener, router).await {
+        error!("Emulator HTTP server failed: {}", e);
+    }
+}
+
+fn lock(state: &SharedState) -> std::sync::MutexGuard<'_, EmulatorState> {
+    let mut state = state.lock().expect("emulator state poisoned");
+    state.expire_realtime();
+    state
+}
+
+/// Rejects requests without a valid `X-Auth-Token`, the way the firmware does.
+fn authorize(state: &EmulatorState, headers: &HeaderMap) -> Result<(), Rejection> {
+    let token = headers
+        .get("X-Auth-Token")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if state.is_token_valid(token) {
+        Ok(())
+    } else {
+        debug!("Rejecting request with token {:?}", token);
+        Err(Rejection::Unauthorized)
+    }
+}
+
+/// Parses a JSON body, answering malformed input with the firmware's error code.
+fn parse_body<T: DeserializeOwned>(body: &Bytes) -> Result<T, Rejection> {
+    serde_json::from_slice(body)
+        .map_err(|_| Rejection::BadRequest(traits::ERROR_MALFORMED_JSON_INPUT))
+}
+
+fn ok() -> HandlerResult {
+    Ok(Json(json!({ "code": traits::OK.code })).into_response())
+}
+
+async fn login(State(state): State<SharedState>, body: Bytes) -> HandlerResult {
+    let challenge: Challenge = parse_body(&body)?;
+    let Ok(challenge) = STANDARD.decode(&challenge.challenge) else {
+        return Err(Rejection::BadRequest(traits::ERROR_INVALID_ARGUMENT));
+    };
+
+    let mut state = lock(&state);
+    let mut token = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut token);
+    let token = STANDARD.encode(token);
+    let challenge_response =
+        Auth::make_challenge_response(&challenge, &state.device_info.mac).unwrap_or_default();
+    let lifetime = state.token_lifetime;
+    state.tokens.insert(
+        token.clone(),
+        IssuedToken {
+            challenge,
+            verified: false,
+            expires_at: Instant::now() + lifetime,
+        },
+    );
+    state.logins += 1;
+
+    Ok(Json(ChallengeResponse {
+        challenge_response,
+        authentication_token: token,
+        authentication_token_expires_in: Some(lifetime.as_secs() as i32),
+        code: traits::OK.code,
+    })
+    .into_response())
+}
+
+async fn verify(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let body: Value = parse_body(&body)?;
+    let token = headers
+        .get("X-Auth-Token")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let mut state = lock(&state);
+    let mac = state.device_info.mac.clone();
+    let check_challenge_response = state.check_challenge_response;
+    let Some(issued) = state.tokens.get_mut(&token) else {
+        return Err(Rejection::Unauthorized);
+    };
+    let expected = Auth::make_challenge_response(&issued.challenge, &mac).unwrap_or_default();
+    if check_challenge_response && body["challenge-response"].as_str() != Some(expected.as_str()) {
+        return Err(Rejection::Unauthorized);
+    }
+    issued.verified = true;
+    ok()
+}
+
+async fn gestalt(State(state): State<SharedState>) -> HandlerResult {
+    let mut state = lock(&state);
+    state.device_info.uptime = state.started_at.elapsed();
+    Ok(Json(&state.device_info).into_response())
+}
+
+async fn firmware_version(State(state): State<SharedState>) -> HandlerResult {
+    let state = lock(&state);
+    Ok(Json(json!({ "version": state.firmware_version, "code": traits::OK.code })).into_response())
+}
+
+async fn get_mode(State(state): State<SharedState>, headers: HeaderMap) -> HandlerResult {
+    let state = lock(&state);
+    authorize(&state, &headers)?;
+    Ok(Json(json!({ "mode": state.mode.to_string(), "code": traits::OK.code })).into_response())
+}
+
+async fn set_mode(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    let mode: Mode = parse_body(&body)?;
+    match mode.mode.parse::<DeviceMode>() {
+        Ok(mode) => {
+            state.set_mode(mode);
+            ok()
+        }
+        Err(_) => Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT)),
+    }
+}
+
+async fn get_brightness(State(state): State<SharedState>, headers: HeaderMap) -> HandlerResult {
+    let state = lock(&state);
+    authorize(&state, &headers)?;
+    Ok(Json(json!({
+        "mode": if state.brightness_enabled { "enabled" } else { "disabled" },
+        "value": state.brightness,
+        "code": traits::OK.code,
+    }))
+    .into_response())
+}
+
+async fn set_brightness(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    let body: Value = parse_body(&body)?;
+    let Some(value) = body["value"]
+        .as_i64()
+        .filter(|value| (0..=100).contains(value))
+    else {
+        return Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT));
+    };
+    state.brightness = value as i32;
+    state.brightness_enabled = body["mode"].as_str() != Some("disabled");
+    ok()
+}
+
+async fn get_timer(State(state): State<SharedState>, headers: HeaderMap) -> HandlerResult {
+    let state = lock(&state);
+    authorize(&state, &headers)?;
+    Ok(Json(json!({
+        "time_now": Local::now().num_seconds_from_midnight(),
+        "time_on": state.time_on,
+        "time_off": state.time_off,
+        "code": traits::OK.code,
+    }))
+    .into_response())
+}
+
+async fn set_timer(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    let body: Value = parse_body(&body)?;
+    match (body["time_on"].as_i64(), body["time_off"].as_i64()) {
+        (Some(time_on), Some(time_off)) => {
+            state.time_on = time_on as i32;
+            state.time_off = time_off as i32;
+            ok()
+        }
+        _ => Err(Rejection::Code(traits::ERROR_VALUE_WRONG_MISSING_KEY)),
+    }
+}
+
+async fn get_playlist(State(state): State<SharedState>, headers: HeaderMap) -> HandlerResult {
+    let state = lock(&state);
+    authorize(&state, &headers)?;
+    Ok(Json(&state.playlist).into_response())
+}
+
+/// The body of a new playlist, referring to movies by their unique ID.
+#[derive(Deserialize)]
+struct NewPlaylist {
+    #[serde(default)]
+    unique_id: Option<String>,
+    #[serde(default)]
+    name: String,
+    entries: Vec<NewPlaylistEntry>,
+}
+
+#[derive(Deserialize)]
+struct NewPlaylistEntry {
+    unique_id: String,
+    duration: u32,
+}
+
+async fn set_playlist(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    let playlist: NewPlaylist = parse_body(&body)?;
+    let mut entries = Vec::with_capacity(playlist.entries.len());
+    for (id, entry) in playlist.entries.into_iter().enumerate() {
+        let Some(movie) = state
+            .movies
+            .iter()
+            .find(|movie| movie.info.unique_id == entry.unique_id)
+        else {
+            return Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT));
+        };
+        if entry.duration == 0 {
+            return Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT));
+        }
+        entries.push(PlaylistEntry {
+            id: id as u32,
+            unique_id: entry.unique_id,
+            name: movie.info.name.clone(),
+            duration: entry.duration,
+            handle: movie.info.id,
+        });
+    }
+    state.current_playlist_entry = if entries.is_empty() { None } else { Some(0) };
+    state.playlist.entries = entries;
+    state.playlist.name = playlist.name;
+    state.playlist.unique_id = playlist
+        .unique_id
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    ok()
+}
+
+async fn delete_playlist(State(state): State<SharedState>, headers: HeaderMap) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    state.playlist.entries.clear();
+    state.playlist.name.clear();
+    state.current_playlist_entry = None;
+    ok()
+}
+
+async fn get_current_playlist_entry(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> HandlerResult {
+    let state = lock(&state);
+    authorize(&state, &headers)?;
+    let Some(entry) = state
+        .current_playlist_entry
+        .and_then(|id| state.playlist.entries.iter().find(|entry| entry.id == id))
+    else {
+        return Err(Rejection::Code(traits::ERROR));
+    };
+    Ok(Json(CurrentPlaylistEntryResponse {
+        id: entry.id,
+        unique_id: entry.unique_id.clone(),
+        name: entry.name.clone(),
+        duration: entry.duration,
+        code: traits::OK.code,
+    })
+    .into_response())
+}
+
+async fn set_current_playlist_entry(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    let body: Value = parse_body(&body)?;
+    let Some(id) = body["id"].as_u64() else {
+        return Err(Rejection::Code(traits::ERROR_VALUE_WRONG_MISSING_KEY));
+    };
+    match state
+        .playlist
+        .entries
+        .iter()
+        .find(|entry| entry.id as u64 == id)
+    {
+        Some(entry) => {
+            state.current_playlist_entry = Some(entry.id);
+            ok()
+        }
+        None => Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT)),
+    }
+}
+
+async fn get_layout(State(state): State<SharedState>, headers: HeaderMap) -> HandlerResult {
+    let state = lock(&state);
+    authorize(&state, &headers)?;
+    Ok(Json(&state.layout).into_response())
+}
+
+/// The body of a layout upload.
+#[derive(Deserialize)]
+struct NewLayout {
+    source: String,
+    coordinates: Vec<LedCoordinate>,
+}
+
+async fn upload_layout(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    let layout: NewLayout = parse_body(&body)?;
+    if layout.coordinates.len() != state.device_info.number_of_led
+        || !["linear", "2d", "3d"].contains(&layout.source.as_str())
+    {
+        return Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT));
+    }
+    let parsed_coordinates = layout.coordinates.len();
+    state.layout.source = layout.source;
+    state.layout.synthesized = false;
+    state.layout.uuid = Uuid::new_v4().to_string();
+    state.layout.coordinates = layout.coordinates;
+    Ok(Json(json!({
+        "parsed_coordinates": parsed_coordinates,
+        "code": traits::OK.code,
+    }))
+    .into_response())
+}
+
+async fn get_movies(State(state): State<SharedState>, headers: HeaderMap) -> HandlerResult {
+    let state = lock(&state);
+    authorize(&state, &headers)?;
+    Ok(Json(MoviesResponse {
+        movies: state
+            .movies
+            .iter()
+            .map(|movie| movie.info.clone())
+            .collect(),
+        available_frames: state.available_frames(),
+        max_capacity: state.device_info.movie_capacity,
+        code: traits::OK.code,
+    })
+    .into_response())
+}
+
+/// Deletes the movie with the `id` given in the body, or all movies without a body.
+async fn delete_movies(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    if body.is_empty() {
+        state.movies.clear();
+        state.current_movie = None;
+        return ok();
+    }
+    let body: Value = parse_body(&body)?;
+    let Some(id) = body["id"].as_u64() else {
+        return Err(Rejection::Code(traits::ERROR_VALUE_WRONG_MISSING_KEY));
+    };
+    let Some(index) = state
+        .movies
+        .iter()
+        .position(|movie| movie.info.id as u64 == id)
+    else {
+        return Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT));
+    };
+    state.movies.remove(index);
+    if state
+        .current_movie
+        .is_some_and(|current| current as u64 == id)
+    {
+        state.current_movie = None;
+    }
+    ok()
+}
+
+async fn get_current_movie(State(state): State<SharedState>, headers: HeaderMap) -> HandlerResult {
+    let state = lock(&state);
+    authorize(&state, &headers)?;
+    let Some(movie) = state
+        .current_movie
+        .and_then(|id| state.movies.iter().find(|movie| movie.info.id == id))
+    else {
+        return Err(Rejection::Code(traits::ERROR));
+    };
+    Ok(Json(CurrentMovieResponse {
+        id: movie.info.id,
+        unique_id: movie.info.unique_id.clone(),
+        name: movie.info.name.clone(),
+        code: traits::OK.code,
+    })
+    .into_response())
+}
+
+async fn set_current_movie(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    let body: Value = parse_body(&body)?;
+    let Some(id) = body["id"].as_u64() else {
+        return Err(Rejection::Code(traits::ERROR_VALUE_WRONG_MISSING_KEY));
+    };
+    match state.movies.iter().find(|movie| movie.info.id as u64 == id) {
+        Some(movie) => {
+            state.current_movie = Some(movie.info.id);
+            ok()
+        }
+        None => Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT)),
+    }
+}
+
+/// The body of `movies/new`, announcing the movie uploaded next.
+#[derive(Deserialize)]
+struct NewMovie {
+    name: String,
+    unique_id: String,
+    descriptor_type: String,
+    leds_per_frame: usize,
+    frames_number: usize,
+    fps: f64,
+}
+
+async fn new_movie(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    let movie: NewMovie = parse_body(&body)?;
+    if !matches!(movie.descriptor_type.as_str(), "rgb_raw" | "rgbw_raw") || movie.fps <= 0.0 {
+        return Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT));
+    }
+    if movie.frames_number > state.available_frames() {
+        return Err(Rejection::Code(traits::ERROR));
+    }
+    let id = state.next_movie_id;
+    state.next_movie_id += 1;
+    state.pending_movie = Some(MovieInfo {
+        id,
+        name: movie.name,
+        unique_id: movie.unique_id,
+        descriptor_type: movie.descriptor_type,
+        leds_per_frame: movie.leds_per_frame,
+        frames_number: movie.frames_number,
+        fps: movie.fps,
+    });
+    ok()
+}
+
+/// Stores the frames of the movie announced by `movies/new`.
+async fn upload_movie_frames(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    let Some(info) = state.pending_movie.take() else {
+        return Err(Rejection::Code(traits::ERROR));
+    };
+    let bytes_per_led = if info.descriptor_type == "rgbw_raw" {
+        4
+    } else {
+        3
+    };
+    if body.len() != info.leds_per_frame * bytes_per_led * info.frames_number {
+        return Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT));
+    }
+    let frames_number = info.frames_number;
+    state.movies.push(EmulatedMovie {
+        info,
+        data: body.to_vec(),
+    });
+    Ok(Json(json!({ "frames_number": frames_number, "code": traits::OK.code })).into_response())
+}
+
+async fn upload_movie(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    // Like the device, split the upload into frames by the movie config sent before
+    let frames_number = state.movie_config.frames_number;
+    let frame_len = state.movie_config.leds_number * state.device_info.bytes_per_led;
+    if frames_number == 0 || body.len() != frame_len * frames_number {
+        return Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT));
+    }
+    if frames_number > state.available_frames() {
+        return Err(Rejection::Code(traits::ERROR));
+    }
+    let id = state.next_movie_id;
+    state.next_movie_id += 1;
+    let info = MovieInfo {
+        id,
+        name: String::new(),
+        unique_id: Uuid::new_v4().to_string(),
+        descriptor_type: state
+            .device_info
+            .led_profile
+            .movie_descriptor_type()
+            .to_string(),
+        leds_per_frame: state.device_info.number_of_led,
+        frames_number,
+        fps: 0.0,
+    };
+    state.movies.push(EmulatedMovie {
+        info,
+        data: body.to_vec(),
+    });
+    Ok(
+        Json(json!({ "id": id, "frames_number": frames_number, "code": traits::OK.code }))
+            .into_response(),
+    )
+}
+
+async fn get_movie_config(State(state): State<SharedState>, headers: HeaderMap) -> HandlerResult {
+    let state = lock(&state);
+    authorize(&state, &headers)?;
+    Ok(Json(&state.movie_config).into_response())
+}
+
+/// The body of `led/movie/config`.
+#[derive(Deserialize)]
+struct MovieConfig {
+    frame_delay: u32,
+    leds_number: usize,
+    frames_number: usize,
+}
+
+async fn set_movie_config(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult {
+    let mut state = lock(&state);
+    authorize(&state, &headers)?;
+    let config: MovieConfig = parse_body(&body)?;
+    if config.frame_delay == 0 || config.leds_number != state.device_info.number_of_led {
+        return Err(Rejection::Code(traits::ERROR_INVALID_ARGUMENT));
+    }
+    state.movie_config.frame_delay = config.frame_delay;
+    state.movie_config.leds_number = config.leds_number;
+    state.movie_config.frames_number = config.frames_number;
+    ok()
+}
@@ -0,0 +1,411 @@
+//! A local emulation of a Twinkly device, for testing without hardware.
+//!
+//! The [DeviceEmulator] serves the `/xled/v1/*` HTTP endpoints used by
+//! [ControlInterface], listens for real-time frames over UDP and answers
+//! discovery pings, all backed by one shared [EmulatorState].
+//!
+//! ```no_run
+//! use glow_control_lib::control_interface::DeviceMode;
+//! use glow_control_lib::emulator::{DeviceEmulator, EmulatorConfig};
+//!
+//! #[tokio::main]
+//! async fn main() -> glow_control_lib::Result<()> {
+//!     let emulator = DeviceEmulator::start(EmulatorConfig::default()).await?;
+//!     let control = emulator.control_interface().await?;
+//!     control.set_mode(DeviceMode::Color).await?;
+//!     assert_eq!(emulator.state().mode, DeviceMode::Color);
+//!     Ok(())
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use log::info;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::control_interface::{
+    ControlInterface, DeviceInfoResponse, DeviceMode, LayoutResponse, LedCoordinate,
+    MovieConfigResponse, MovieInfo, PlaylistResponse, RT_PORT,
+};
+use crate::error::Result;
+use crate::util::traits;
+
+mod discovery;
+mod http;
+pub mod realtime;
+
+pub use crate::util::discovery::DISCOVERY_PORT;
+pub use realtime::RealtimePacket;
+
+/// Configuration of a [DeviceEmulator].
+#[derive(Debug, Clone)]
+pub struct EmulatorConfig {
+    /// Address of the HTTP API. Port `0` picks a free port.
+    pub http_addr: SocketAddr,
+    /// Address of the real-time UDP listener. Port `0` picks a free port.
+    pub rt_addr: SocketAddr,
+    /// Address of the discovery responder, or `None` to not answer discovery pings.
+    pub discovery_addr: Option<SocketAddr>,
+    /// The IP address reported in discovery replies.
+    /// If `None`, the address of the interface the ping arrived on is used.
+    pub advertised_ip: Option<Ipv4Addr>,
+    /// The device served by the gestalt endpoint.
+    pub device_info: DeviceInfoResponse,
+    /// The firmware version reported by the device.
+    pub firmware_version: String,
+    /// The LED layout, or `None` to synthesize a cone-shaped spiral.
+    pub layout: Option<Vec<LedCoordinate>>,
+    /// How long issued authentication tokens stay valid.
+    pub token_lifetime: Duration,
+    /// How long the device stays in real-time mode without receiving frames.
+    pub rt_timeout: Duration,
+    /**
+    Whether the challenge response is checked against the MAC address on `login/verify`.
+    Turn it off to emulate firmware which accepts any response, and so any MAC address.
+     */
+    pub check_challenge_response: bool,
+}
+
+impl EmulatorConfig {
+    /**
+    Creates a configuration listening on ephemeral loopback ports, without a discovery responder.
+    This is what integration tests usually want.
+     */
+    pub fn new(device_info: DeviceInfoResponse) -> Self {
+        let loopback = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+        EmulatorConfig {
+            http_addr: loopback,
+            rt_addr: loopback,
+            discovery_addr: None,
+            advertised_ip: None,
+            device_info,
+            firmware_version: "2.8.18".to_string(),
+            layout: None,
+            token_lifetime: Duration::from_secs(14_400),
+            rt_timeout: Duration::from_secs(60),
+            check_challenge_response: true,
+        }
+    }
+
+    /**
+    Creates a configuration listening on the standard Twinkly ports of `ip`,
+    as used by the `glow-control emulate` command.
+     */
+    pub fn on_standard_ports(ip: IpAddr, device_info: DeviceInfoResponse) -> Self {
+        EmulatorConfig {
+            http_addr: SocketAddr::new(ip, 80),
+            rt_addr: SocketAddr::new(ip, RT_PORT),
+            discovery_addr: Some(SocketAddr::new(ip, DISCOVERY_PORT)),
+            ..EmulatorConfig::new(device_info)
+        }
+    }
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        EmulatorConfig::new(ControlInterface::new_mock_device_info_response(
+            "000000".to_string(),
+            "Emulated Twinkly".to_string(),
+            "02:00:00:00:00:01".to_string(),
+            250,
+        ))
+    }
+}
+
+/// A movie stored on the emulated device.
+#[derive(Debug, Clone)]
+pub struct EmulatedMovie {
+    pub info: MovieInfo,
+    pub data: Vec<u8>,
+}
+
+/// An authentication token issued by the emulated device.
+#[derive(Debug, Clone)]
+pub(crate) struct IssuedToken {
+    pub(crate) challenge: Vec<u8>,
+    pub(crate) verified: bool,
+    pub(crate) expires_at: Instant,
+}
+
+/// The observable state of an emulated device.
+#[derive(Debug)]
+pub struct EmulatorState {
+    pub device_info: DeviceInfoResponse,
+    pub firmware_version: String,
+    pub layout: LayoutResponse,
+    pub mode: DeviceMode,
+    /// Brightness in the range 0..100.
+    pub brightness: i32,
+    pub brightness_enabled: bool,
+    /// Seconds after midnight, or `-1` if unset.
+    pub time_on: i32,
+    /// Seconds after midnight, or `-1` if unset.
+    pub time_off: i32,
+    pub playlist: PlaylistResponse,
+    /// The ID of the playlist entry played in playlist mode.
+    pub current_playlist_entry: Option<u32>,
+    pub movies: Vec<EmulatedMovie>,
+    /// The ID of the movie played in movie mode.
+    pub current_movie: Option<u32>,
+    /// The config set for the movie uploaded to `led/movie/full`.
+    pub movie_config: MovieConfigResponse,
+    /// The last complete real-time frame shown, in the device's byte layout.
+    pub rt_frame: Vec<u8>,
+    /// All real-time packets received with a valid token, in arrival order.
+    pub rt_packets: Vec<RealtimePacket>,
+    /// Number of logins answered by the device.
+    pub logins: usize,
+    pub(crate) tokens: HashMap<String, IssuedToken>,
+    pub(crate) mode_before_rt: DeviceMode,
+    pub(crate) last_rt_frame_at: Option<Instant>,
+    pub(crate) token_lifetime: Duration,
+    pub(crate) rt_timeout: Duration,
+    pub(crate) check_challenge_response: bool,
+    pub(crate) started_at: Instant,
+    pub(crate) next_movie_id: u32,
+    /// Metadata of a movie announced by `movies/new`, waiting for its frames.
+    pub(crate) pending_movie: Option<MovieInfo>,
+}
+
+impl EmulatorState {
+    fn new(config: &EmulatorConfig) -> Self {
+        let device_info = config.device_info.clone();
+        let coordinates = config
+            .layout
+            .clone()
+            .unwrap_or_else(|| spiral_layout(device_info.number_of_led));
+        let frame_len = device_info.number_of_led * device_info.bytes_per_led;
+        EmulatorState {
+            layout: LayoutResponse {
+                source: "3d".to_string(),
+                synthesized: config.layout.is_none(),
+                uuid: Uuid::new_v4().to_string(),
+                coordinates,
+                code: traits::OK.code,
+            },
+            mode: DeviceMode::Movie,
+            brightness: 100,
+            brightness_enabled: true,
+            time_on: -1,
+            time_off: -1,
+            playlist: PlaylistResponse {
+                entries: Vec::new(),
+                unique_id: Uuid::new_v4().to_string(),
+                name: String::new(),
+                code: traits::OK.code,
+            },
+            current_playlist_entry: None,
+            movies: Vec::new(),
+            current_movie: None,
+            movie_config: MovieConfigResponse {
+                frame_delay: 100,
+                leds_number: device_info.number_of_led,
+                frames_number: 0,
+                code: traits::OK.code,
+            },
+            rt_frame: vec![0; frame_len],
+            rt_packets: Vec::new(),
+            logins: 0,
+            tokens: HashMap::new(),
+            mode_before_rt: DeviceMode::Movie,
+            last_rt_frame_at: None,
+            token_lifetime: config.token_lifetime,
+            rt_timeout: config.rt_timeout,
+            check_challenge_response: config.check_challenge_response,
+            started_at: Instant::now(),
+            next_movie_id: 0,
+            pending_movie: None,
+            firmware_version: config.firmware_version.clone(),
+            device_info,
+        }
+    }
+
+    /// Whether `token` was issued, verified and hasn't expired yet.
+    pub fn is_token_valid(&self, token: &str) -> bool {
+        self.tokens
+            .get(token)
+            .is_some_and(|issued| issued.verified && issued.expires_at > Instant::now())
+    }
+
+    /// Frames still available for movie uploads.
+    pub fn available_frames(&self) -> usize {
+        let used: usize = self
+            .movies
+            .iter()
+            .map(|movie| movie.info.frames_number)
+            .sum();
+        self.device_info.movie_capacity.saturating_sub(used)
+    }
+
+    /// Switches the mode, remembering the mode to fall back to when real-time frames stop.
+    pub(crate) fn set_mode(&mut self, mode: DeviceMode) {
+        if mode == DeviceMode::RealTime && self.mode != DeviceMode::RealTime {
+            self.mode_before_rt = self.mode;
+            self.last_rt_frame_at = Some(Instant::now());
+        }
+        self.mode = mode;
+    }
+
+    /// Leaves real-time mode if no frame arrived within the real-time timeout, like the hardware does.
+    pub(crate) fn expire_realtime(&mut self) {
+        if self.mode != DeviceMode::RealTime {
+            return;
+        }
+        if let Some(last) = self.last_rt_frame_at {
+            if last.elapsed() > self.rt_timeout {
+                self.mode = self.mode_before_rt;
+                self.last_rt_frame_at = None;
+            }
+        }
+    }
+}
+
+/**
+A running emulated device.
+
+The listeners are stopped when the emulator is dropped.
+ */
+pub struct DeviceEmulator {
+    http_addr: SocketAddr,
+    rt_addr: SocketAddr,
+    discovery_addr: Option<SocketAddr>,
+    state: Arc<Mutex<EmulatorState>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl DeviceEmulator {
+    /// Binds all listeners and starts serving in the background.
+    pub async fn start(config: EmulatorConfig) -> Result<Self> {
+        let state = Arc::new(Mutex::new(EmulatorState::new(&config)));
+
+        let http_listener = TcpListener::bind(config.http_addr)
+            .await
+            .map_err(|e| bind_error("HTTP listener", config.http_addr, e))?;
+        let http_addr = http_listener.local_addr()?;
+
+        let rt_socket = UdpSocket::bind(config.rt_addr)
+            .await
+            .map_err(|e| bind_error("real-time listener", config.rt_addr, e))?;
+        let rt_addr = rt_socket.local_addr()?;
+
+        let mut tasks = vec![
+            tokio::spawn(http::serve(http_listener, state.clone())),
+            tokio::spawn(realtime::serve(rt_socket, state.clone())),
+        ];
+
+        let discovery_addr = match config.discovery_addr {
+            Some(addr) => {
+                let socket = UdpSocket::bind(addr)
+                    .await
+                    .map_err(|e| bind_error("discovery responder", addr, e))?;
+                let discovery_addr = socket.local_addr()?;
+                let device_id = discovery::device_id(&config.device_info.mac);
+                tasks.push(tokio::spawn(discovery::serve(
+                    socket,
+                    device_id,
+                    config.advertised_ip,
+                )));
+                Some(discovery_addr)
+            }
+            None => None,
+        };
+
+        info!(
+            "Emulating device {} on http://{}, real-time on {}",
+            config.device_info.device_name, http_addr, rt_addr
+        );
+
+        Ok(DeviceEmulator {
+            http_addr,
+            rt_addr,
+            discovery_addr,
+            state,
+            tasks,
+        })
+    }
+
+    pub fn http_addr(&self) -> SocketAddr {
+        self.http_addr
+    }
+
+    pub fn rt_addr(&self) -> SocketAddr {
+        self.rt_addr
+    }
+
+    pub fn discovery_addr(&self) -> Option<SocketAddr> {
+        self.discovery_addr
+    }
+
+    /// The host to pass to [ControlInterface::new], including the HTTP port.
+    pub fn host(&self) -> String {
+        self.http_addr.to_string()
+    }
+
+    pub fn mac(&self) -> String {
+        self.state().device_info.mac.clone()
+    }
+
+    /// Locks and returns the device state, for inspection or manipulation.
+    pub fn state(&self) -> MutexGuard<'_, EmulatorState> {
+        self.state.lock().expect("emulator state poisoned")
+    }
+
+    /// Authenticates a [ControlInterface] against this emulator, with the real-time port already set.
+    pub async fn control_interface(&self) -> Result<ControlInterface> {
+        let control = ControlInterface::new(&self.host(), &self.mac(), None).await?;
+        Ok(control.with_rt_port(self.rt_addr.port()))
+    }
+
+    /// Serves until one of the listeners fails, e.g. to run the emulator from a CLI.
+    pub async fn run(mut self) -> Result<()> {
+        let tasks = std::mem::take(&mut self.tasks);
+        let (result, _, remaining) = futures::future::select_all(tasks).await;
+        for task in remaining {
+            task.abort();
+        }
+        result.map_err(|e| io::Error::other(format!("Emulator listener stopped: {}", e)).into())
+    }
+}
+
+/// Keeps the address in the error, since the I/O error alone doesn't say which listener failed.
+fn bind_error(listener: &str, addr: SocketAddr, e: io::Error) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        format!("Failed to bind {} on {}: {}", listener, addr, e),
+    )
+}
+
+impl Drop for DeviceEmulator {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Synthesizes a spiral around a cone, which resembles a tree.
+/// Coordinates are rounded to the precision the firmware reports.
+fn spiral_layout(number_of_led: usize) -> Vec<LedCoordinate> {
+    let turns = 8.0;
+    let round = |value: f64| (value * 1e6).round() / 1e6;
+    (0..number_of_led)
+        .map(|i| {
+            let t = i as f64 / number_of_led.max(2).saturating_sub(1) as f64;
+            let radius = 1.0 - t;
+            let angle = t * turns * 2.0 * PI;
+            LedCoordinate {
+                x: round(radius * angle.cos()),
+                y: round(t),
+                z: round(radius * angle.sin()),
+            }
+        })
+        .collect()
+}
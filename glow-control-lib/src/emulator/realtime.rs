@@ -0,0 +1,145 @@
+//! Decoding of the real-time UDP protocol, as received by the emulator.
+
+use std::sync::{Arc, Mutex};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{debug, error};
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+use crate::control_interface::DeviceMode;
+use crate::emulator::EmulatorState;
+
+/// Length of the decoded authentication token at the start of every packet.
+const TOKEN_LENGTH: usize = 8;
+
+/// Number of frame bytes carried by each version 3 fragment.
+pub const V3_FRAGMENT_SIZE: usize = 900;
+
+/// A decoded real-time packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RealtimePacket {
+    /// Protocol version, 1 to 3.
+    pub version: u8,
+    /// The decoded authentication token.
+    pub token: Vec<u8>,
+    /// The LED count from the header, only sent by version 1.
+    pub number_of_led: Option<u8>,
+    /// The fragment index, only sent by version 3.
+    pub fragment: Option<u8>,
+    /// Frame bytes carried by this packet.
+    pub data: Vec<u8>,
+}
+
+impl RealtimePacket {
+    /**
+    Decodes a packet of any protocol version.
+
+    Returns `None` for unknown versions or packets too short to hold their header.
+     */
+    pub fn decode(packet: &[u8]) -> Option<Self> {
+        let version = *packet.first()?;
+        let header_length = match version {
+            1 | 2 => 1 + TOKEN_LENGTH + 1,
+            3 => 1 + TOKEN_LENGTH + 3,
+            _ => return None,
+        };
+        if packet.len() < header_length {
+            return None;
+        }
+        let token = packet[1..1 + TOKEN_LENGTH].to_vec();
+        let (number_of_led, fragment) = match version {
+            1 => (Some(packet[1 + TOKEN_LENGTH]), None),
+            2 => (None, None),
+            _ => (None, Some(packet[header_length - 1])),
+        };
+        Some(RealtimePacket {
+            version,
+            token,
+            number_of_led,
+            fragment,
+            data: packet[header_length..].to_vec(),
+        })
+    }
+
+    /// The byte offset of this packet's data within the frame.
+    pub fn frame_offset(&self) -> usize {
+        self.fragment
+            .map_or(0, |fragment| fragment as usize * V3_FRAGMENT_SIZE)
+    }
+}
+
+pub(crate) async fn serve(socket: UdpSocket, state: Arc<Mutex<EmulatorState>>) {
+    let mut buffer = [0u8; 2048];
+    loop {
+        let number_of_bytes = match socket.recv_from(&mut buffer).await {
+            Ok((number_of_bytes, _)) => number_of_bytes,
+            Err(e) => {
+                error!("Emulator real-time listener failed: {}", e);
+                return;
+            }
+        };
+        let Some(packet) = RealtimePacket::decode(&buffer[..number_of_bytes]) else {
+            debug!("Ignoring undecodable real-time packet");
+            continue;
+        };
+
+        let mut state = state.lock().expect("emulator state poisoned");
+        state.expire_realtime();
+        let token = STANDARD.encode(&packet.token);
+        if !state.is_token_valid(&token) {
+            debug!("Ignoring real-time packet with invalid token");
+            continue;
+        }
+        // Like the hardware, frames are only shown while in real-time mode.
+        if state.mode == DeviceMode::RealTime {
+            let offset = packet.frame_offset();
+            let end = (offset + packet.data.len()).min(state.rt_frame.len());
+            if offset < end {
+                state.rt_frame[offset..end].copy_from_slice(&packet.data[..end - offset]);
+            }
+            state.last_rt_frame_at = Some(Instant::now());
+        }
+        state.rt_packets.push(packet);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_versions() {
+        let token = [7u8; TOKEN_LENGTH];
+
+        let mut v1 = vec![1];
+        v1.extend_from_slice(&token);
+        v1.extend_from_slice(&[2, 10, 20, 30, 40, 50, 60]);
+        let packet = RealtimePacket::decode(&v1).unwrap();
+        assert_eq!(packet.number_of_led, Some(2));
+        assert_eq!(packet.data, vec![10, 20, 30, 40, 50, 60]);
+
+        let mut v2 = vec![2];
+        v2.extend_from_slice(&token);
+        v2.extend_from_slice(&[0, 1, 2, 3]);
+        let packet = RealtimePacket::decode(&v2).unwrap();
+        assert_eq!((packet.number_of_led, packet.fragment), (None, None));
+        assert_eq!(packet.data, vec![1, 2, 3]);
+
+        let mut v3 = vec![3];
+        v3.extend_from_slice(&token);
+        v3.extend_from_slice(&[0, 0, 2, 9, 9, 9]);
+        let packet = RealtimePacket::decode(&v3).unwrap();
+        assert_eq!(packet.fragment, Some(2));
+        assert_eq!(packet.frame_offset(), 2 * V3_FRAGMENT_SIZE);
+        assert_eq!(packet.token, token.to_vec());
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_packets() {
+        assert_eq!(RealtimePacket::decode(&[]), None);
+        assert_eq!(RealtimePacket::decode(&[4; 20]), None);
+        assert_eq!(RealtimePacket::decode(&[3, 1, 2, 3]), None);
+    }
+}
@@ -0,0 +1,99 @@
+use std::io;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use thiserror::Error;
+
+use crate::util::traits::ResponseCode;
+
+/**
+The error type of this library.
+
+The variants tell apart where a failure happened, so callers can react to e.g. an unreachable
+device differently than to a rejected authentication or a request the device refused:
+
+```no_run
+use glow_control_lib::control_interface::ControlInterface;
+use glow_control_lib::util::traits;
+use glow_control_lib::Error;
+
+# async fn example(control: ControlInterface) {
+match control.set_brightness(50).await {
+    Ok(()) => {}
+    Err(Error::Transport(e)) => eprintln!("Device unreachable: {}", e),
+    Err(Error::Device(code)) if code == traits::ERROR_INVALID_ARGUMENT => {
+        eprintln!("Brightness out of range")
+    }
+    Err(e) => eprintln!("{}", e),
+}
+# }
+```
+ */
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The request couldn't be sent, or the response couldn't be received.
+    #[error("Failed to communicate with the device: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// The device answered with an unexpected HTTP status.
+    #[error("Failed to {operation} with status: {status}")]
+    HttpStatus {
+        /// What was attempted, e.g. `set mode`.
+        operation: &'static str,
+        status: StatusCode,
+    },
+
+    /// The device didn't accept the challenge response, or the token couldn't be used.
+    #[error("Authentication failed: {0}")]
+    Authentication(String),
+
+    /// The device didn't finish answering in time.
+    #[error("Failed to {operation} within {timeout:?}")]
+    Timeout {
+        /// What was attempted, e.g. `identify the device`.
+        operation: &'static str,
+        timeout: Duration,
+    },
+
+    /// Another device than the expected one answered, e.g. after the expected one changed its IP address.
+    #[error("Device {found} answered instead of {expected}")]
+    DeviceMismatch {
+        /// The MAC address of the expected device.
+        expected: String,
+        /// The MAC address of the device which answered.
+        found: String,
+    },
+
+    /// The device answered with an error code, one of the constants in [crate::util::traits].
+    #[error("Device returned code {}: {}", .0.code, .0.message)]
+    Device(ResponseCode),
+
+    /// A response, or a JSON input, couldn't be decoded.
+    #[error("Failed to decode JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// A movie file or movie data is malformed, or doesn't fit the device.
+    #[error("Invalid movie: {0}")]
+    MovieFormat(String),
+
+    /// A layout file or layout data is malformed, or doesn't fit the device.
+    #[error("Invalid layout: {0}")]
+    LayoutFormat(String),
+
+    /// The device doesn't have enough free frames for a movie.
+    #[error(
+        "Not enough capacity for the movie: {required} frames required, {available} available"
+    )]
+    Capacity { required: usize, available: usize },
+
+    /// An argument is out of range or can't be parsed.
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// Reading a file or stream, or a UDP socket operation, failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A `Result` with [Error](enum@Error) as the default error type.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,847 @@
+/*!
+Animations producing frames over time, shared by real-time output and movies, see [Animation].
+
+An animation only says which colors the LEDs have at a point in time. The drivers decide
+when frames are needed:
+
+- [ControlInterface::play_animation] plays an animation live, in real-time mode.
+- [Movie::from_animation] renders it into a movie, which can be uploaded with
+  [ControlInterface::upload_named_movie].
+- [write_animation] renders it into a movie file.
+
+Animations are combined like iterators, for example a rainbow crossfading into fire after ten
+seconds, with twinkles on top and everything twice as fast:
+
+```
+use glow_control_lib::led::animation::{Animation, BlendMode, SpatialAnimation};
+use glow_control_lib::led::spatial::{Fire, RotatingRainbow, Twinkle};
+# let layout = Vec::new();
+
+let animation = SpatialAnimation::new(RotatingRainbow::default(), layout.clone())
+    .limit(10.0)
+    .crossfade(SpatialAnimation::new(Fire::new(0), layout.clone()), 2.0)
+    .layer(
+        SpatialAnimation::new(Twinkle::new((255, 255, 255), 0), layout),
+        BlendMode::Screen,
+    )
+    .speed(2.0);
+```
+ */
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use tokio::time::Instant;
+
+use crate::control_interface::{ControlInterface, LedCoordinate, LedProfile, RGB};
+use crate::error::{Error, Result};
+use crate::led::pattern::Pattern;
+use crate::led::rgbw::WhiteExtraction;
+use crate::led::spatial::SpatialEffect;
+use crate::util::movie::{Movie, MovieFileFormat};
+
+/**
+Colors for a number of LEDs at any point in time.
+
+Time is in seconds from the start of the animation. Drivers ask for frames in increasing time,
+which lets animations like [ShineAnimation] keep state between frames.
+ */
+pub trait Animation: Send {
+    /// The colors of `led_count` LEDs after `time` seconds.
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)>;
+
+    /// How long the animation lasts in seconds, `None` if it goes on forever.
+    fn duration(&self) -> Option<f64> {
+        None
+    }
+
+    /// Plays `next` after this animation has ended.
+    fn then<A: Animation + 'static>(self, next: A) -> Sequence
+    where
+        Self: Sized + 'static,
+    {
+        Sequence::new(vec![Box::new(self), Box::new(next)])
+    }
+
+    /// Fades into `next` during the last `fade` seconds of this animation.
+    fn crossfade<A: Animation>(self, next: A, fade: f64) -> Crossfade<Self, A>
+    where
+        Self: Sized,
+    {
+        Crossfade {
+            first: self,
+            second: next,
+            fade,
+        }
+    }
+
+    /// Draws `top` over this animation, combining the colors with `mode`.
+    fn layer<A: Animation>(self, top: A, mode: BlendMode) -> Layer<Self, A>
+    where
+        Self: Sized,
+    {
+        Layer {
+            bottom: self,
+            top,
+            mode,
+            opacity: 1.0,
+        }
+    }
+
+    /// Repeats this animation `count` times, or forever if `None`.
+    fn repeat(self, count: Option<usize>) -> Loop<Self>
+    where
+        Self: Sized,
+    {
+        Loop { inner: self, count }
+    }
+
+    /// Plays this animation `factor` times as fast.
+    fn speed(self, factor: f64) -> Speed<Self>
+    where
+        Self: Sized,
+    {
+        Speed {
+            inner: self,
+            factor,
+        }
+    }
+
+    /// Ends this animation after `duration` seconds at the latest.
+    fn limit(self, duration: f64) -> Limit<Self>
+    where
+        Self: Sized,
+    {
+        Limit {
+            inner: self,
+            duration,
+        }
+    }
+
+    /// Boxes this animation, to keep animations of different types together.
+    fn boxed(self) -> Box<dyn Animation>
+    where
+        Self: Sized + 'static,
+    {
+        Box::new(self)
+    }
+}
+
+impl<A: Animation + ?Sized> Animation for Box<A> {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        (**self).frame(time, led_count)
+    }
+
+    fn duration(&self) -> Option<f64> {
+        (**self).duration()
+    }
+}
+
+/// The same color on all LEDs, forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Solid {
+    pub color: (u8, u8, u8),
+}
+
+impl Solid {
+    pub fn new(color: (u8, u8, u8)) -> Solid {
+        Solid { color }
+    }
+}
+
+impl Animation for Solid {
+    fn frame(&mut self, _time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        vec![self.color; led_count]
+    }
+}
+
+/// An animation computed by a closure from the time and the number of LEDs, see [from_fn].
+pub struct FromFn<F> {
+    frame: F,
+}
+
+/// An animation going on forever, computing each frame with `frame(time, led_count)`.
+pub fn from_fn<F>(frame: F) -> FromFn<F>
+where
+    F: FnMut(f64, usize) -> Vec<(u8, u8, u8)> + Send,
+{
+    FromFn { frame }
+}
+
+impl<F> Animation for FromFn<F>
+where
+    F: FnMut(f64, usize) -> Vec<(u8, u8, u8)> + Send,
+{
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        (self.frame)(time, led_count)
+    }
+}
+
+/**
+A [SpatialEffect] shown on the LEDs of a layout, going on forever.
+
+Frames have one color per LED of the layout, cut off or filled up with black to the number
+of LEDs asked for.
+ */
+pub struct SpatialAnimation<E> {
+    pub effect: E,
+    pub leds: Vec<LedCoordinate>,
+}
+
+impl<E: SpatialEffect> SpatialAnimation<E> {
+    pub fn new(effect: E, leds: Vec<LedCoordinate>) -> SpatialAnimation<E> {
+        SpatialAnimation { effect, leds }
+    }
+}
+
+impl<E: SpatialEffect> Animation for SpatialAnimation<E> {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let mut frame = self.effect.frame(&self.leds, time);
+        frame.resize(led_count, (0, 0, 0));
+        frame
+    }
+}
+
+/// A movie played once, with the frames cut off or filled up with black to the LEDs asked for.
+impl Animation for Movie {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let index = (time.max(0.0) * self.fps) as usize;
+        let mut frame = self
+            .frames
+            .get(index.min(self.frames.len().saturating_sub(1)))
+            .cloned()
+            .unwrap_or_default();
+        frame.resize(led_count, (0, 0, 0));
+        frame
+    }
+
+    fn duration(&self) -> Option<f64> {
+        Some(self.frames.len() as f64 / self.fps)
+    }
+}
+
+/**
+LEDs glowing up and fading out at random, in random colors.
+
+Shine advances one step per frame, so steps are taken at a fixed frame rate, however often
+frames are asked for.
+ */
+pub struct ShineAnimation {
+    colors: Vec<(u8, u8, u8)>,
+    fps: f64,
+    frames_between_glow_start: u64,
+    frames_to_max_glow: u64,
+    frames_to_fade: u64,
+    num_start_simultaneous: usize,
+    rng: StdRng,
+    /// The step each LED last started to glow at, `None` if it never did.
+    glow_starts: Vec<Option<u64>>,
+    glow_colors: Vec<(u8, u8, u8)>,
+    frames_since_glow_start: u64,
+    steps: u64,
+    current: Vec<(u8, u8, u8)>,
+}
+
+impl ShineAnimation {
+    /**
+    A shine effect on `led_count` LEDs, stepping at `fps`.
+
+    # Errors
+    [Error::InvalidArgument] if `colors` is empty, `num_start_simultaneous` isn't between 1 and
+    `led_count`, or the frame rate isn't positive.
+     */
+    pub fn new(
+        led_count: usize,
+        colors: HashSet<RGB>,
+        time_between_glow_start: Duration,
+        time_to_max_glow: Duration,
+        time_to_fade: Duration,
+        num_start_simultaneous: usize,
+        fps: f64,
+    ) -> Result<ShineAnimation> {
+        check_fps(fps)?;
+        if num_start_simultaneous == 0 || num_start_simultaneous > led_count {
+            return Err(Error::InvalidArgument(format!(
+                "The number of LEDs starting to glow at once must be between 1 and {}",
+                led_count
+            )));
+        }
+        // Sorted, as the order of a set changes from run to run and would defeat seeding
+        let mut colors: Vec<_> = colors
+            .into_iter()
+            .map(|color| (color.red, color.green, color.blue))
+            .collect();
+        colors.sort_unstable();
+        if colors.is_empty() {
+            return Err(Error::InvalidArgument(
+                "The shine effect needs at least one color".to_string(),
+            ));
+        }
+        let frames = |duration: Duration| (duration.as_secs_f64() * fps) as u64;
+        let frames_between_glow_start = frames(time_between_glow_start);
+        Ok(ShineAnimation {
+            glow_colors: vec![colors[0]; led_count],
+            colors,
+            fps,
+            frames_between_glow_start,
+            frames_to_max_glow: frames(time_to_max_glow),
+            frames_to_fade: frames(time_to_fade),
+            num_start_simultaneous,
+            rng: StdRng::from_entropy(),
+            glow_starts: vec![None; led_count],
+            // The first LEDs start to glow right away
+            frames_since_glow_start: frames_between_glow_start,
+            steps: 0,
+            current: vec![(0, 0, 0); led_count],
+        })
+    }
+
+    /// Picks the glowing LEDs and their colors with `rng`, the same for the same generator.
+    pub fn with_rng(mut self, rng: StdRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Picks the glowing LEDs and their colors the same way for the same `seed`.
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.with_rng(StdRng::seed_from_u64(seed))
+    }
+
+    fn step(&mut self) {
+        let glow_length = self.frames_to_max_glow + self.frames_to_fade;
+        let elapsed = |start: Option<u64>| start.map_or(glow_length, |start| self.steps - start);
+        if self.frames_since_glow_start >= self.frames_between_glow_start {
+            let mut available: Vec<usize> = (0..self.glow_starts.len())
+                .filter(|&led| elapsed(self.glow_starts[led]) >= glow_length)
+                .collect();
+            available.shuffle(&mut self.rng);
+            for &led in available.iter().take(self.num_start_simultaneous) {
+                self.glow_starts[led] = Some(self.steps);
+                self.glow_colors[led] = *self.colors.choose(&mut self.rng).expect("no colors");
+            }
+            self.frames_since_glow_start = 0;
+        } else {
+            self.frames_since_glow_start += 1;
+        }
+
+        for (led, color) in self.current.iter_mut().enumerate() {
+            let elapsed = elapsed(self.glow_starts[led]);
+            let brightness = if elapsed < self.frames_to_max_glow {
+                elapsed as f64 / self.frames_to_max_glow as f64
+            } else if elapsed < glow_length {
+                1.0 - (elapsed - self.frames_to_max_glow) as f64 / self.frames_to_fade as f64
+            } else {
+                0.0
+            };
+            *color = Pattern::dim_color(self.glow_colors[led], brightness);
+        }
+        self.steps += 1;
+    }
+}
+
+impl Animation for ShineAnimation {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let target = (time.max(0.0) * self.fps) as u64 + 1;
+        while self.steps < target {
+            self.step();
+        }
+        let mut frame = self.current.clone();
+        frame.resize(led_count, (0, 0, 0));
+        frame
+    }
+}
+
+/**
+Animations played one after another.
+
+An animation going on forever is never left, so only the last one should do that.
+ */
+pub struct Sequence {
+    parts: Vec<Box<dyn Animation>>,
+}
+
+impl Sequence {
+    pub fn new(parts: Vec<Box<dyn Animation>>) -> Sequence {
+        Sequence { parts }
+    }
+
+    /// Appends `next`, flattening chains of [Animation::then].
+    pub fn then<A: Animation + 'static>(mut self, next: A) -> Sequence {
+        self.parts.push(Box::new(next));
+        self
+    }
+}
+
+impl Animation for Sequence {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let mut start = 0.0;
+        let last = self.parts.len().saturating_sub(1);
+        for (index, part) in self.parts.iter_mut().enumerate() {
+            match part.duration() {
+                Some(duration) if index < last && time >= start + duration => start += duration,
+                Some(duration) => return part.frame((time - start).min(duration), led_count),
+                None => return part.frame(time - start, led_count),
+            }
+        }
+        vec![(0, 0, 0); led_count]
+    }
+
+    fn duration(&self) -> Option<f64> {
+        self.parts.iter().map(|part| part.duration()).sum()
+    }
+}
+
+/**
+Plays `first`, then blends into `second` over the last `fade` seconds of `first`.
+
+`second` starts at the beginning of the fade. If `first` goes on forever, `second` never shows.
+ */
+pub struct Crossfade<A, B> {
+    pub first: A,
+    pub second: B,
+    /// Seconds the fade takes.
+    pub fade: f64,
+}
+
+impl<A, B> Crossfade<A, B> {
+    fn second_start(&self, first_duration: f64) -> f64 {
+        (first_duration - self.fade.max(0.0)).max(0.0)
+    }
+}
+
+impl<A: Animation, B: Animation> Animation for Crossfade<A, B> {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let Some(first_duration) = self.first.duration() else {
+            return self.first.frame(time, led_count);
+        };
+        let start = self.second_start(first_duration);
+        if time < start {
+            return self.first.frame(time, led_count);
+        }
+        let second = self.second.frame(time - start, led_count);
+        if time >= first_duration {
+            return second;
+        }
+        let first = self.first.frame(time, led_count);
+        let amount = (time - start) / (first_duration - start);
+        first
+            .into_iter()
+            .zip(second)
+            .map(|(from, to)| Pattern::blend_colors(from, to, amount))
+            .collect()
+    }
+
+    fn duration(&self) -> Option<f64> {
+        let first_duration = self.first.duration()?;
+        Some(self.second_start(first_duration) + self.second.duration()?)
+    }
+}
+
+/// How [Layer] combines the colors of the top animation with the ones below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// The top colors replace the ones below.
+    #[default]
+    Normal,
+    /// The colors are added, saturating at full brightness.
+    Add,
+    /// The colors are multiplied, black on top makes black.
+    Multiply,
+    /// The inverse of multiplying the inverted colors, only ever brightens.
+    Screen,
+    /// The brighter of both, per channel.
+    Lighten,
+    /// The darker of both, per channel.
+    Darken,
+}
+
+impl BlendMode {
+    /// Blends one channel of the top color over the bottom one.
+    pub fn blend_channel(self, bottom: u8, top: u8) -> u8 {
+        match self {
+            BlendMode::Normal => top,
+            BlendMode::Add => bottom.saturating_add(top),
+            BlendMode::Multiply => ((bottom as u16 * top as u16 + 127) / 255) as u8,
+            BlendMode::Screen => {
+                255 - (((255 - bottom) as u16 * (255 - top) as u16 + 127) / 255) as u8
+            }
+            BlendMode::Lighten => bottom.max(top),
+            BlendMode::Darken => bottom.min(top),
+        }
+    }
+
+    /// Blends the top color over the bottom one.
+    pub fn blend(self, bottom: (u8, u8, u8), top: (u8, u8, u8)) -> (u8, u8, u8) {
+        (
+            self.blend_channel(bottom.0, top.0),
+            self.blend_channel(bottom.1, top.1),
+            self.blend_channel(bottom.2, top.2),
+        )
+    }
+}
+
+/**
+One animation drawn over another.
+
+Lasts as long as the longer of both, the shorter one keeps its last frame.
+ */
+pub struct Layer<A, B> {
+    pub bottom: A,
+    pub top: B,
+    pub mode: BlendMode,
+    /// How much of the blended colors shows, from 0 for only the bottom to 1.
+    pub opacity: f64,
+}
+
+impl<A, B> Layer<A, B> {
+    /// Sets how much of the blended colors shows, from 0 for only the bottom to 1.
+    pub fn with_opacity(mut self, opacity: f64) -> Self {
+        self.opacity = opacity;
+        self
+    }
+}
+
+impl<A: Animation, B: Animation> Animation for Layer<A, B> {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let bottom = held_frame(&mut self.bottom, time, led_count);
+        let top = held_frame(&mut self.top, time, led_count);
+        let opacity = self.opacity.clamp(0.0, 1.0);
+        bottom
+            .into_iter()
+            .zip(top)
+            .map(|(bottom, top)| {
+                Pattern::blend_colors(bottom, self.mode.blend(bottom, top), opacity)
+            })
+            .collect()
+    }
+
+    fn duration(&self) -> Option<f64> {
+        Some(self.bottom.duration()?.max(self.top.duration()?))
+    }
+}
+
+/**
+An animation repeated a number of times, or forever.
+
+Animations going on forever are played once, as there is nothing to repeat.
+ */
+pub struct Loop<A> {
+    pub inner: A,
+    /// How often to play the animation, forever if `None`.
+    pub count: Option<usize>,
+}
+
+impl<A: Animation> Animation for Loop<A> {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        match self.inner.duration() {
+            Some(duration) if duration > 0.0 => {
+                let round = (time.max(0.0) / duration).floor();
+                if self.count.is_some_and(|count| round >= count as f64) {
+                    self.inner.frame(duration, led_count)
+                } else {
+                    self.inner.frame(time - round * duration, led_count)
+                }
+            }
+            _ => self.inner.frame(time, led_count),
+        }
+    }
+
+    fn duration(&self) -> Option<f64> {
+        let duration = self.inner.duration()?;
+        Some(duration * self.count? as f64)
+    }
+}
+
+/// An animation played faster, or slower with a factor below 1.
+pub struct Speed<A> {
+    pub inner: A,
+    /// How many seconds of the animation play per second, positive.
+    pub factor: f64,
+}
+
+impl<A: Animation> Animation for Speed<A> {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        self.inner.frame(time * self.factor, led_count)
+    }
+
+    fn duration(&self) -> Option<f64> {
+        self.inner
+            .duration()
+            .filter(|_| self.factor > 0.0)
+            .map(|duration| duration / self.factor)
+    }
+}
+
+/// An animation ended after a time, keeping its last frame if asked for frames after that.
+pub struct Limit<A> {
+    pub inner: A,
+    /// Seconds after which the animation ends.
+    pub duration: f64,
+}
+
+impl<A: Animation> Animation for Limit<A> {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        self.inner.frame(time.min(self.duration), led_count)
+    }
+
+    fn duration(&self) -> Option<f64> {
+        Some(
+            self.inner
+                .duration()
+                .map_or(self.duration, |duration| duration.min(self.duration)),
+        )
+    }
+}
+
+/// The frame of `animation` at `time`, or its last frame once it has ended.
+fn held_frame<A: Animation>(animation: &mut A, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+    let time = animation
+        .duration()
+        .map_or(time, |duration| time.min(duration));
+    animation.frame(time, led_count)
+}
+
+pub(crate) fn check_fps(fps: f64) -> Result<()> {
+    if !fps.is_finite() || fps <= 0.0 {
+        return Err(Error::InvalidArgument(format!(
+            "Invalid frame rate: {}",
+            fps
+        )));
+    }
+    Ok(())
+}
+
+impl Movie {
+    /**
+    Renders an animation for `led_count` LEDs at `fps`.
+
+    Renders `duration` seconds, or the whole animation if `None`.
+
+    # Errors
+    [Error::InvalidArgument] for a frame rate that isn't positive, or if neither `duration`
+    nor the animation says how long to render.
+     */
+    pub fn from_animation(
+        animation: &mut dyn Animation,
+        led_count: usize,
+        fps: f64,
+        duration: Option<f64>,
+    ) -> Result<Movie> {
+        check_fps(fps)?;
+        let duration = duration.or(animation.duration()).ok_or_else(|| {
+            Error::InvalidArgument(
+                "The animation goes on forever, a duration is needed to render it".to_string(),
+            )
+        })?;
+        let frame_count = (duration * fps).round().max(1.0) as usize;
+        Ok(Movie {
+            frames: (0..frame_count)
+                .map(|frame| animation.frame(frame as f64 / fps, led_count))
+                .collect(),
+            fps,
+            ..Movie::default()
+        })
+    }
+}
+
+/**
+Renders an animation into a movie file, see [Movie::from_animation] and [Movie::save].
+
+RGBW colors are encoded with [WhiteExtraction::MinSubtraction].
+ */
+pub fn write_animation<P: AsRef<Path>>(
+    animation: &mut dyn Animation,
+    led_count: usize,
+    fps: f64,
+    duration: Option<f64>,
+    path: P,
+    format: MovieFileFormat,
+    led_profile: LedProfile,
+) -> Result<()> {
+    Movie::from_animation(animation, led_count, fps, duration)?.save(
+        path,
+        format,
+        led_profile,
+        WhiteExtraction::default(),
+    )
+}
+
+impl ControlInterface {
+    /**
+    Plays an animation in real time on all LEDs of the device, at up to `frame_rate` frames
+    per second.
+
+    Runs until the animation has ended, or forever for animations without a duration.
+     */
+    pub async fn play_animation(
+        &self,
+        animation: &mut dyn Animation,
+        frame_rate: f64,
+    ) -> Result<()> {
+        check_fps(frame_rate)?;
+        let led_count = self.get_device_info().number_of_led;
+        let mut session = self.realtime_session().await?;
+        session.set_frame_rate(frame_rate);
+        let start = Instant::now();
+        loop {
+            let time = start.elapsed().as_secs_f64();
+            if animation
+                .duration()
+                .is_some_and(|duration| time >= duration)
+            {
+                break;
+            }
+            let frame = animation.frame(time, led_count);
+            session.send_frame(&self.pack_frame(&frame)).await?;
+        }
+        session.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One LED whose red channel counts the seconds, for a given number of seconds.
+    struct Clock(f64);
+
+    impl Animation for Clock {
+        fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+            vec![(time as u8, 0, 0); led_count]
+        }
+
+        fn duration(&self) -> Option<f64> {
+            Some(self.0)
+        }
+    }
+
+    fn red(animation: &mut dyn Animation, time: f64) -> u8 {
+        animation.frame(time, 1)[0].0
+    }
+
+    #[test]
+    fn test_sequence() {
+        let mut sequence = Clock(2.0).then(Clock(3.0)).then(Solid::new((9, 9, 9)));
+        assert_eq!(sequence.duration(), None);
+        assert_eq!(red(&mut sequence, 1.5), 1);
+        assert_eq!(red(&mut sequence, 4.5), 2);
+        assert_eq!(sequence.frame(5.0, 2), vec![(9, 9, 9); 2]);
+
+        let mut finite = Clock(2.0).then(Clock(3.0));
+        assert_eq!(finite.duration(), Some(5.0));
+        // After the end, the last part keeps its last frame
+        assert_eq!(red(&mut finite, 10.0), 3);
+    }
+
+    #[test]
+    fn test_crossfade() {
+        let mut fade = Solid::new((200, 0, 0))
+            .limit(4.0)
+            .crossfade(Solid::new((0, 0, 200)).limit(3.0), 2.0);
+        assert_eq!(fade.duration(), Some(5.0));
+        assert_eq!(fade.frame(1.0, 1), vec![(200, 0, 0)]);
+        assert_eq!(fade.frame(3.0, 1), vec![(100, 0, 100)]);
+        assert_eq!(fade.frame(4.5, 1), vec![(0, 0, 200)]);
+    }
+
+    #[test]
+    fn test_layer() {
+        let mut layer = Solid::new((100, 50, 0)).layer(Solid::new((100, 100, 100)), BlendMode::Add);
+        assert_eq!(layer.frame(0.0, 1), vec![(200, 150, 100)]);
+        assert_eq!(
+            BlendMode::Multiply.blend((255, 128, 0), (128, 255, 255)),
+            (128, 128, 0)
+        );
+        assert_eq!(
+            BlendMode::Screen.blend((0, 255, 128), (0, 0, 255)),
+            (0, 255, 255)
+        );
+
+        let mut half = Solid::new((0, 0, 0))
+            .layer(Solid::new((200, 200, 200)), BlendMode::Normal)
+            .with_opacity(0.5);
+        assert_eq!(half.frame(0.0, 1), vec![(100, 100, 100)]);
+    }
+
+    #[test]
+    fn test_loop_speed_and_limit() {
+        let mut looped = Clock(3.0).repeat(Some(2));
+        assert_eq!(looped.duration(), Some(6.0));
+        assert_eq!(red(&mut looped, 4.0), 1);
+        assert_eq!(red(&mut looped, 7.0), 3);
+        assert_eq!(Clock(3.0).repeat(None).duration(), None);
+
+        let mut fast = Clock(4.0).speed(2.0);
+        assert_eq!(fast.duration(), Some(2.0));
+        assert_eq!(red(&mut fast, 1.5), 3);
+
+        let mut limited = Clock(4.0).limit(2.5);
+        assert_eq!(limited.duration(), Some(2.5));
+        assert_eq!(red(&mut limited, 3.5), 2);
+        assert_eq!(Solid::new((0, 0, 0)).limit(1.0).duration(), Some(1.0));
+    }
+
+    #[test]
+    fn test_movie_from_animation() {
+        let mut animation = Clock(2.0);
+        let movie = Movie::from_animation(&mut animation, 3, 4.0, None).unwrap();
+        assert_eq!(movie.frames.len(), 8);
+        assert_eq!(movie.frames[5], vec![(1, 0, 0); 3]);
+
+        // The movie plays back as an animation with the same frames
+        let mut replay = movie.clone();
+        assert_eq!(replay.duration(), Some(2.0));
+        assert_eq!(replay.frame(1.3, 3), movie.frames[5]);
+
+        assert!(Movie::from_animation(&mut Solid::new((1, 2, 3)), 3, 4.0, None).is_err());
+        let solid = Movie::from_animation(&mut Solid::new((1, 2, 3)), 3, 4.0, Some(1.0)).unwrap();
+        assert_eq!(solid.frames, vec![vec![(1, 2, 3); 3]; 4]);
+    }
+
+    #[test]
+    fn test_shine_steps_at_its_frame_rate() {
+        let colors = HashSet::from([RGB {
+            red: 255,
+            green: 0,
+            blue: 0,
+        }]);
+        let millis = Duration::from_millis;
+        let mut shine =
+            ShineAnimation::new(4, colors, millis(100), millis(200), millis(200), 1, 10.0).unwrap();
+        // Asking for the same step twice doesn't advance the effect
+        let first = shine.frame(0.01, 4);
+        assert_eq!(shine.frame(0.05, 4), first);
+        shine.frame(0.5, 4);
+        assert_eq!(shine.steps, 6);
+        assert!(shine.frame(0.6, 4).iter().any(|&(red, _, _)| red > 0));
+    }
+
+    #[test]
+    fn test_seeded_shine_renders_the_same_movie() {
+        let colors: HashSet<RGB> = [(255, 0, 0), (0, 255, 0), (0, 0, 255)]
+            .into_iter()
+            .map(|(red, green, blue)| RGB { red, green, blue })
+            .collect();
+        let millis = Duration::from_millis;
+        let render = |seed: u64| {
+            let mut shine = ShineAnimation::new(
+                20,
+                colors.clone(),
+                millis(100),
+                millis(300),
+                millis(300),
+                3,
+                10.0,
+            )
+            .unwrap()
+            .with_seed(seed);
+            Movie::from_animation(&mut shine, 20, 10.0, Some(3.0)).unwrap()
+        };
+        assert_eq!(render(5), render(5));
+        assert_ne!(render(5), render(6));
+    }
+}
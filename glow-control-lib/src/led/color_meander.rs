@@ -1,7 +1,10 @@
 use crate::led::led_color::LedColor;
-use rand::Rng;
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::f64::consts::PI;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum MeanderStyle {
     Sphere,
     Cylinder,
@@ -14,11 +17,33 @@ pub struct ColorMeander {
     xyz: (f64, f64, f64),
     dir: (f64, f64, f64),
     style: MeanderStyle,
+    rng: StdRng,
 }
 
 impl ColorMeander {
     pub fn new(style: MeanderStyle, speed: f64, noise: f64, start: (f64, f64, f64)) -> Self {
-        let mut rng = rand::thread_rng();
+        ColorMeander::with_rng(style, speed, noise, start, StdRng::from_entropy())
+    }
+
+    /// A meander taking the same steps for the same `seed`.
+    pub fn with_seed(
+        style: MeanderStyle,
+        speed: f64,
+        noise: f64,
+        start: (f64, f64, f64),
+        seed: u64,
+    ) -> Self {
+        ColorMeander::with_rng(style, speed, noise, start, StdRng::seed_from_u64(seed))
+    }
+
+    /// A meander drawing its start direction and steps from `rng`.
+    pub fn with_rng(
+        style: MeanderStyle,
+        speed: f64,
+        noise: f64,
+        start: (f64, f64, f64),
+        mut rng: StdRng,
+    ) -> Self {
         let dir = (
             rng.gen_range(-0.5..0.5),
             rng.gen_range(-0.5..0.5),
@@ -30,10 +55,11 @@ impl ColorMeander {
             xyz: start,
             dir,
             style,
+            rng,
         }
     }
 
-    fn normalize(&self, vec: (f64, f64, f64)) -> (f64, f64, f64) {
+    fn normalize(vec: (f64, f64, f64)) -> (f64, f64, f64) {
         let nrm = (vec.0 * vec.0 + vec.1 * vec.1 + vec.2 * vec.2).sqrt();
         if nrm == 0.0 {
             (0.0, 0.0, 0.0)
@@ -82,7 +108,7 @@ impl ColorMeander {
     }
 
     pub fn step(&mut self) {
-        let mut rng = rand::thread_rng();
+        let rng = &mut self.rng;
         let (mut nx, mut ny, mut nz) = (
             self.xyz.0 + self.dir.0 * self.step_length,
             self.xyz.1 + self.dir.1 * self.step_length,
@@ -97,7 +123,7 @@ impl ColorMeander {
                 if nrm > 1.0 {
                     nx /= nrm;
                     ny /= nrm;
-                    self.dir = self.normalize((nx - self.xyz.0, ny - self.xyz.1, nz - self.xyz.2));
+                    self.dir = Self::normalize((nx - self.xyz.0, ny - self.xyz.1, nz - self.xyz.2));
                 }
                 ndir_x += rng.gen_range(-self.noise_level..self.noise_level);
                 ndir_y += rng.gen_range(-self.noise_level..self.noise_level);
@@ -116,7 +142,7 @@ impl ColorMeander {
                 nx /= nrm;
                 ny /= nrm;
                 nz /= nrm;
-                self.dir = self.normalize((nx - self.xyz.0, ny - self.xyz.1, nz - self.xyz.2));
+                self.dir = Self::normalize((nx - self.xyz.0, ny - self.xyz.1, nz - self.xyz.2));
                 ndir_x += rng.gen_range(-self.noise_level..self.noise_level);
                 ndir_y += rng.gen_range(-self.noise_level..self.noise_level);
                 ndir_z += rng.gen_range(-self.noise_level..self.noise_level);
@@ -127,7 +153,7 @@ impl ColorMeander {
                     nx /= nrm * nrm;
                     ny /= nrm * nrm;
                     nz /= nrm * nrm;
-                    self.dir = self.normalize((nx - self.xyz.0, ny - self.xyz.1, nz - self.xyz.2));
+                    self.dir = Self::normalize((nx - self.xyz.0, ny - self.xyz.1, nz - self.xyz.2));
                 }
                 ndir_x += rng.gen_range(-self.noise_level..self.noise_level);
                 ndir_y += rng.gen_range(-self.noise_level..self.noise_level);
@@ -143,3 +169,27 @@ impl ColorMeander {
         self.xyz = (nx, ny, nz);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_meander_repeats() {
+        let walk = |seed: u64| {
+            let mut meander =
+                ColorMeander::with_seed(MeanderStyle::Surface, 0.05, 0.1, (0.0, 0.0, 0.0), seed);
+            (0..50)
+                .map(|_| {
+                    meander.step();
+                    meander.get_xyz()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(walk(3), walk(3));
+        assert_ne!(walk(3), walk(4));
+        // The surface style keeps the meander on the unit sphere
+        let (x, y, z) = *walk(3).last().unwrap();
+        assert!(((x * x + y * y + z * z).sqrt() - 1.0).abs() < 1e-9);
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Result};
+use crate::error::{Error, Result};
 
 #[derive(Debug, Clone, Copy)]
 pub enum ColorStyle {
@@ -15,7 +15,7 @@ pub enum LightnessPolicy {
     Equilight,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ColorModel {
     color_style: ColorStyle,
     lightness_policy: LightnessPolicy,
@@ -44,7 +44,12 @@ impl ColorModel {
             "10col" => self.color_style = ColorStyle::Col10,
             "linear" => self.lightness_policy = LightnessPolicy::Linear,
             "equilight" => self.lightness_policy = LightnessPolicy::Equilight,
-            _ => return Err(anyhow!("Invalid color style or lightness policy")),
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "Invalid color style or lightness policy: {}",
+                    style
+                )))
+            }
         }
         Ok(())
     }
@@ -54,6 +59,7 @@ impl ColorModel {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct LedColor {
     gamma: f64,
     brightness: Vec<f64>,
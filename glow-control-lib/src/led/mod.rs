@@ -1,3 +1,7 @@
+pub mod animation;
 pub mod color_meander;
 pub mod led_color;
 pub mod pattern;
+pub mod pattern_animation;
+pub mod rgbw;
+pub mod spatial;
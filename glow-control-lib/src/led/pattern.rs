@@ -1,5 +1,13 @@
+/*!
+Color patterns for a number of LEDs.
+
+The random patterns use [rand::thread_rng], and have `_with_rng` variants taking the random
+number generator to use instead. With a seeded generator like
+[StdRng::seed_from_u64](rand::SeedableRng::seed_from_u64), the same seed makes the same patterns.
+ */
+
+use crate::error::{Error, Result};
 use crate::led::led_color::LedColor;
-use anyhow::{anyhow, Result};
 use rand::prelude::SliceRandom;
 use rand::Rng;
 use rand_distr::Distribution;
@@ -9,15 +17,20 @@ pub struct Pattern;
 
 impl Pattern {
     pub fn random_discrete(probs: &[f64]) -> Result<usize> {
+        Pattern::random_discrete_with_rng(probs, &mut rand::thread_rng())
+    }
+
+    pub fn random_discrete_with_rng<R: Rng + ?Sized>(probs: &[f64], rng: &mut R) -> Result<usize> {
         let sum: f64 = probs.iter().sum();
         const TOLERANCE: f64 = 1e-5;
 
         if (sum - 1.0).abs() > TOLERANCE {
             // The sum of probabilities does not equal 1 within the tolerance
-            return Err(anyhow!("Probabilities do not sum up to 1.0"));
+            return Err(Error::InvalidArgument(
+                "Probabilities do not sum up to 1.0".to_string(),
+            ));
         }
 
-        let mut rng = rand::thread_rng();
         let mut acc = 0.0;
         let r: f64 = rng.gen(); // generates a float between 0.0 and 1.0
         for (ind, &prob) in probs.iter().enumerate() {
@@ -28,16 +41,24 @@ impl Pattern {
         }
 
         // This point should not be reached if the distribution is valid
-        Err(anyhow!("Invalid probability distribution"))
+        Err(Error::InvalidArgument(
+            "Invalid probability distribution".to_string(),
+        ))
     }
 
     pub fn random_poisson(lam: f64) -> Result<usize> {
-        let poisson = Poisson::new(lam).map_err(|e| anyhow!("Poisson error: {}", e))?;
-        let mut rng = rand::thread_rng();
-        let sample = poisson.sample(&mut rng) as u64;
+        Pattern::random_poisson_with_rng(lam, &mut rand::thread_rng())
+    }
+
+    pub fn random_poisson_with_rng<R: Rng + ?Sized>(lam: f64, rng: &mut R) -> Result<usize> {
+        let poisson = Poisson::new(lam)
+            .map_err(|e| Error::InvalidArgument(format!("Poisson error: {}", e)))?;
+        let sample = poisson.sample(rng) as u64;
 
         if sample > usize::MAX as u64 {
-            return Err(anyhow!("Sampled value is too large for usize"));
+            return Err(Error::InvalidArgument(
+                "Sampled value is too large for usize".to_string(),
+            ));
         }
 
         Ok(sample as usize)
@@ -51,8 +72,7 @@ impl Pattern {
     }
 
     pub fn blend_colors(rgb1: (u8, u8, u8), rgb2: (u8, u8, u8), prop: f64) -> (u8, u8, u8) {
-        let blend =
-            |c1, c2| ((c1 as f64 * (1.0 - prop) + c2 as f64 * prop).clamp(0.0, 255.0) as u8);
+        let blend = |c1, c2| (c1 as f64 * (1.0 - prop) + c2 as f64 * prop).clamp(0.0, 255.0) as u8;
         let blended_r = blend(rgb1.0, rgb2.0);
         let blended_g = blend(rgb1.1, rgb2.1);
         let blended_b = blend(rgb1.2, rgb2.2);
@@ -60,7 +80,10 @@ impl Pattern {
     }
 
     pub fn random_color() -> (u8, u8, u8) {
-        let mut rng = rand::thread_rng();
+        Pattern::random_color_with_rng(&mut rand::thread_rng())
+    }
+
+    pub fn random_color_with_rng<R: Rng + ?Sized>(rng: &mut R) -> (u8, u8, u8) {
         let r = rng.gen_range(0..=255);
         let g = rng.gen_range(0..=255);
         let b = rng.gen_range(0..=255);
@@ -74,41 +97,73 @@ impl Pattern {
         light: Option<(f64, f64)>,
         led_color: &'a LedColor, // Use explicit lifetime 'a
     ) -> Result<Box<dyn Fn() -> Result<(u8, u8, u8)> + 'a>> {
-        // Helper to generate a random value within a given range or default to the full range if None
-        let random_in_range = |range_option: Option<(f64, f64)>| -> f64 {
-            let mut rng = rand::thread_rng();
-            match range_option {
-                Some((start, end)) => rng.gen_range(start..=end),
-                None => rng.gen(),
-            }
-        };
+        Ok(Box::new(move || {
+            Ok(Pattern::random_hsl_color(
+                hue,
+                sat,
+                light,
+                led_color,
+                &mut rand::thread_rng(),
+            ))
+        }))
+    }
 
+    /// Like [Self::random_hsl_color_func], drawing from `rng`.
+    #[allow(clippy::type_complexity)]
+    pub fn random_hsl_color_func_with_rng<'a, R: Rng + ?Sized>(
+        hue: Option<(f64, f64)>,
+        sat: Option<(f64, f64)>,
+        light: Option<(f64, f64)>,
+        led_color: &'a LedColor,
+        rng: &'a mut R,
+    ) -> Result<Box<dyn FnMut() -> Result<(u8, u8, u8)> + 'a>> {
         Ok(Box::new(move || {
-            let h = random_in_range(hue.or(Some((0.0, 1.0))));
-            let s = random_in_range(sat.or(Some((0.0, 1.0))));
-            let l = random_in_range(light.or(Some((0.0, 1.0))));
-            // Use the provided LedColor instance to convert HSL to RGB
-            Ok(led_color.hsl_color(h, s, l))
+            Ok(Pattern::random_hsl_color(hue, sat, light, led_color, rng))
         }))
     }
 
+    fn random_hsl_color<R: Rng + ?Sized>(
+        hue: Option<(f64, f64)>,
+        sat: Option<(f64, f64)>,
+        light: Option<(f64, f64)>,
+        led_color: &LedColor,
+        rng: &mut R,
+    ) -> (u8, u8, u8) {
+        // A random value within a given range, or within the full range if None
+        let mut random_in_range = |range: Option<(f64, f64)>| -> f64 {
+            let (start, end) = range.unwrap_or((0.0, 1.0));
+            rng.gen_range(start..=end)
+        };
+        let h = random_in_range(hue);
+        let s = random_in_range(sat);
+        let l = random_in_range(light);
+        // Use the provided LedColor instance to convert HSL to RGB
+        led_color.hsl_color(h, s, l)
+    }
+
     pub fn sprinkle_pattern(
         &self,
         pat: &mut [(u8, u8, u8)],
         rgblst: &[(u8, u8, u8)],
         freq: f64,
     ) -> Result<()> {
-        let n = Pattern::random_poisson(freq)?;
-        let mut rng = rand::thread_rng();
+        self.sprinkle_pattern_with_rng(pat, rgblst, freq, &mut rand::thread_rng())
+    }
+
+    pub fn sprinkle_pattern_with_rng<R: Rng + ?Sized>(
+        &self,
+        pat: &mut [(u8, u8, u8)],
+        rgblst: &[(u8, u8, u8)],
+        freq: f64,
+        rng: &mut R,
+    ) -> Result<()> {
+        let n = Pattern::random_poisson_with_rng(freq, rng)?;
         let leds = (0..pat.len()).collect::<Vec<_>>();
-        let inds = leds
-            .choose_multiple(&mut rng, n)
-            .cloned()
-            .collect::<Vec<_>>();
+        let inds = leds.choose_multiple(rng, n).cloned().collect::<Vec<_>>();
         for &i in &inds {
             let &color = rgblst
-                .choose(&mut rng)
-                .ok_or_else(|| anyhow!("Color list is empty"))?;
+                .choose(rng)
+                .ok_or_else(|| Error::InvalidArgument("Color list is empty".to_string()))?;
             pat[i] = color;
         }
         Ok(())
@@ -140,11 +195,24 @@ impl Pattern {
         rgblst: &[(u8, u8, u8)],
         probs: Option<&[f64]>,
     ) -> Result<Vec<(u8, u8, u8)>> {
-        let mut rng = rand::thread_rng();
+        Pattern::make_random_select_color_pattern_with_rng(
+            leds,
+            rgblst,
+            probs,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    pub fn make_random_select_color_pattern_with_rng<R: Rng + ?Sized>(
+        leds: usize,
+        rgblst: &[(u8, u8, u8)],
+        probs: Option<&[f64]>,
+        rng: &mut R,
+    ) -> Result<Vec<(u8, u8, u8)>> {
         let pattern = (0..leds)
             .map(|_| {
                 if let Some(probs) = probs {
-                    let ind = Pattern::random_discrete(probs)?;
+                    let ind = Pattern::random_discrete_with_rng(probs, rng)?;
                     Ok(rgblst[ind])
                 } else {
                     let ind = rng.gen_range(0..rgblst.len());
@@ -160,7 +228,15 @@ impl Pattern {
         rgb1: (u8, u8, u8),
         rgb2: (u8, u8, u8),
     ) -> Vec<(u8, u8, u8)> {
-        let mut rng = rand::thread_rng();
+        Pattern::make_random_blend_color_pattern_with_rng(leds, rgb1, rgb2, &mut rand::thread_rng())
+    }
+
+    pub fn make_random_blend_color_pattern_with_rng<R: Rng + ?Sized>(
+        leds: usize,
+        rgb1: (u8, u8, u8),
+        rgb2: (u8, u8, u8),
+        rng: &mut R,
+    ) -> Vec<(u8, u8, u8)> {
         (0..leds)
             .map(|_| {
                 let prop = rng.gen::<f64>();
@@ -174,7 +250,20 @@ impl Pattern {
         lightness: f64,
         led_color: &LedColor,
     ) -> Vec<(u8, u8, u8)> {
-        let mut rng = rand::thread_rng();
+        Pattern::make_random_colors_pattern_with_rng(
+            leds,
+            lightness,
+            led_color,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    pub fn make_random_colors_pattern_with_rng<R: Rng + ?Sized>(
+        leds: usize,
+        lightness: f64,
+        led_color: &LedColor,
+        rng: &mut R,
+    ) -> Vec<(u8, u8, u8)> {
         (0..leds)
             .map(|_| {
                 let hue = rng.gen::<f64>();
@@ -188,7 +277,20 @@ impl Pattern {
         hue: f64,
         led_color: &LedColor,
     ) -> Vec<(u8, u8, u8)> {
-        let mut rng = rand::thread_rng();
+        Pattern::make_random_lightness_pattern_with_rng(
+            leds,
+            hue,
+            led_color,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    pub fn make_random_lightness_pattern_with_rng<R: Rng + ?Sized>(
+        leds: usize,
+        hue: f64,
+        led_color: &LedColor,
+        rng: &mut R,
+    ) -> Vec<(u8, u8, u8)> {
         (0..leds)
             .map(|_| {
                 let lightness = rng.gen::<f64>() * 2.0 - 1.0;
@@ -204,11 +306,73 @@ impl Pattern {
         light: Option<(f64, f64)>,
         led_color: &LedColor, // Pass a reference to LedColor
     ) -> Result<Vec<(u8, u8, u8)>> {
-        // Now we pass the reference directly without dereferencing
-        let color_func = Pattern::random_hsl_color_func(hue, sat, light, led_color)?;
+        Pattern::make_random_hsl_pattern_with_rng(
+            leds,
+            hue,
+            sat,
+            light,
+            led_color,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    pub fn make_random_hsl_pattern_with_rng<R: Rng + ?Sized>(
+        leds: usize,
+        hue: Option<(f64, f64)>,
+        sat: Option<(f64, f64)>,
+        light: Option<(f64, f64)>,
+        led_color: &LedColor,
+        rng: &mut R,
+    ) -> Result<Vec<(u8, u8, u8)>> {
+        let mut color_func =
+            Pattern::random_hsl_color_func_with_rng(hue, sat, light, led_color, rng)?;
         let pattern = (0..leds)
             .map(|_| color_func())
             .collect::<Result<Vec<_>>>()?;
         Ok(pattern)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_seeded_patterns_repeat() {
+        let led_color = LedColor::new();
+        let pattern = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut pattern =
+                Pattern::make_random_colors_pattern_with_rng(50, 0.0, &led_color, &mut rng);
+            Pattern
+                .sprinkle_pattern_with_rng(&mut pattern, &[(255, 255, 255)], 5.0, &mut rng)
+                .unwrap();
+            pattern.extend(
+                Pattern::make_random_hsl_pattern_with_rng(
+                    10,
+                    Some((0.2, 0.4)),
+                    None,
+                    None,
+                    &led_color,
+                    &mut rng,
+                )
+                .unwrap(),
+            );
+            pattern
+        };
+        assert_eq!(pattern(7), pattern(7));
+        assert_ne!(pattern(7), pattern(8));
+    }
+
+    #[test]
+    fn test_random_discrete() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            Pattern::random_discrete_with_rng(&[0.0, 1.0], &mut rng).unwrap(),
+            1
+        );
+        assert!(Pattern::random_discrete_with_rng(&[0.5, 0.2], &mut rng).is_err());
+    }
+}
@@ -0,0 +1,478 @@
+/*!
+Animations built from the generators of [Pattern] and [ColorMeander], see [Animation].
+
+Static patterns are animated by moving them along the LEDs, or by generating new random
+patterns at an interval. The random animations make the same frames for the same seed, see
+their `with_seed` methods.
+ */
+
+use log::warn;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::error::{Error, Result};
+use crate::led::animation::Animation;
+use crate::led::color_meander::{ColorMeander, MeanderStyle};
+use crate::led::led_color::LedColor;
+use crate::led::pattern::Pattern;
+use clap::ValueEnum;
+
+/// A color spectrum over all LEDs, turning around the LEDs.
+#[derive(Debug, Clone)]
+pub struct RotatingSpectrum {
+    /// The lightness of the colors, from -1 for black over 0 for full colors to 1 for white.
+    pub lightness: f64,
+    /// Seconds for the spectrum to move along all LEDs, negative to move the other way and 0
+    /// to stand still.
+    pub period: f64,
+    pub led_color: LedColor,
+}
+
+impl RotatingSpectrum {
+    pub fn new(period: f64) -> RotatingSpectrum {
+        RotatingSpectrum {
+            lightness: 0.0,
+            period,
+            led_color: LedColor::new(),
+        }
+    }
+}
+
+impl Animation for RotatingSpectrum {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let offset = if self.period == 0.0 {
+            0
+        } else {
+            ((time / self.period).rem_euclid(1.0) * led_count as f64) as usize
+        };
+        Pattern::make_color_spectrum_pattern(led_count, offset, self.lightness, &self.led_color)
+    }
+}
+
+/// Colors repeating along the LEDs, moving by one LED at an interval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternating {
+    colors: Vec<(u8, u8, u8)>,
+    /// Seconds between moving the pattern by one LED, 0 to stand still.
+    pub step_interval: f64,
+}
+
+impl Alternating {
+    /**
+    Repeats `colors` along the LEDs.
+
+    # Errors
+    [Error::InvalidArgument] if `colors` is empty.
+     */
+    pub fn new(colors: Vec<(u8, u8, u8)>, step_interval: f64) -> Result<Alternating> {
+        check_colors(&colors)?;
+        Ok(Alternating {
+            colors,
+            step_interval,
+        })
+    }
+}
+
+impl Animation for Alternating {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let mut pattern = Pattern::make_alternating_color_pattern(led_count, &self.colors);
+        if self.step_interval > 0.0 && led_count > 0 {
+            let steps = (time.max(0.0) / self.step_interval) as usize;
+            pattern.rotate_right(steps % led_count);
+        }
+        pattern
+    }
+}
+
+/// Generates one random pattern for a number of LEDs.
+type PatternGenerator = Box<dyn FnMut(usize, &mut StdRng) -> Result<Vec<(u8, u8, u8)>> + Send>;
+
+/// A random pattern, replaced by a new one at an interval.
+pub struct RandomPattern {
+    generate: PatternGenerator,
+    /// Seconds between new patterns, `None` to keep the first one.
+    pub interval: Option<f64>,
+    rng: StdRng,
+    slot: Option<u64>,
+    current: Vec<(u8, u8, u8)>,
+}
+
+impl RandomPattern {
+    /// Random blends of two colors, see [Pattern::make_random_blend_color_pattern].
+    pub fn blend(rgb1: (u8, u8, u8), rgb2: (u8, u8, u8), interval: Option<f64>) -> RandomPattern {
+        RandomPattern::new(
+            Box::new(move |leds, rng| {
+                Ok(Pattern::make_random_blend_color_pattern_with_rng(
+                    leds, rgb1, rgb2, rng,
+                ))
+            }),
+            interval,
+        )
+    }
+
+    /**
+    Random colors in ranges of hue, saturation and lightness, see
+    [Pattern::make_random_hsl_pattern]. Hue and saturation are from 0 to 1, lightness from -1
+    to 1; missing ranges are the full range.
+
+    # Errors
+    [Error::InvalidArgument] if a range is reversed or out of bounds.
+     */
+    pub fn hsl(
+        hue: Option<(f64, f64)>,
+        sat: Option<(f64, f64)>,
+        light: Option<(f64, f64)>,
+        led_color: LedColor,
+        interval: Option<f64>,
+    ) -> Result<RandomPattern> {
+        check_range("hue", hue, 0.0)?;
+        check_range("saturation", sat, 0.0)?;
+        check_range("lightness", light, -1.0)?;
+        Ok(RandomPattern::new(
+            Box::new(move |leds, rng| {
+                Pattern::make_random_hsl_pattern_with_rng(leds, hue, sat, light, &led_color, rng)
+            }),
+            interval,
+        ))
+    }
+
+    /// Makes the same patterns for the same `seed`.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    fn new(generate: PatternGenerator, interval: Option<f64>) -> RandomPattern {
+        RandomPattern {
+            generate,
+            interval,
+            rng: StdRng::from_entropy(),
+            slot: None,
+            current: Vec::new(),
+        }
+    }
+}
+
+impl Animation for RandomPattern {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let slot = match self.interval {
+            Some(interval) if interval > 0.0 => (time.max(0.0) / interval) as u64,
+            _ => 0,
+        };
+        if self.slot != Some(slot) || self.current.len() != led_count {
+            self.current = (self.generate)(led_count, &mut self.rng).unwrap_or_else(|e| {
+                warn!("Failed to generate a pattern: {}", e);
+                vec![(0, 0, 0); led_count]
+            });
+            self.slot = Some(slot);
+        }
+        self.current.clone()
+    }
+}
+
+/**
+Random LEDs lighting up at an interval and fading back to the background until the next
+sprinkle, see [Pattern::sprinkle_pattern].
+ */
+#[derive(Debug, Clone)]
+pub struct Sprinkles {
+    colors: Vec<(u8, u8, u8)>,
+    frequency: f64,
+    pub background: (u8, u8, u8),
+    /// Seconds between sprinkles.
+    pub interval: f64,
+    rng: StdRng,
+    slot: Option<u64>,
+    sprinkled: Vec<(u8, u8, u8)>,
+}
+
+impl Sprinkles {
+    /**
+    Sprinkles of `colors` on about `frequency` LEDs, a Poisson distributed number.
+
+    # Errors
+    [Error::InvalidArgument] if `colors` is empty or `frequency` isn't positive.
+     */
+    pub fn new(colors: Vec<(u8, u8, u8)>, frequency: f64, interval: f64) -> Result<Sprinkles> {
+        check_colors(&colors)?;
+        Pattern::random_poisson(frequency)?;
+        Ok(Sprinkles {
+            colors,
+            frequency,
+            background: (0, 0, 0),
+            interval,
+            rng: StdRng::from_entropy(),
+            slot: None,
+            sprinkled: Vec::new(),
+        })
+    }
+
+    /// Sprinkles the same LEDs in the same colors for the same `seed`.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+}
+
+impl Animation for Sprinkles {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let interval = self.interval.max(f64::EPSILON);
+        let position = time.max(0.0) / interval;
+        let slot = position as u64;
+        if self.slot != Some(slot) || self.sprinkled.len() != led_count {
+            self.sprinkled = vec![self.background; led_count];
+            if let Err(e) = Pattern.sprinkle_pattern_with_rng(
+                &mut self.sprinkled,
+                &self.colors,
+                self.frequency,
+                &mut self.rng,
+            ) {
+                warn!("Failed to sprinkle: {}", e);
+            }
+            self.slot = Some(slot);
+        }
+        let fade = position - slot as f64;
+        self.sprinkled
+            .iter()
+            .map(|&color| Pattern::blend_colors(color, self.background, fade))
+            .collect()
+    }
+}
+
+/// How [MeanderDrift] spreads the colors of the meander over the LEDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum MeanderMode {
+    /// All LEDs show the color, see [ColorMeander::get].
+    #[default]
+    Solid,
+    /// The LEDs alternate between the color and its complement, see [ColorMeander::get_compl].
+    Alternating,
+    /// The LEDs blend from the color to its complement and back.
+    Gradient,
+}
+
+/// Where [MeanderDrift] starts in color space, the center.
+const MEANDER_START: (f64, f64, f64) = (0.0, 0.0, 0.0);
+
+/// Colors drifting through color space with a [ColorMeander].
+pub struct MeanderDrift {
+    meander: ColorMeander,
+    style: MeanderStyle,
+    speed: f64,
+    noise: f64,
+    pub mode: MeanderMode,
+    /// Meander steps per second.
+    pub steps_per_second: f64,
+    pub led_color: LedColor,
+    steps: usize,
+}
+
+impl MeanderDrift {
+    /**
+    A meander starting at the center of the color space, see [ColorMeander::new].
+
+    # Errors
+    [Error::InvalidArgument] if `noise` isn't positive.
+     */
+    pub fn new(
+        style: MeanderStyle,
+        speed: f64,
+        noise: f64,
+        mode: MeanderMode,
+        steps_per_second: f64,
+    ) -> Result<MeanderDrift> {
+        if noise.is_nan() || noise <= 0.0 {
+            return Err(Error::InvalidArgument(format!(
+                "The meander noise must be positive, not {}",
+                noise
+            )));
+        }
+        Ok(MeanderDrift {
+            meander: ColorMeander::new(style, speed, noise, MEANDER_START),
+            style,
+            speed,
+            noise,
+            mode,
+            steps_per_second,
+            led_color: LedColor::new(),
+            steps: 0,
+        })
+    }
+
+    /// Restarts the meander, to take the same steps for the same `seed`.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.meander =
+            ColorMeander::with_seed(self.style, self.speed, self.noise, MEANDER_START, seed);
+        self.steps = 0;
+        self
+    }
+}
+
+impl Animation for MeanderDrift {
+    fn frame(&mut self, time: f64, led_count: usize) -> Vec<(u8, u8, u8)> {
+        let target = (time.max(0.0) * self.steps_per_second) as usize;
+        while self.steps < target {
+            self.meander.step();
+            self.steps += 1;
+        }
+        let color = self.meander.get(&self.led_color);
+        let complement = self.meander.get_compl(&self.led_color);
+        match self.mode {
+            MeanderMode::Solid => vec![color; led_count],
+            MeanderMode::Alternating => (0..led_count)
+                .map(|i| if i % 2 == 0 { color } else { complement })
+                .collect(),
+            MeanderMode::Gradient => (0..led_count)
+                .map(|i| {
+                    let position = i as f64 / led_count as f64;
+                    let amount = 1.0 - (2.0 * position - 1.0).abs();
+                    Pattern::blend_colors(color, complement, amount)
+                })
+                .collect(),
+        }
+    }
+}
+
+fn check_colors(colors: &[(u8, u8, u8)]) -> Result<()> {
+    if colors.is_empty() {
+        return Err(Error::InvalidArgument(
+            "At least one color is needed".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that a range is within `min` to 1 and not reversed.
+fn check_range(name: &str, range: Option<(f64, f64)>, min: f64) -> Result<()> {
+    match range {
+        Some((start, end)) if !(min <= start && start <= end && end <= 1.0) => {
+            Err(Error::InvalidArgument(format!(
+                "Invalid {} range {} to {}, expected {} to 1",
+                name, start, end, min
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotating_spectrum() {
+        let mut spectrum = RotatingSpectrum::new(10.0);
+        let start = spectrum.frame(0.0, 10);
+        // A tenth of the period moves the spectrum by one LED
+        let moved = spectrum.frame(1.0, 10);
+        assert_eq!(moved[0], start[1]);
+        assert_eq!(moved[9], start[0]);
+        assert_eq!(RotatingSpectrum::new(0.0).frame(3.0, 10), start);
+    }
+
+    #[test]
+    fn test_alternating() {
+        let (red, blue) = ((255, 0, 0), (0, 0, 255));
+        let mut alternating = Alternating::new(vec![red, blue], 0.5).unwrap();
+        assert_eq!(alternating.frame(0.0, 3), vec![red, blue, red]);
+        assert_eq!(alternating.frame(0.6, 3), vec![red, red, blue]);
+        assert!(Alternating::new(Vec::new(), 0.5).is_err());
+    }
+
+    #[test]
+    fn test_random_pattern_interval() {
+        let mut pattern = RandomPattern::blend((0, 0, 0), (255, 255, 255), Some(1.0));
+        let first = pattern.frame(0.0, 50);
+        assert_eq!(pattern.frame(0.9, 50), first);
+        assert_ne!(pattern.frame(1.1, 50), first);
+
+        let mut hsl = RandomPattern::hsl(
+            Some((0.0, 0.0)),
+            Some((1.0, 1.0)),
+            Some((0.0, 0.0)),
+            LedColor::new(),
+            None,
+        )
+        .unwrap();
+        let red = LedColor::new().hsl_color(0.0, 1.0, 0.0);
+        assert_eq!(hsl.frame(5.0, 4), vec![red; 4]);
+        assert!(RandomPattern::hsl(Some((0.8, 0.2)), None, None, LedColor::new(), None).is_err());
+    }
+
+    #[test]
+    fn test_sprinkles_fade() {
+        let white = (255, 255, 255);
+        let mut sprinkles = Sprinkles::new(vec![white], 3.0, 1.0).unwrap();
+        let lit = |frame: &[(u8, u8, u8)]| frame.iter().filter(|&&c| c != (0, 0, 0)).count();
+
+        let mut total = 0;
+        for round in 0..20 {
+            let start = sprinkles.frame(round as f64, 100);
+            assert!(start.iter().all(|&c| c == white || c == (0, 0, 0)));
+            total += lit(&start);
+            // Halfway to the next sprinkle, the same LEDs are half as bright
+            let half = sprinkles.frame(round as f64 + 0.5, 100);
+            assert_eq!(lit(&half), lit(&start));
+            assert!(half.iter().all(|&c| c == (127, 127, 127) || c == (0, 0, 0)));
+        }
+        assert!((20..=120).contains(&total), "{}", total);
+        assert!(Sprinkles::new(vec![white], 0.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_meander_drift() {
+        let mut drift = MeanderDrift::new(
+            MeanderStyle::Surface,
+            0.1,
+            0.1,
+            MeanderMode::Alternating,
+            10.0,
+        )
+        .unwrap();
+        let start = drift.frame(0.0, 4);
+        assert_eq!(start[0], start[2]);
+        assert_eq!(start[1], drift.meander.get_compl(&drift.led_color));
+
+        drift.frame(1.0, 4);
+        assert_eq!(drift.steps, 10);
+        assert!(
+            MeanderDrift::new(MeanderStyle::Sphere, 0.1, 0.0, MeanderMode::Solid, 1.0).is_err()
+        );
+    }
+
+    #[test]
+    fn test_seeded_animations_repeat() {
+        fn frames(animation: &mut dyn Animation) -> Vec<Vec<(u8, u8, u8)>> {
+            (0..20)
+                .map(|i| animation.frame(i as f64 * 0.25, 30))
+                .collect()
+        }
+        let sprinkles = || {
+            Sprinkles::new(vec![(255, 0, 0), (0, 0, 255)], 4.0, 0.5)
+                .unwrap()
+                .with_seed(1)
+        };
+        assert_eq!(frames(&mut sprinkles()), frames(&mut sprinkles()));
+
+        let hsl = || {
+            RandomPattern::hsl(None, None, None, LedColor::new(), Some(1.0))
+                .unwrap()
+                .with_seed(2)
+        };
+        assert_eq!(frames(&mut hsl()), frames(&mut hsl()));
+        assert_ne!(frames(&mut hsl()), frames(&mut hsl().with_seed(3)));
+
+        let meander = || {
+            MeanderDrift::new(
+                MeanderStyle::Cylinder,
+                0.05,
+                0.2,
+                MeanderMode::Gradient,
+                20.0,
+            )
+            .unwrap()
+            .with_seed(4)
+        };
+        assert_eq!(frames(&mut meander()), frames(&mut meander()));
+    }
+}
@@ -0,0 +1,342 @@
+/*!
+Conversion between RGB colors and the byte layout of the [LedProfile]s.
+
+RGBW devices have a fourth, white LED per light. How much of a color is shown by the white LED is
+decided by a [WhiteExtraction] strategy, which must also be used to decode the bytes again.
+ */
+
+use std::str::FromStr;
+
+use crate::control_interface::LedProfile;
+use crate::error::{Error, Result};
+
+/// Rec. 709 weights of red, green and blue in the perceived luminance.
+const LUMINANCE_WEIGHTS: (f64, f64, f64) = (0.2126, 0.7152, 0.0722);
+
+/// How the white channel of RGBW LEDs is derived from an RGB color.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WhiteExtraction {
+    /**
+    Moves the part shared by all channels to the white LED: `w = min(r, g, b)` is subtracted from
+    each channel. Keeps the color exact for a neutral white LED.
+     */
+    #[default]
+    MinSubtraction,
+    /**
+    Drives the white LED with `min(r, g, b)` like [Self::MinSubtraction], but scales the color
+    channels instead of subtracting from them, so the total luminance stays the same and the
+    proportions of the channels are kept. Gives smoother gradients at the cost of some saturation.
+     */
+    LuminancePreserving,
+    /**
+    Models the white LED as a light of the given color temperature in Kelvin, e.g. `2700.0` for
+    warm white LEDs. Only as much white is used as the color contains of that tint, so warm white
+    LEDs don't turn neutral or cool colors yellow.
+     */
+    CalibratedWhite { kelvin: f64 },
+}
+
+impl WhiteExtraction {
+    /// Splits an RGB color into the red, green, blue and white channels.
+    pub fn to_rgbw(&self, (r, g, b): (u8, u8, u8)) -> (u8, u8, u8, u8) {
+        let w = r.min(g).min(b);
+        match self {
+            WhiteExtraction::MinSubtraction => (r - w, g - w, b - w, w),
+            WhiteExtraction::LuminancePreserving => {
+                let luminance = luminance((r, g, b));
+                if w == 0 || luminance == 0.0 {
+                    return (r, g, b, w);
+                }
+                let scale = (luminance - w as f64) / luminance;
+                (
+                    to_channel(r as f64 * scale),
+                    to_channel(g as f64 * scale),
+                    to_channel(b as f64 * scale),
+                    w,
+                )
+            }
+            WhiteExtraction::CalibratedWhite { kelvin } => {
+                let tint = white_tint(*kelvin);
+                // The most white that fits into every channel
+                let w = [(r, tint.0), (g, tint.1), (b, tint.2)]
+                    .iter()
+                    .map(|&(channel, tint)| channel as f64 / tint)
+                    .fold(255.0, f64::min);
+                let w = w.floor();
+                (
+                    to_channel(r as f64 - w * tint.0),
+                    to_channel(g as f64 - w * tint.1),
+                    to_channel(b as f64 - w * tint.2),
+                    w as u8,
+                )
+            }
+        }
+    }
+
+    /// Combines the red, green, blue and white channels into an RGB color, saturating at 255.
+    pub fn to_rgb(&self, (r, g, b, w): (u8, u8, u8, u8)) -> (u8, u8, u8) {
+        match self {
+            WhiteExtraction::MinSubtraction => (
+                r.saturating_add(w),
+                g.saturating_add(w),
+                b.saturating_add(w),
+            ),
+            WhiteExtraction::LuminancePreserving => {
+                let luminance = luminance((r, g, b));
+                if w == 0 {
+                    return (r, g, b);
+                }
+                if luminance == 0.0 {
+                    return (w, w, w);
+                }
+                let scale = (luminance + w as f64) / luminance;
+                (
+                    to_channel(r as f64 * scale),
+                    to_channel(g as f64 * scale),
+                    to_channel(b as f64 * scale),
+                )
+            }
+            WhiteExtraction::CalibratedWhite { kelvin } => {
+                let tint = white_tint(*kelvin);
+                let w = w as f64;
+                (
+                    to_channel(r as f64 + w * tint.0),
+                    to_channel(g as f64 + w * tint.1),
+                    to_channel(b as f64 + w * tint.2),
+                )
+            }
+        }
+    }
+}
+
+/// Parses `min`, `luminance`, or a color temperature like `2700K` for [WhiteExtraction::CalibratedWhite].
+impl FromStr for WhiteExtraction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "min" => Ok(WhiteExtraction::MinSubtraction),
+            "luminance" => Ok(WhiteExtraction::LuminancePreserving),
+            other => other
+                .trim_end_matches('k')
+                .parse::<f64>()
+                .ok()
+                .filter(|kelvin| (1000.0..=40000.0).contains(kelvin))
+                .map(|kelvin| WhiteExtraction::CalibratedWhite { kelvin })
+                .ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "Invalid white extraction: {}, expected min, luminance or a color temperature like 2700K",
+                        s
+                    ))
+                }),
+        }
+    }
+}
+
+/// Packs RGB colors into the byte layout of `led_profile`, appending to `data`.
+pub fn pack_frame(
+    frame: &[(u8, u8, u8)],
+    led_profile: LedProfile,
+    white_extraction: WhiteExtraction,
+    data: &mut Vec<u8>,
+) {
+    data.reserve(frame.len() * led_profile.bytes_per_led());
+    for &color in frame {
+        match led_profile {
+            LedProfile::RGB => data.extend_from_slice(&[color.0, color.1, color.2]),
+            LedProfile::RGBW => {
+                let (r, g, b, w) = white_extraction.to_rgbw(color);
+                data.extend_from_slice(&[r, g, b, w]);
+            }
+        }
+    }
+}
+
+/// Packs frames of RGB colors into the byte layout of `led_profile`, one frame after the other.
+pub fn pack_frames(
+    frames: &[Vec<(u8, u8, u8)>],
+    led_profile: LedProfile,
+    white_extraction: WhiteExtraction,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    for frame in frames {
+        pack_frame(frame, led_profile, white_extraction, &mut data);
+    }
+    data
+}
+
+/**
+Unpacks bytes in the layout of `led_profile` into RGB colors.
+
+Trailing bytes not making up a whole LED are ignored.
+ */
+pub fn unpack_frame(
+    data: &[u8],
+    led_profile: LedProfile,
+    white_extraction: WhiteExtraction,
+) -> Vec<(u8, u8, u8)> {
+    data.chunks_exact(led_profile.bytes_per_led())
+        .map(|chunk| match led_profile {
+            LedProfile::RGB => (chunk[0], chunk[1], chunk[2]),
+            LedProfile::RGBW => white_extraction.to_rgb((chunk[0], chunk[1], chunk[2], chunk[3])),
+        })
+        .collect()
+}
+
+fn luminance((r, g, b): (u8, u8, u8)) -> f64 {
+    LUMINANCE_WEIGHTS.0 * r as f64 + LUMINANCE_WEIGHTS.1 * g as f64 + LUMINANCE_WEIGHTS.2 * b as f64
+}
+
+fn to_channel(value: f64) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+/**
+The color of a white LED at the color temperature `kelvin`, per channel in 0..=1 with the
+brightest channel at 1.
+
+Uses Tanner Helland's approximation of the black body colors, valid from 1000 K to 40000 K.
+ */
+fn white_tint(kelvin: f64) -> (f64, f64, f64) {
+    let temperature = kelvin.clamp(1000.0, 40000.0) / 100.0;
+    let red = if temperature <= 66.0 {
+        255.0
+    } else {
+        329.698_727_446 * (temperature - 60.0).powf(-0.133_204_759_2)
+    };
+    let green = if temperature <= 66.0 {
+        99.470_802_586_1 * temperature.ln() - 161.119_568_166_1
+    } else {
+        288.122_169_528_3 * (temperature - 60.0).powf(-0.075_514_849_2)
+    };
+    let blue = if temperature >= 66.0 {
+        255.0
+    } else if temperature <= 19.0 {
+        0.0
+    } else {
+        138.517_731_223_1 * (temperature - 10.0).ln() - 305.044_792_730_7
+    };
+
+    let (red, green, blue) = (
+        red.clamp(0.0, 255.0),
+        green.clamp(0.0, 255.0),
+        blue.clamp(0.0, 255.0),
+    );
+    let max = red.max(green).max(blue);
+    // A channel of 0 would allow unlimited white, keep a tiny share instead
+    let share = |channel: f64| (channel / max).max(1.0 / 255.0);
+    (share(red), share(green), share(blue))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRATEGIES: [WhiteExtraction; 3] = [
+        WhiteExtraction::MinSubtraction,
+        WhiteExtraction::LuminancePreserving,
+        WhiteExtraction::CalibratedWhite { kelvin: 2700.0 },
+    ];
+
+    #[test]
+    fn test_min_subtraction() {
+        let white = WhiteExtraction::MinSubtraction;
+        assert_eq!(white.to_rgbw((200, 100, 50)), (150, 50, 0, 50));
+        assert_eq!(white.to_rgb((150, 50, 0, 50)), (200, 100, 50));
+    }
+
+    #[test]
+    fn test_decoding_saturates() {
+        for white in STRATEGIES {
+            assert_eq!(white.to_rgb((255, 255, 255, 255)), (255, 255, 255));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_is_close() {
+        let colors = [
+            (0, 0, 0),
+            (255, 255, 255),
+            (255, 0, 0),
+            (200, 100, 100),
+            (30, 60, 90),
+            (255, 180, 120),
+        ];
+        for white in STRATEGIES {
+            for color in colors {
+                let decoded = white.to_rgb(white.to_rgbw(color));
+                let distance = [
+                    (decoded.0, color.0),
+                    (decoded.1, color.1),
+                    (decoded.2, color.2),
+                ]
+                .iter()
+                .map(|&(a, b)| a.abs_diff(b))
+                .max()
+                .unwrap();
+                assert!(
+                    distance <= 3,
+                    "{:?}: {:?} decoded as {:?}",
+                    white,
+                    color,
+                    decoded
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_luminance_preserving_keeps_proportions() {
+        let (r, g, b, w) = WhiteExtraction::LuminancePreserving.to_rgbw((200, 100, 100));
+        assert_eq!(w, 100);
+        assert_eq!(g, b);
+        assert!(r.abs_diff(2 * g) <= 1);
+    }
+
+    #[test]
+    fn test_calibrated_white_keeps_the_tint() {
+        let warm = WhiteExtraction::CalibratedWhite { kelvin: 2700.0 };
+        // Pure white needs blue and green on top of a warm white LED
+        let (r, g, b, w) = warm.to_rgbw((255, 255, 255));
+        assert_eq!((r, w), (0, 255));
+        assert!(b > g && g > 0);
+        // Warm colors are mostly shown by the warm white LED
+        let (_, _, _, w) = warm.to_rgbw((255, 180, 120));
+        assert!(w > 120);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(
+            "min".parse::<WhiteExtraction>().unwrap(),
+            WhiteExtraction::MinSubtraction
+        );
+        assert_eq!(
+            "2700K".parse::<WhiteExtraction>().unwrap(),
+            WhiteExtraction::CalibratedWhite { kelvin: 2700.0 }
+        );
+        assert!("warm".parse::<WhiteExtraction>().is_err());
+    }
+
+    #[test]
+    fn test_pack_and_unpack() {
+        let frame = [(10, 20, 30), (255, 255, 255)];
+        let rgb = pack_frames(
+            &[frame.to_vec()],
+            LedProfile::RGB,
+            WhiteExtraction::default(),
+        );
+        let rgbw = pack_frames(
+            &[frame.to_vec()],
+            LedProfile::RGBW,
+            WhiteExtraction::default(),
+        );
+
+        assert_eq!(rgb, vec![10, 20, 30, 255, 255, 255]);
+        assert_eq!(rgbw, vec![0, 10, 20, 10, 0, 0, 0, 255]);
+        assert_eq!(
+            unpack_frame(&rgbw, LedProfile::RGBW, WhiteExtraction::default()),
+            frame
+        );
+    }
+}
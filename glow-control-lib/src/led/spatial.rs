@@ -0,0 +1,727 @@
+/*!
+Effects computed from the positions of the LEDs in the layout, see [SpatialEffect].
+
+The effects adapt to the bounding box of the layout they are rendered for, so they work with
+the normalized layouts of the devices as well as with hand-made layouts in other units.
+Every effect is a pure function of the position and the time, so the same effect can be shown
+in real time with [ControlInterface::show_spatial_effect] or rendered into a movie with
+[Movie::from_spatial_effect].
+ */
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+use std::time::Duration;
+
+use palette::{FromColor, Hsl, Srgb};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::control_interface::{Axis, ControlInterface, LedCoordinate};
+use crate::error::Result;
+use crate::led::animation::{Animation, SpatialAnimation};
+use crate::led::pattern::Pattern;
+use crate::util::movie::Movie;
+
+/// A color for every position in the layout, changing over time.
+pub trait SpatialEffect: Send + Sync {
+    /// The color of the LED at `led` after `time` seconds, in a layout spanning `bounds`.
+    fn color_at(&self, led: &LedCoordinate, bounds: &Bounds, time: f64) -> (u8, u8, u8);
+
+    /// The colors of all LEDs after `time` seconds.
+    fn frame(&self, leds: &[LedCoordinate], time: f64) -> Vec<(u8, u8, u8)> {
+        let bounds = Bounds::of(leds);
+        leds.iter()
+            .map(|led| self.color_at(led, &bounds, time))
+            .collect()
+    }
+}
+
+impl<E: SpatialEffect + ?Sized> SpatialEffect for &E {
+    fn color_at(&self, led: &LedCoordinate, bounds: &Bounds, time: f64) -> (u8, u8, u8) {
+        (**self).color_at(led, bounds, time)
+    }
+}
+
+impl<E: SpatialEffect + ?Sized> SpatialEffect for Box<E> {
+    fn color_at(&self, led: &LedCoordinate, bounds: &Bounds, time: f64) -> (u8, u8, u8) {
+        (**self).color_at(led, bounds, time)
+    }
+}
+
+/// The bounding box of a layout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: LedCoordinate,
+    pub max: LedCoordinate,
+}
+
+impl Bounds {
+    /// The bounding box of `leds`, a point at the origin if there are none.
+    pub fn of(leds: &[LedCoordinate]) -> Bounds {
+        let origin = LedCoordinate {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let Some(first) = leds.first() else {
+            return Bounds {
+                min: origin,
+                max: origin,
+            };
+        };
+        leds.iter().fold(
+            Bounds {
+                min: *first,
+                max: *first,
+            },
+            |bounds, led| Bounds {
+                min: LedCoordinate {
+                    x: bounds.min.x.min(led.x),
+                    y: bounds.min.y.min(led.y),
+                    z: bounds.min.z.min(led.z),
+                },
+                max: LedCoordinate {
+                    x: bounds.max.x.max(led.x),
+                    y: bounds.max.y.max(led.y),
+                    z: bounds.max.z.max(led.z),
+                },
+            },
+        )
+    }
+
+    pub fn center(&self) -> LedCoordinate {
+        LedCoordinate {
+            x: (self.min.x + self.max.x) / 2.0,
+            y: (self.min.y + self.max.y) / 2.0,
+            z: (self.min.z + self.max.z) / 2.0,
+        }
+    }
+
+    /// The position of `led` in the box, from 0 to 1 along each axis, 0.5 along flat axes.
+    pub fn normalize(&self, led: &LedCoordinate) -> LedCoordinate {
+        let scale = |value: f64, min: f64, max: f64| {
+            if max > min {
+                (value - min) / (max - min)
+            } else {
+                0.5
+            }
+        };
+        LedCoordinate {
+            x: scale(led.x, self.min.x, self.max.x),
+            y: scale(led.y, self.min.y, self.max.y),
+            z: scale(led.z, self.min.z, self.max.z),
+        }
+    }
+
+    /// The smallest and largest distance along `direction`, a unit vector, of the corners.
+    pub fn extent_along(&self, direction: [f64; 3]) -> (f64, f64) {
+        self.corners()
+            .iter()
+            .map(|corner| dot(corner, direction))
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), along| {
+                (min.min(along), max.max(along))
+            })
+    }
+
+    /// The distance from `point` to the farthest corner of the box.
+    pub fn farthest_corner(&self, point: &LedCoordinate) -> f64 {
+        self.corners()
+            .iter()
+            .map(|corner| distance(corner, point))
+            .fold(0.0, f64::max)
+    }
+
+    fn corners(&self) -> [LedCoordinate; 8] {
+        let (min, max) = (self.min, self.max);
+        let corner = |x: f64, y: f64, z: f64| LedCoordinate { x, y, z };
+        [
+            corner(min.x, min.y, min.z),
+            corner(max.x, min.y, min.z),
+            corner(min.x, max.y, min.z),
+            corner(max.x, max.y, min.z),
+            corner(min.x, min.y, max.z),
+            corner(max.x, min.y, max.z),
+            corner(min.x, max.y, max.z),
+            corner(max.x, max.y, max.z),
+        ]
+    }
+}
+
+/// A band of light sweeping through the layout along a direction, over and over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaneSweep {
+    /// The direction the band moves in, of any length. A zero vector sweeps upwards along `y`.
+    pub direction: [f64; 3],
+    /// Seconds for one sweep through the layout.
+    pub period: f64,
+    /// The thickness of the band, relative to the extent of the layout along the direction.
+    pub width: f64,
+    pub color: (u8, u8, u8),
+    pub background: (u8, u8, u8),
+}
+
+impl PlaneSweep {
+    pub fn new(direction: [f64; 3], color: (u8, u8, u8)) -> PlaneSweep {
+        PlaneSweep {
+            direction,
+            period: 3.0,
+            width: 0.2,
+            color,
+            background: (0, 0, 0),
+        }
+    }
+}
+
+impl SpatialEffect for PlaneSweep {
+    fn color_at(&self, led: &LedCoordinate, bounds: &Bounds, time: f64) -> (u8, u8, u8) {
+        let direction = unit(self.direction).unwrap_or([0.0, 1.0, 0.0]);
+        let (min, max) = bounds.extent_along(direction);
+        let width = (self.width * (max - min)).max(f64::EPSILON);
+        // The band starts and ends outside of the layout, so it enters and leaves smoothly
+        let position = min - width + progress(time, self.period) * (max - min + 2.0 * width);
+        let intensity = 1.0 - (dot(led, direction) - position).abs() / width;
+        Pattern::blend_colors(self.background, self.color, intensity.clamp(0.0, 1.0))
+    }
+}
+
+/// A shell of light growing from a center point until it has passed all LEDs, over and over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpandingSphere {
+    /// The center of the sphere, the center of the layout if `None`.
+    pub center: Option<LedCoordinate>,
+    /// Seconds for the sphere to grow through the layout.
+    pub period: f64,
+    /// The thickness of the shell, relative to the largest distance from the center to the layout.
+    pub thickness: f64,
+    pub color: (u8, u8, u8),
+    pub background: (u8, u8, u8),
+}
+
+impl ExpandingSphere {
+    pub fn new(color: (u8, u8, u8)) -> ExpandingSphere {
+        ExpandingSphere {
+            center: None,
+            period: 2.0,
+            thickness: 0.15,
+            color,
+            background: (0, 0, 0),
+        }
+    }
+}
+
+impl SpatialEffect for ExpandingSphere {
+    fn color_at(&self, led: &LedCoordinate, bounds: &Bounds, time: f64) -> (u8, u8, u8) {
+        let center = self.center.unwrap_or_else(|| bounds.center());
+        let farthest = bounds.farthest_corner(&center);
+        let thickness = (self.thickness * farthest).max(f64::EPSILON);
+        let radius = progress(time, self.period) * (farthest + thickness);
+        let intensity = 1.0 - (distance(led, &center) - radius).abs() / thickness;
+        Pattern::blend_colors(self.background, self.color, intensity.clamp(0.0, 1.0))
+    }
+}
+
+/// Rainbows around an axis through the center of the layout, turning around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RotatingRainbow {
+    pub axis: Axis,
+    /// Seconds for one turn, negative to turn the other way.
+    pub period: f64,
+    /// How many rainbows fit around the axis.
+    pub bands: f64,
+    /// The saturation of the colors, from 0 to 1.
+    pub saturation: f64,
+    /// The lightness of the colors, from 0 to 1.
+    pub lightness: f64,
+}
+
+impl Default for RotatingRainbow {
+    fn default() -> Self {
+        RotatingRainbow {
+            axis: Axis::Y,
+            period: 4.0,
+            bands: 1.0,
+            saturation: 1.0,
+            lightness: 0.5,
+        }
+    }
+}
+
+impl SpatialEffect for RotatingRainbow {
+    fn color_at(&self, led: &LedCoordinate, bounds: &Bounds, time: f64) -> (u8, u8, u8) {
+        let center = bounds.center();
+        let (x, y, z) = (led.x - center.x, led.y - center.y, led.z - center.z);
+        let angle = match self.axis {
+            Axis::X => z.atan2(y),
+            Axis::Y => x.atan2(z),
+            Axis::Z => y.atan2(x),
+        };
+        let turn = if self.period == 0.0 {
+            0.0
+        } else {
+            time / self.period
+        };
+        let hue = (angle / (2.0 * PI) * self.bands - turn).rem_euclid(1.0);
+        hsl_to_rgb(hue, self.saturation, self.lightness)
+    }
+}
+
+/// Colors picked from a gradient by smooth 3D noise drifting through the layout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoiseField {
+    /// The size of the noise features, as the number of features across the layout.
+    pub scale: f64,
+    /// How fast the noise drifts, in features per second.
+    pub speed: f64,
+    /// The gradient the noise picks colors from, from low to high values.
+    pub colors: Vec<(u8, u8, u8)>,
+    noise: PerlinNoise,
+}
+
+impl NoiseField {
+    /// A noise field picking from `colors`, the same for the same `seed`.
+    pub fn new(colors: Vec<(u8, u8, u8)>, seed: u64) -> NoiseField {
+        NoiseField {
+            scale: 2.0,
+            speed: 0.5,
+            colors,
+            noise: PerlinNoise::new(seed),
+        }
+    }
+}
+
+impl SpatialEffect for NoiseField {
+    fn color_at(&self, led: &LedCoordinate, bounds: &Bounds, time: f64) -> (u8, u8, u8) {
+        let position = bounds.normalize(led);
+        let drift = time * self.speed;
+        let value = self.noise.fractal(
+            position.x * self.scale + drift,
+            position.y * self.scale + drift * 0.7,
+            position.z * self.scale - drift * 0.4,
+        );
+        gradient(&self.colors, value)
+    }
+}
+
+/// Flames rising along `y` from the bottom of the layout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fire {
+    /// The size of the flames, as the number of tongues across the layout.
+    pub scale: f64,
+    /// How fast the flames rise, in layout heights per second.
+    pub speed: f64,
+    /// How high the flames reach, relative to the height of the layout.
+    pub height: f64,
+    noise: PerlinNoise,
+}
+
+impl Fire {
+    /// Flames that flicker the same way for the same `seed`.
+    pub fn new(seed: u64) -> Fire {
+        Fire {
+            scale: 4.0,
+            speed: 0.6,
+            height: 0.8,
+            noise: PerlinNoise::new(seed),
+        }
+    }
+}
+
+impl SpatialEffect for Fire {
+    fn color_at(&self, led: &LedCoordinate, bounds: &Bounds, time: f64) -> (u8, u8, u8) {
+        let position = bounds.normalize(led);
+        let flicker = self.noise.fractal(
+            position.x * self.scale,
+            (position.y - time * self.speed) * self.scale,
+            position.z * self.scale + time * 0.3,
+        );
+        let cooling = (1.0 - position.y / self.height.max(f64::EPSILON)).clamp(0.0, 1.0);
+        let heat = (flicker * 1.6 * cooling).clamp(0.0, 1.0);
+        gradient(
+            &[
+                (0, 0, 0),
+                (180, 10, 0),
+                (255, 90, 0),
+                (255, 190, 20),
+                (255, 240, 160),
+            ],
+            heat,
+        )
+    }
+}
+
+/// The density of [Twinkle] at a position normalized to the bounds, see [Bounds::normalize].
+pub type TwinkleDensity = Arc<dyn Fn(&LedCoordinate) -> f64 + Send + Sync>;
+
+/// LEDs lighting up at random, more often where the density is higher.
+#[derive(Clone)]
+pub struct Twinkle {
+    pub color: (u8, u8, u8),
+    pub background: (u8, u8, u8),
+    /// Twinkles per second of an LED where the density is 1.
+    pub rate: f64,
+    /// Seconds a twinkle lasts.
+    pub duration: f64,
+    /**
+    How often LEDs twinkle depending on their position, from 0 for never to 1 for [Self::rate].
+    Gets the position normalized to the bounds of the layout.
+     */
+    pub density: TwinkleDensity,
+    /// The same seed makes the same LEDs twinkle at the same times.
+    pub seed: u64,
+}
+
+impl Twinkle {
+    /// LEDs twinkling everywhere equally often.
+    pub fn new(color: (u8, u8, u8), seed: u64) -> Twinkle {
+        Twinkle {
+            color,
+            background: (0, 0, 0),
+            rate: 0.5,
+            duration: 0.6,
+            density: Arc::new(|_| 1.0),
+            seed,
+        }
+    }
+
+    /// A density rising linearly along `axis`, from `from` at the low end to `to` at the high end.
+    pub fn density_along(axis: Axis, from: f64, to: f64) -> TwinkleDensity {
+        Arc::new(move |position| from + (to - from) * position.along(axis))
+    }
+}
+
+impl SpatialEffect for Twinkle {
+    fn color_at(&self, led: &LedCoordinate, bounds: &Bounds, time: f64) -> (u8, u8, u8) {
+        let duration = self.duration.max(f64::EPSILON);
+        let led_hash = hash_position(led, self.seed);
+        // Each LED may twinkle once per slot, the slots of the LEDs are shifted randomly
+        let shifted = time / duration + unit_interval(led_hash);
+        let slot = shifted.floor();
+        let probability =
+            (self.rate * duration * (self.density)(&bounds.normalize(led))).clamp(0.0, 1.0);
+        if unit_interval(mix(led_hash ^ (slot as i64 as u64))) >= probability {
+            return self.background;
+        }
+        let intensity = (PI * (shifted - slot)).sin().powi(2);
+        Pattern::blend_colors(self.background, self.color, intensity)
+    }
+}
+
+impl Movie {
+    /// Renders `duration` of a spatial effect for the LEDs at `leds`.
+    pub fn from_spatial_effect(
+        effect: &dyn SpatialEffect,
+        leds: &[LedCoordinate],
+        duration: Duration,
+        fps: f64,
+    ) -> Movie {
+        let frame_count = (duration.as_secs_f64() * fps).round().max(1.0) as usize;
+        Movie {
+            frames: (0..frame_count)
+                .map(|frame| effect.frame(leds, frame as f64 / fps))
+                .collect(),
+            fps,
+            ..Movie::default()
+        }
+    }
+}
+
+impl ControlInterface {
+    /**
+    Shows a spatial effect in real time, using the layout of the device.
+
+    Runs until `duration` has passed, or forever without one.
+     */
+    pub async fn show_spatial_effect(
+        &self,
+        effect: &dyn SpatialEffect,
+        frame_rate: f64,
+        duration: Option<Duration>,
+    ) -> Result<()> {
+        let layout = self.fetch_layout().await?;
+        let mut animation = SpatialAnimation::new(effect, layout.coordinates);
+        match duration {
+            Some(duration) => {
+                self.play_animation(&mut animation.limit(duration.as_secs_f64()), frame_rate)
+                    .await
+            }
+            None => self.play_animation(&mut animation, frame_rate).await,
+        }
+    }
+}
+
+/// Ken Perlin's improved gradient noise, with a permutation shuffled by a seed.
+#[derive(Debug, Clone, PartialEq)]
+struct PerlinNoise {
+    permutation: Vec<u8>,
+}
+
+impl PerlinNoise {
+    fn new(seed: u64) -> PerlinNoise {
+        let mut permutation: Vec<u8> = (0..=255).collect();
+        permutation.shuffle(&mut StdRng::seed_from_u64(seed));
+        permutation.extend_from_within(..);
+        PerlinNoise { permutation }
+    }
+
+    /// Noise from about -1 to 1, smooth in all directions.
+    fn get(&self, x: f64, y: f64, z: f64) -> f64 {
+        let p = |i: usize| self.permutation[i] as usize;
+        let cell = |v: f64| (v.floor() as i64 & 255) as usize;
+        let (xi, yi, zi) = (cell(x), cell(y), cell(z));
+        let (x, y, z) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let a = p(xi) + yi;
+        let (aa, ab) = (p(a) + zi, p(a + 1) + zi);
+        let b = p(xi + 1) + yi;
+        let (ba, bb) = (p(b) + zi, p(b + 1) + zi);
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad(p(aa), x, y, z), grad(p(ba), x - 1.0, y, z)),
+                lerp(
+                    u,
+                    grad(p(ab), x, y - 1.0, z),
+                    grad(p(bb), x - 1.0, y - 1.0, z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(p(aa + 1), x, y, z - 1.0),
+                    grad(p(ba + 1), x - 1.0, y, z - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(p(ab + 1), x, y - 1.0, z - 1.0),
+                    grad(p(bb + 1), x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+
+    /// Two octaves of noise, mapped to 0..=1.
+    fn fractal(&self, x: f64, y: f64, z: f64) -> f64 {
+        let value = self.get(x, y, z) + 0.5 * self.get(2.0 * x, 2.0 * y, 2.0 * z);
+        (value / 1.5 * 0.5 + 0.5).clamp(0.0, 1.0)
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = match h {
+        0..=3 => y,
+        12 | 14 => x,
+        _ => z,
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// The color at `value` from 0 to 1 along evenly spaced color stops.
+fn gradient(colors: &[(u8, u8, u8)], value: f64) -> (u8, u8, u8) {
+    match colors {
+        [] => (0, 0, 0),
+        [color] => *color,
+        _ => {
+            let position = value.clamp(0.0, 1.0) * (colors.len() - 1) as f64;
+            let index = (position.floor() as usize).min(colors.len() - 2);
+            Pattern::blend_colors(colors[index], colors[index + 1], position - index as f64)
+        }
+    }
+}
+
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> (u8, u8, u8) {
+    let rgb = Srgb::from_color(Hsl::new(
+        hue * 360.0,
+        saturation.clamp(0.0, 1.0),
+        lightness.clamp(0.0, 1.0),
+    ));
+    let channel = |value: f64| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+    (channel(rgb.red), channel(rgb.green), channel(rgb.blue))
+}
+
+/// The position of `progress` in a cycle of `period` seconds, from 0 to 1.
+fn progress(time: f64, period: f64) -> f64 {
+    if period == 0.0 {
+        0.0
+    } else {
+        (time / period).rem_euclid(1.0)
+    }
+}
+
+fn dot(led: &LedCoordinate, direction: [f64; 3]) -> f64 {
+    led.x * direction[0] + led.y * direction[1] + led.z * direction[2]
+}
+
+fn distance(a: &LedCoordinate, b: &LedCoordinate) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt()
+}
+
+fn unit(vector: [f64; 3]) -> Option<[f64; 3]> {
+    let length = vector.iter().map(|v| v * v).sum::<f64>().sqrt();
+    (length > 0.0 && length.is_finite()).then(|| vector.map(|v| v / length))
+}
+
+/// The splitmix64 finalizer, spreading every input bit over the output.
+fn mix(mut value: u64) -> u64 {
+    value = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+fn hash_position(led: &LedCoordinate, seed: u64) -> u64 {
+    [led.x, led.y, led.z]
+        .iter()
+        .fold(mix(seed), |hash, value| mix(hash ^ value.to_bits()))
+}
+
+/// Maps a hash to 0..1.
+fn unit_interval(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn led(x: f64, y: f64, z: f64) -> LedCoordinate {
+        LedCoordinate { x, y, z }
+    }
+
+    /// A vertical line of 11 LEDs.
+    fn column() -> Vec<LedCoordinate> {
+        (0..=10).map(|i| led(0.0, i as f64 / 10.0, 0.0)).collect()
+    }
+
+    #[test]
+    fn test_plane_sweep() {
+        let sweep = PlaneSweep {
+            width: 0.1,
+            period: 1.0,
+            ..PlaneSweep::new([0.0, 2.0, 0.0], (255, 0, 0))
+        };
+        // Halfway through, the band is at the middle of the column
+        let frame = sweep.frame(&column(), 0.5);
+        assert_eq!(frame[5], (255, 0, 0));
+        assert_eq!(frame[0], (0, 0, 0));
+        assert_eq!(frame[10], (0, 0, 0));
+        // At the start, the band is still below the layout
+        assert!(sweep.frame(&column(), 0.0).iter().all(|&c| c == (0, 0, 0)));
+    }
+
+    #[test]
+    fn test_expanding_sphere() {
+        let sphere = ExpandingSphere {
+            center: Some(led(0.0, 0.0, 0.0)),
+            period: 1.0,
+            thickness: 0.05,
+            ..ExpandingSphere::new((0, 0, 255))
+        };
+        let leds = column();
+        // The shell grows to 1.05 in a period, reaching 0.5 at about 0.476 seconds
+        let frame = sphere.frame(&leds, 0.5 / 1.05);
+        assert_eq!(frame[5], (0, 0, 255));
+        assert_eq!(frame[2], (0, 0, 0));
+        assert_eq!(frame[8], (0, 0, 0));
+    }
+
+    #[test]
+    fn test_rotating_rainbow() {
+        let rainbow = RotatingRainbow::default();
+        let ring: Vec<LedCoordinate> = (0..4)
+            .map(|i| {
+                let angle = i as f64 * PI / 2.0;
+                led(angle.sin(), 0.0, angle.cos())
+            })
+            .collect();
+        let start = rainbow.frame(&ring, 0.0);
+        // A quarter turn later, each LED shows the color of its neighbor
+        let turned = rainbow.frame(&ring, rainbow.period / 4.0);
+        assert_ne!(start[0], start[1]);
+        for i in 0..4 {
+            assert_eq!(turned[(i + 1) % 4], start[i]);
+        }
+        assert_eq!(rainbow.frame(&ring, rainbow.period), start);
+    }
+
+    #[test]
+    fn test_noise_is_seeded_and_smooth() {
+        let colors = vec![(0, 0, 0), (255, 255, 255)];
+        let noise = NoiseField::new(colors.clone(), 7);
+        let leds = column();
+        assert_eq!(
+            noise.frame(&leds, 1.0),
+            NoiseField::new(colors.clone(), 7).frame(&leds, 1.0)
+        );
+        assert_ne!(
+            noise.frame(&leds, 1.0),
+            NoiseField::new(colors, 8).frame(&leds, 1.0)
+        );
+
+        let step = |a: (u8, u8, u8), b: (u8, u8, u8)| a.0.abs_diff(b.0);
+        let now = noise.frame(&leds, 1.0);
+        let soon = noise.frame(&leds, 1.01);
+        assert!(now.iter().zip(&soon).all(|(&a, &b)| step(a, b) < 10));
+    }
+
+    #[test]
+    fn test_fire_is_hotter_at_the_bottom() {
+        let fire = Fire::new(1);
+        let leds = column();
+        let brightness = |color: (u8, u8, u8)| color.0 as u32 + color.1 as u32 + color.2 as u32;
+        let (mut bottom, mut top) = (0, 0);
+        for step in 0..50 {
+            let frame = fire.frame(&leds, step as f64 * 0.1);
+            bottom += brightness(frame[1]);
+            top += brightness(frame[9]);
+        }
+        assert!(bottom > top, "bottom {} top {}", bottom, top);
+        // Above the flames it stays dark
+        assert_eq!(fire.frame(&leds, 0.3)[10], (0, 0, 0));
+    }
+
+    #[test]
+    fn test_twinkle_density() {
+        let mut twinkle = Twinkle::new((255, 255, 255), 3);
+        twinkle.rate = 1.0 / twinkle.duration;
+        twinkle.density = Twinkle::density_along(Axis::Y, 0.0, 1.0);
+        let leds = column();
+
+        let lit = |index: usize| {
+            (0..200)
+                .filter(|step| twinkle.frame(&leds, *step as f64 * 0.05)[index] != (0, 0, 0))
+                .count()
+        };
+        // No twinkles where the density is 0, most of the time where it is 1
+        assert_eq!(lit(0), 0);
+        assert!(lit(10) > 150);
+        assert_eq!(twinkle.frame(&leds, 1.3), twinkle.clone().frame(&leds, 1.3));
+    }
+
+    #[test]
+    fn test_movie_from_effect() {
+        let movie = Movie::from_spatial_effect(
+            &RotatingRainbow::default(),
+            &column(),
+            Duration::from_secs(2),
+            12.5,
+        );
+        assert_eq!(movie.frames.len(), 25);
+        assert_eq!(movie.frames[0].len(), 11);
+        assert_eq!(movie.fps, 12.5);
+    }
+}
@@ -46,6 +46,28 @@
 //! This project is dual-licensed under the MIT License and the Apache License, Version 2.0.
 //! You may choose to use either license, depending on your project needs.
 //! See the `LICENSE-MIT` and `LICENSE-APACHE` files for the full text of the licenses.
+// The `error` module defines the [Error] type returned throughout the library, so callers can
+// tell an unreachable device from a rejected authentication or a device error code.
+//
+// Example usage:
+//
+// ```
+// use glow_control_lib::control_interface::ControlInterface;
+// use glow_control_lib::Error;
+//
+// #[tokio::main]
+// async fn main() {
+//     match ControlInterface::new("192.168.1.100", "AA:BB:CC:DD:EE:FF", None).await {
+//         Err(Error::Transport(e)) => eprintln!("Device unreachable: {}", e),
+//         Err(Error::Authentication(e)) => eprintln!("Authentication failed: {}", e),
+//         _ => {}
+//     }
+// }
+// ```
+pub mod error;
+
+pub use error::{Error, Result};
+
 // The `control_interface` module provides an interface for communicating with
 // LED devices. It includes methods for sending commands, querying device status,
 // and managing device settings.
@@ -64,6 +86,24 @@
 // ```
 pub mod control_interface;
 
+// The `emulator` module runs a fake Twinkly device locally: the HTTP API, the real-time
+// UDP listener and the discovery responder. It allows testing without hardware.
+// It's only built with the `emulator` feature.
+//
+// Example usage:
+//
+// ```
+// use glow_control_lib::emulator::{DeviceEmulator, EmulatorConfig};
+//
+// #[tokio::main]
+// async fn main() {
+//     let emulator = DeviceEmulator::start(EmulatorConfig::default()).await.unwrap();
+//     let control = emulator.control_interface().await.unwrap();
+// }
+// ```
+#[cfg(feature = "emulator")]
+pub mod emulator;
+
 // The `led` module contains abstractions and utilities for working with LED colors,
 // patterns, and animations. It provides functionality to create and manipulate
 // color patterns, apply effects, and convert between different color models.
@@ -1,5 +1,5 @@
+use crate::error::Result;
 use crate::util::rc4::Rc4;
-use anyhow::Result;
 use rand::RngCore;
 use sha1::Digest;
 use sha1::Sha1;
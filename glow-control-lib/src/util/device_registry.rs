@@ -0,0 +1,296 @@
+/*!
+A registry of known devices, remembering their last known IP addresses under friendly aliases.
+
+Devices get their IP addresses by DHCP, so the address a device was found at may change.
+The registry keys devices by MAC address, and [DeviceRegistry::connect] looks a device up
+by discovery again if it doesn't answer at its last known address.
+
+The registry is stored as JSON, by default in `glow-control/devices.json` in the user's
+configuration directory, see [DeviceRegistry::default_path].
+ */
+
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::control_interface::{ControlInterface, TraceHook};
+use crate::error::{Error, Result};
+use crate::util::discovery::{DeviceIdentifier, Discovery, DiscoveryOptions};
+
+/// A device in the [DeviceRegistry].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredDevice {
+    pub mac_address: String,
+    /// The IP address the device was last found at.
+    pub ip_address: Ipv4Addr,
+    pub device_name: String,
+    /// Friendly names for the device, each one unique in the registry.
+    #[serde(default)]
+    pub aliases: BTreeSet<String>,
+}
+
+/// Devices by MAC address, with their last known IP addresses and aliases.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceRegistry {
+    devices: Vec<RegisteredDevice>,
+}
+
+impl DeviceRegistry {
+    /// The registry file in the user's configuration directory, if the platform has one.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("glow-control").join("devices.json"))
+    }
+
+    /// Loads a registry, or returns an empty one if `path` doesn't exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<DeviceRegistry> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(DeviceRegistry::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves the registry, creating the parent directories if needed.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn devices(&self) -> &[RegisteredDevice] {
+        &self.devices
+    }
+
+    /// Finds a device by one of its aliases, or by its MAC address.
+    pub fn find(&self, name: &str) -> Option<&RegisteredDevice> {
+        self.devices
+            .iter()
+            .find(|device| device.aliases.contains(name))
+            .or_else(|| {
+                self.devices
+                    .iter()
+                    .find(|device| device.mac_address.eq_ignore_ascii_case(name))
+            })
+    }
+
+    fn find_mut(&mut self, name: &str) -> Option<&mut RegisteredDevice> {
+        let mac_address = self.find(name)?.mac_address.clone();
+        self.devices
+            .iter_mut()
+            .find(|device| device.mac_address == mac_address)
+    }
+
+    /// Adds a discovered device, or updates the IP address and name of a registered one.
+    pub fn update(&mut self, found: &DeviceIdentifier) {
+        match self
+            .devices
+            .iter_mut()
+            .find(|device| device.mac_address.eq_ignore_ascii_case(&found.mac_address))
+        {
+            Some(device) => {
+                device.ip_address = found.ip_address;
+                device.device_name.clone_from(&found.device_name);
+            }
+            None => {
+                self.devices.push(RegisteredDevice {
+                    mac_address: found.mac_address.clone(),
+                    ip_address: found.ip_address,
+                    device_name: found.device_name.clone(),
+                    aliases: BTreeSet::new(),
+                });
+                self.devices
+                    .sort_by(|a, b| a.mac_address.cmp(&b.mac_address));
+            }
+        }
+    }
+
+    /**
+    Gives the device found by `name`, an alias or MAC address, the alias `alias`.
+
+    An alias already given to another device is moved.
+     */
+    pub fn set_alias(&mut self, alias: &str, name: &str) -> Result<()> {
+        if alias.trim().is_empty() {
+            return Err(Error::InvalidArgument("The alias is empty".to_string()));
+        }
+        let mac_address = self
+            .find(name)
+            .ok_or_else(|| unknown_device(name))?
+            .mac_address
+            .clone();
+        self.remove_alias(alias);
+        if let Some(device) = self.find_mut(&mac_address) {
+            device.aliases.insert(alias.to_string());
+        }
+        Ok(())
+    }
+
+    /// Removes an alias, returning whether it existed.
+    pub fn remove_alias(&mut self, alias: &str) -> bool {
+        self.devices
+            .iter_mut()
+            .any(|device| device.aliases.remove(alias))
+    }
+
+    /// Removes the device found by `name`, an alias or MAC address, with all its aliases.
+    pub fn forget(&mut self, name: &str) -> Option<RegisteredDevice> {
+        let mac_address = self.find(name)?.mac_address.clone();
+        let index = self
+            .devices
+            .iter()
+            .position(|device| device.mac_address == mac_address)?;
+        Some(self.devices.remove(index))
+    }
+
+    /**
+    Searches the network without authenticating the devices, and updates the registry with
+    the devices found.
+     */
+    pub async fn rediscover(
+        &mut self,
+        options: &DiscoveryOptions,
+    ) -> Result<HashSet<DeviceIdentifier>> {
+        let options = DiscoveryOptions {
+            authenticate: false,
+            ..options.clone()
+        };
+        let found = Discovery::find_devices_with(&options).await?;
+        for device in &found {
+            self.update(device);
+        }
+        Ok(found)
+    }
+
+    /**
+    Connects to the device found by `name`, an alias or MAC address.
+
+    If the device can't be reached or authenticated at its last known IP address, or another
+    device answers there, it's searched for with `options`, and the connection is retried at its
+    new address. The registry is updated with the devices found, so it should be saved afterwards.
+     */
+    pub async fn connect(
+        &mut self,
+        name: &str,
+        options: &DiscoveryOptions,
+        trace_hook: Option<TraceHook>,
+    ) -> Result<ControlInterface> {
+        let device = self.find(name).ok_or_else(|| unknown_device(name))?.clone();
+        let error = match Self::connect_at(&device, options, trace_hook.clone()).await {
+            Ok(control) => return Ok(control),
+            Err(e) => e,
+        };
+        info!(
+            "Device {} didn't answer at {}: {}, searching for it",
+            name, device.ip_address, error
+        );
+        // The original error tells more about why the device can't be used than a failed search
+        if let Err(e) = self.rediscover(options).await {
+            warn!("Failed to search for device {}: {}", name, e);
+            return Err(error);
+        }
+        match self.find(&device.mac_address) {
+            Some(moved) if moved.ip_address != device.ip_address => {
+                info!("Device {} moved to {}", name, moved.ip_address);
+                Self::connect_at(moved, options, trace_hook).await
+            }
+            _ => Err(error),
+        }
+    }
+
+    /// Connects to `device` at its registered IP address, checking that it's the same device.
+    async fn connect_at(
+        device: &RegisteredDevice,
+        options: &DiscoveryOptions,
+        trace_hook: Option<TraceHook>,
+    ) -> Result<ControlInterface> {
+        let control = ControlInterface::new_with_trace_hook(
+            &options.http_host(device.ip_address),
+            &device.mac_address,
+            None,
+            trace_hook,
+        )
+        .await?;
+        let mac_address = &control.get_device_info().mac;
+        if !mac_address.eq_ignore_ascii_case(&device.mac_address) {
+            return Err(Error::DeviceMismatch {
+                expected: device.mac_address.clone(),
+                found: mac_address.clone(),
+            });
+        }
+        Ok(control)
+    }
+}
+
+fn unknown_device(name: &str) -> Error {
+    Error::InvalidArgument(format!(
+        "No device with the alias or MAC address {} is registered",
+        name
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::discovery::tests::device;
+
+    #[test]
+    fn test_update_and_aliases() {
+        let mut registry = DeviceRegistry::default();
+        registry.update(&device("02:00:00:00:00:02", [10, 0, 0, 2], "Tree"));
+        registry.update(&device("02:00:00:00:00:01", [10, 0, 0, 3], "Porch"));
+        registry.set_alias("porch", "02:00:00:00:00:01").unwrap();
+        registry
+            .set_alias("tree-left", "02:00:00:00:00:02")
+            .unwrap();
+        assert!(registry.set_alias("garage", "nowhere").is_err());
+
+        // A new IP address keeps the aliases
+        registry.update(&device("02:00:00:00:00:01", [10, 0, 0, 9], "Porch"));
+        let porch = registry.find("porch").unwrap();
+        assert_eq!(porch.ip_address, Ipv4Addr::new(10, 0, 0, 9));
+        assert_eq!(registry.find("02:00:00:00:00:01"), Some(porch));
+        assert_eq!(registry.devices().len(), 2);
+
+        // Aliases are moved rather than shared
+        registry.set_alias("porch", "tree-left").unwrap();
+        assert_eq!(registry.find("porch").unwrap().device_name, "Tree");
+        assert!(registry
+            .find("02:00:00:00:00:01")
+            .unwrap()
+            .aliases
+            .is_empty());
+
+        assert!(registry.remove_alias("tree-left"));
+        assert!(!registry.remove_alias("tree-left"));
+        assert_eq!(registry.forget("porch").unwrap().device_name, "Tree");
+        assert_eq!(registry.devices().len(), 1);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir()
+            .join(format!("glow-control-registry-{}", std::process::id()))
+            .join("devices.json");
+        assert_eq!(
+            DeviceRegistry::load(&path).unwrap(),
+            DeviceRegistry::default()
+        );
+
+        let mut registry = DeviceRegistry::default();
+        registry.update(&device("02:00:00:00:00:01", [10, 0, 0, 3], "Porch"));
+        registry.set_alias("porch", "02:00:00:00:00:01").unwrap();
+        registry.save(&path).unwrap();
+        assert_eq!(DeviceRegistry::load(&path).unwrap(), registry);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -1,27 +1,70 @@
 use std::cmp::max;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::{Display, Formatter};
+use std::future::Future;
 use std::net::Ipv4Addr;
 use std::time::Duration;
 
-use anyhow::Context;
-use log::{error, info};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
 use tokio::time::{timeout, Instant};
 
 use derivative::Derivative;
+use futures::future::ready;
+use futures::stream::{poll_fn, Stream, StreamExt};
+use if_addrs::IfAddr;
+use ipnet::Ipv4Net;
+use lazy_static::lazy_static;
 
 use crate::control_interface::ControlInterface;
+use crate::error::{Error, Result};
 
-const PING_MESSAGE: &[u8] = b"\x01discover";
-const BROADCAST_ADDRESS: &str = "255.255.255.255:5555";
+pub(crate) const PING_MESSAGE: &[u8] = b"\x01discover";
 
+/// The UDP port Twinkly devices answer discovery pings on.
+pub const DISCOVERY_PORT: u16 = 5555;
+
+/// The TCP port Twinkly devices serve their HTTP API on.
+pub const HTTP_PORT: u16 = 80;
+
+/// The default for [DiscoveryOptions::concurrency].
+pub const DEFAULT_DISCOVERY_CONCURRENCY: usize = 8;
+
+/// The default for [DiscoveryOptions::identify_timeout].
+pub const DEFAULT_IDENTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a gestalt request may take, connecting included.
+const GESTALT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    /// The client for all gestalt requests, so their connections are pooled.
+    static ref GESTALT_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(GESTALT_REQUEST_TIMEOUT)
+        .build()
+        .expect("Failed to build the HTTP client");
+}
+
+/// The shortest prefix of a [DiscoveryTarget::Range], which limits it to 65534 hosts.
+pub const MIN_RANGE_PREFIX_LEN: u8 = 16;
+
+/**
+The fields of the unauthenticated gestalt response needed to identify a device.
+
+Fields which older firmware may not report are optional.
+ */
 #[derive(Deserialize, Debug)]
 pub struct GestaltResponse {
-    mac: String,
-    device_name: String,
-    // Include other fields from the response as needed
+    pub(crate) mac: String,
+    pub(crate) device_name: String,
+    pub(crate) number_of_led: usize,
+    #[serde(default)]
+    pub(crate) product_code: Option<String>,
+    #[serde(default)]
+    pub(crate) fw_family: Option<String>,
+    #[serde(default)]
+    pub(crate) hardware_version: Option<String>,
 }
 
 impl Display for GestaltResponse {
@@ -30,7 +73,7 @@ impl Display for GestaltResponse {
     }
 }
 
-#[derive(Debug, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct DiscoveryResponse {
     ip_address: Ipv4Addr,
     device_id: String,
@@ -43,6 +86,14 @@ impl DiscoveryResponse {
             device_id,
         }
     }
+
+    pub fn ip_address(&self) -> Ipv4Addr {
+        self.ip_address
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
 }
 
 #[derive(Derivative)]
@@ -55,11 +106,25 @@ pub struct DeviceIdentifier {
     pub device_name: String,
     pub led_count: u16,
 
+    /// The product code from the gestalt info, e.g. `TWS250STP-B`.
+    #[derivative(Hash = "ignore", PartialEq = "ignore", PartialOrd = "ignore")]
+    pub product_code: Option<String>,
+
+    /// The firmware family from the gestalt info, e.g. `G`.
+    #[derivative(Hash = "ignore", PartialEq = "ignore", PartialOrd = "ignore")]
+    pub fw_family: Option<String>,
+
+    /// The hardware version from the gestalt info, e.g. `100`.
+    #[derivative(Hash = "ignore", PartialEq = "ignore", PartialOrd = "ignore")]
+    pub hardware_version: Option<String>,
+
     /**
     The auth-token if the device was authenticated.
 
     If the device was found by a search, the auth_token must
     be generated to pull info from the device, and then that new token must be used.
+    Devices found without authentication, see [DiscoveryOptions::authenticate], have no token
+    until they are authenticated by [ControlInterface::from_device_identifier].
 
     **Too frequent token generation _can_ lead to erroneous behavior.**
     */
@@ -82,11 +147,129 @@ impl DeviceIdentifier {
             mac_address,
             device_name,
             led_count,
+            product_code: None,
+            fw_family: None,
+            hardware_version: None,
             auth_token,
         }
     }
 }
 
+/**
+Where discovery pings are sent, see [DiscoveryOptions::targets].
+
+Broadcasts only reach the network of the interface they leave through. On hosts with several
+interfaces, such as Docker bridges or VPNs, [Self::Interface] or [Self::Subnet] make sure the
+ping reaches the right network. [Self::Range] and [Self::Host] reach devices on networks
+which block broadcasts.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryTarget {
+    /// The limited broadcast `255.255.255.255`, leaving through the interface chosen by the OS.
+    Broadcast,
+    /// The directed broadcast of each IPv4 address of a network interface, sent from that address.
+    Interface(String),
+    /// The directed broadcast of a subnet, e.g. `192.168.10.255` for `192.168.10.0/24`.
+    Subnet(Ipv4Net),
+    /// A unicast ping to every host of a range, up to a `/16` network.
+    Range(Ipv4Net),
+    /// A unicast ping to one host.
+    Host(Ipv4Addr),
+}
+
+impl DiscoveryTarget {
+    /// The source and destination address of each ping, with an unspecified source for any interface.
+    fn pings(&self) -> Result<Vec<(Ipv4Addr, Ipv4Addr)>> {
+        let any = Ipv4Addr::UNSPECIFIED;
+        Ok(match self {
+            DiscoveryTarget::Broadcast => vec![(any, Ipv4Addr::BROADCAST)],
+            DiscoveryTarget::Interface(name) => {
+                let pings: Vec<_> = if_addrs::get_if_addrs()?
+                    .into_iter()
+                    .filter(|interface| &interface.name == name)
+                    .filter_map(|interface| match interface.addr {
+                        IfAddr::V4(address) => Some((
+                            address.ip,
+                            address.broadcast.unwrap_or_else(|| {
+                                Ipv4Net::new(address.ip, address.prefixlen)
+                                    .map_or(Ipv4Addr::BROADCAST, |net| net.broadcast())
+                            }),
+                        )),
+                        IfAddr::V6(_) => None,
+                    })
+                    .collect();
+                if pings.is_empty() {
+                    return Err(Error::InvalidArgument(format!(
+                        "No IPv4 address found for network interface {}",
+                        name
+                    )));
+                }
+                pings
+            }
+            DiscoveryTarget::Subnet(net) => vec![(any, net.broadcast())],
+            DiscoveryTarget::Range(net) => {
+                if net.prefix_len() < MIN_RANGE_PREFIX_LEN {
+                    return Err(Error::InvalidArgument(format!(
+                        "The range {} is larger than a /{} network",
+                        net, MIN_RANGE_PREFIX_LEN
+                    )));
+                }
+                net.hosts().map(|host| (any, host)).collect()
+            }
+            DiscoveryTarget::Host(host) => vec![(any, *host)],
+        })
+    }
+}
+
+/// How devices are searched for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveryOptions {
+    /// How long to wait for replies.
+    pub timeout: Duration,
+    /// Where the pings are sent, all targets are pinged in one search.
+    pub targets: Vec<DiscoveryTarget>,
+    /// The UDP port the pings are sent to.
+    pub port: u16,
+    /// The TCP port of the devices' HTTP API, which only differs from [HTTP_PORT] for emulated devices.
+    pub http_port: u16,
+    /// How many devices are asked for their gestalt info and authenticated at the same time.
+    pub concurrency: usize,
+    /// How long identifying a single device may take, authentication included.
+    pub identify_timeout: Duration,
+    /**
+    Whether found devices are authenticated, which checks that they accept commands and
+    provides a token to reuse.
+
+    Without authentication, devices are identified from their gestalt info alone, and only
+    authenticated once a command is sent to them.
+     */
+    pub authenticate: bool,
+}
+
+impl DiscoveryOptions {
+    /// Broadcasts to [DISCOVERY_PORT] on the interface chosen by the OS.
+    pub fn new(timeout: Duration) -> Self {
+        DiscoveryOptions {
+            timeout,
+            targets: vec![DiscoveryTarget::Broadcast],
+            port: DISCOVERY_PORT,
+            http_port: HTTP_PORT,
+            concurrency: DEFAULT_DISCOVERY_CONCURRENCY,
+            identify_timeout: DEFAULT_IDENTIFY_TIMEOUT,
+            authenticate: true,
+        }
+    }
+
+    /// The host to send HTTP requests to for a device at `ip_address`, with the port if needed.
+    pub fn http_host(&self, ip_address: Ipv4Addr) -> String {
+        if self.http_port == HTTP_PORT {
+            ip_address.to_string()
+        } else {
+            format!("{}:{}", ip_address, self.http_port)
+        }
+    }
+}
+
 pub struct Discovery;
 
 /**
@@ -105,6 +288,26 @@ pub struct ResponseNewExisting {
     Used by [`Self::find_new_devices`] if the `existing_devices` argument  has been supplied.
      */
     pub existing_devices: HashSet<DeviceIdentifier>,
+
+    /// Devices which answered the ping, but couldn't be asked for their gestalt info or authenticated.
+    pub failed_devices: Vec<DiscoveryFailure>,
+}
+
+/// A device which answered the discovery ping, but couldn't be identified.
+#[derive(Debug)]
+pub struct DiscoveryFailure {
+    pub response: DiscoveryResponse,
+    pub error: Error,
+}
+
+impl Display for DiscoveryFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at {}: {}",
+            self.response.device_id, self.response.ip_address, self.error
+        )
+    }
 }
 
 impl Discovery {
@@ -136,10 +339,15 @@ impl Discovery {
         })
     }
 
-    pub async fn find_devices(
-        given_timeout: Duration,
-    ) -> anyhow::Result<HashSet<DeviceIdentifier>> {
-        Self::find_new_devices(given_timeout, None)
+    pub async fn find_devices(given_timeout: Duration) -> Result<HashSet<DeviceIdentifier>> {
+        Self::find_devices_with(&DiscoveryOptions::new(given_timeout)).await
+    }
+
+    /// Like [`Self::find_devices`], sending the pings to the targets in `options`.
+    pub async fn find_devices_with(
+        options: &DiscoveryOptions,
+    ) -> Result<HashSet<DeviceIdentifier>> {
+        Self::find_new_devices_with(options, None)
             .await
             .map(|devices: ResponseNewExisting| devices.new_devices)
     }
@@ -153,99 +361,268 @@ impl Discovery {
     pub async fn find_new_devices(
         given_timeout: Duration,
         existing_devices: Option<HashSet<DeviceIdentifier>>,
-    ) -> anyhow::Result<ResponseNewExisting> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        socket.set_broadcast(true)?;
-        socket.send_to(PING_MESSAGE, BROADCAST_ADDRESS).await?;
+    ) -> Result<ResponseNewExisting> {
+        Self::find_new_devices_with(&DiscoveryOptions::new(given_timeout), existing_devices).await
+    }
 
-        let mut discovered_devices = HashSet::<DeviceIdentifier>::new();
-        let mut buffer = [0; 1024];
+    /**
+    Like [`Self::find_new_devices`], sending the pings to the targets in `options`.
 
-        let timeout_end = Instant::now() + given_timeout;
+    Devices are identified while the replies are still being collected, up to
+    [`DiscoveryOptions::concurrency`] at a time, so one slow device doesn't hold up the others.
+    Devices which fail are reported in [`ResponseNewExisting::failed_devices`].
+     */
+    pub async fn find_new_devices_with(
+        options: &DiscoveryOptions,
+        existing_devices: Option<HashSet<DeviceIdentifier>>,
+    ) -> Result<ResponseNewExisting> {
+        let responses = Self::response_stream(options).await?;
 
         let mut found_existing_devices = HashSet::<DeviceIdentifier>::new();
+        let identified: Vec<_> = responses
+            .filter(|discovery_response| {
+                // Search if `existing_devices` matches a `discovery_response`:
+                let exist = existing_devices.as_ref().and_then(|existing_devices| {
+                    Self::find_discovered_device(existing_devices, discovery_response)
+                });
+                let is_new = exist.is_none();
+                if let Some(exist) = exist {
+                    info!("Device {:?} isn't new, skipping", discovery_response);
+                    found_existing_devices.insert(exist);
+                }
+                ready(is_new)
+            })
+            .map(|discovery_response| async move {
+                info!("Found device: {:?}", discovery_response);
+                let device = Self::identify_within(
+                    options,
+                    Self::identify_response(&discovery_response, options),
+                )
+                .await;
+                (discovery_response, device)
+            })
+            .buffer_unordered(options.concurrency.max(1))
+            .collect()
+            .await;
 
-        loop {
-            if Instant::now() >= timeout_end {
-                break;
+        let mut discovered_devices = HashSet::<DeviceIdentifier>::new();
+        let mut failed_devices = Vec::new();
+        for (response, device) in identified {
+            match device {
+                Ok(device) => {
+                    discovered_devices.insert(device);
+                }
+                Err(error) => {
+                    let failure = DiscoveryFailure { response, error };
+                    warn!("Failed to identify device {}", failure);
+                    failed_devices.push(failure);
+                }
             }
+        }
 
-            let remaining_time = timeout_end - Instant::now();
-            let result = timeout(remaining_time, socket.recv_from(&mut buffer)).await;
+        Ok(ResponseNewExisting {
+            new_devices: discovered_devices,
+            existing_devices: found_existing_devices,
+            failed_devices,
+        })
+    }
+
+    /**
+    Sends discovery pings to the targets in `options` and collects the replies until its timeout runs out.
+
+    Unlike [`Self::find_devices`], the devices are neither asked for their gestalt info nor
+    authenticated. Devices answering more than once are reported once.
+
+    Fails only if no ping could be sent at all, failures for single targets are logged.
+     */
+    pub async fn collect_responses(
+        options: &DiscoveryOptions,
+    ) -> Result<HashSet<DiscoveryResponse>> {
+        Ok(Self::response_stream(options).await?.collect().await)
+    }
+
+    /**
+    Sends the pings and returns the replies as they arrive, until the timeout runs out.
 
-            match result {
+    Some devices may respond multiple times for one request, to make sure the listener gets it.
+    Each device is only passed on once, which saves its needless re-authentication.
+     */
+    async fn response_stream(
+        options: &DiscoveryOptions,
+    ) -> Result<impl Stream<Item = DiscoveryResponse>> {
+        let sockets = Self::send_pings(options).await?;
+        let timeout_end = Instant::now() + options.timeout;
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        for socket in sockets {
+            tokio::spawn(Self::receive_responses(socket, timeout_end, sender.clone()));
+        }
+        let mut seen = HashSet::new();
+        Ok(poll_fn(move |cx| receiver.poll_recv(cx))
+            .filter(move |response: &DiscoveryResponse| ready(seen.insert(response.clone()))))
+    }
+
+    /// Passes the replies arriving on `socket` to `sender` until `timeout_end`.
+    async fn receive_responses(
+        socket: UdpSocket,
+        timeout_end: Instant,
+        sender: mpsc::UnboundedSender<DiscoveryResponse>,
+    ) {
+        let mut buffer = [0; 1024];
+        loop {
+            let remaining_time = timeout_end.saturating_duration_since(Instant::now());
+            match timeout(remaining_time, socket.recv_from(&mut buffer)).await {
                 Ok(Ok((number_of_bytes, _src_addr))) => {
-                    let received_data = &buffer[..number_of_bytes];
-                    if let Some(discovery_response) = Self::decode_discovery_response(received_data)
+                    if let Some(response) =
+                        Self::decode_discovery_response(&buffer[..number_of_bytes])
                     {
-                        /*
-                        Look first if the device (i.e. address) is already in `discovered_devices`,
-                        and skip it, if it is, that makes the discovery process faster.
-
-                        It also saves the needless re-authentication of the device, which saves additional time.
-
-                        Cause: Some devices may respond multiple times for one request, to make sure the listener
-                               gets it.
-                        */
-                        // Search if `discovered_devices` matches a `discovery_response`:
-                        if Self::find_discovered_device(&discovered_devices, &discovery_response)
-                            .is_some()
-                        {
-                            info!("Found device {:?} again, skipping", discovery_response);
-                            continue;
-                        }
-                        // Search if `existing_devices` matches a `discovery_response`:
-                        if let Some(existing_devices) = &existing_devices {
-                            if let Some(exist) =
-                                Self::find_discovered_device(existing_devices, &discovery_response)
-                            {
-                                found_existing_devices.insert(exist);
-                                info!("Device {:?} isn't new, skipping", discovery_response);
-                                continue;
-                            }
-                        }
-                        info!("Found device: {:?}", discovery_response);
-                        match Self::fetch_gestalt_info(discovery_response.ip_address).await {
-                            Ok(gestalt_info) => {
-                                info!("MAC address: {}", gestalt_info);
-                                // Fetch the LED count from a high control interface
-                                let high_control_interface = ControlInterface::new(
-                                    &discovery_response.ip_address.to_string(),
-                                    &gestalt_info.mac,
-                                    None,
-                                )
-                                .await?;
-                                let led_count =
-                                    high_control_interface.get_device_info().number_of_led as u16;
-                                let device = DeviceIdentifier::new(
-                                    discovery_response.ip_address,
-                                    discovery_response.device_id,
-                                    gestalt_info.mac,
-                                    gestalt_info.device_name,
-                                    led_count,
-                                    // Reuse the auth token from the high control interface to speed up authentication.
-                                    Some(high_control_interface.auth_token),
-                                );
-                                discovered_devices.insert(device);
-                            }
-                            Err(e) => eprintln!("Error fetching MAC address: {:?}", e),
+                        if sender.send(response).is_err() {
+                            break;
                         }
                     }
                 }
                 Ok(Err(e)) => {
-                    eprintln!("Failed to receive response: {}", e);
+                    error!("Failed to receive response: {}", e);
                     break;
                 }
                 Err(_) => {
-                    eprintln!("Discovery time complete. If devices are missing, try increasing the search timeout.");
+                    info!("Discovery time complete. If devices are missing, try increasing the search timeout.");
                     break;
                 }
             }
         }
+    }
+
+    /**
+    Sends the pings for all targets, one socket per source address, and returns the sockets
+    to receive the replies on.
+     */
+    async fn send_pings(options: &DiscoveryOptions) -> Result<Vec<UdpSocket>> {
+        let mut by_source = BTreeMap::<Ipv4Addr, BTreeSet<Ipv4Addr>>::new();
+        for target in &options.targets {
+            for (source, destination) in target.pings()? {
+                by_source.entry(source).or_default().insert(destination);
+            }
+        }
+
+        let mut sockets = Vec::new();
+        let mut last_error = None;
+        for (source, destinations) in by_source {
+            let socket = match Self::bind_ping_socket(source).await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    error!("Failed to bind discovery socket to {}: {}", source, e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            let mut sent = false;
+            for destination in destinations {
+                match socket
+                    .send_to(PING_MESSAGE, (destination, options.port))
+                    .await
+                {
+                    Ok(_) => sent = true,
+                    Err(e) => {
+                        error!("Failed to send discovery ping to {}: {}", destination, e);
+                        last_error = Some(e.into());
+                    }
+                }
+            }
+            if sent {
+                sockets.push(socket);
+            }
+        }
 
-        Ok(ResponseNewExisting {
-            new_devices: discovered_devices,
-            existing_devices: found_existing_devices,
+        match last_error {
+            Some(e) if sockets.is_empty() => Err(e),
+            _ => Ok(sockets),
+        }
+    }
+
+    async fn bind_ping_socket(source: Ipv4Addr) -> Result<UdpSocket> {
+        let socket = UdpSocket::bind((source, 0)).await?;
+        socket.set_broadcast(true)?;
+        Ok(socket)
+    }
+
+    /// Fetches the gestalt info of a discovered device and, if asked to, authenticates it.
+    async fn identify_response(
+        discovery_response: &DiscoveryResponse,
+        options: &DiscoveryOptions,
+    ) -> Result<DeviceIdentifier> {
+        let host = options.http_host(discovery_response.ip_address);
+        let gestalt_info = Self::fetch_gestalt_info(&host).await?;
+        info!("MAC address: {}", gestalt_info);
+        let device = Self::device_from_gestalt(discovery_response.clone(), gestalt_info)?;
+        if options.authenticate {
+            Self::authenticate_device(device, &host).await
+        } else {
+            Ok(device)
+        }
+    }
+
+    /// Runs `identification`, failing with [Error::Timeout] once [DiscoveryOptions::identify_timeout] runs out.
+    pub(crate) async fn identify_within(
+        options: &DiscoveryOptions,
+        identification: impl Future<Output = Result<DeviceIdentifier>>,
+    ) -> Result<DeviceIdentifier> {
+        timeout(options.identify_timeout, identification)
+            .await
+            .unwrap_or_else(|_| {
+                Err(Error::Timeout {
+                    operation: "identify the device",
+                    timeout: options.identify_timeout,
+                })
+            })
+    }
+
+    /**
+    Identifies a discovered device from its gestalt info, without an auth token.
+
+    # Errors
+    [Error::InvalidArgument] if the device reports more LEDs than a [DeviceIdentifier] holds.
+     */
+    pub(crate) fn device_from_gestalt(
+        discovery_response: DiscoveryResponse,
+        gestalt_info: GestaltResponse,
+    ) -> Result<DeviceIdentifier> {
+        Ok(DeviceIdentifier {
+            product_code: gestalt_info.product_code,
+            fw_family: gestalt_info.fw_family,
+            hardware_version: gestalt_info.hardware_version,
+            ..DeviceIdentifier::new(
+                discovery_response.ip_address,
+                discovery_response.device_id,
+                gestalt_info.mac,
+                gestalt_info.device_name,
+                Self::led_count(gestalt_info.number_of_led)?,
+                None,
+            )
+        })
+    }
+
+    /// Converts the number of LEDs a device reports to [DeviceIdentifier::led_count].
+    fn led_count(number_of_led: usize) -> Result<u16> {
+        u16::try_from(number_of_led).map_err(|_| {
+            Error::InvalidArgument(format!(
+                "The device reports {} LEDs, more than the supported {}",
+                number_of_led,
+                u16::MAX
+            ))
+        })
+    }
+
+    /// Authenticates a discovered device at `host`, keeping the token in its [DeviceIdentifier].
+    pub(crate) async fn authenticate_device(
+        device: DeviceIdentifier,
+        host: &str,
+    ) -> Result<DeviceIdentifier> {
+        let high_control_interface = ControlInterface::new(host, &device.mac_address, None).await?;
+        Ok(DeviceIdentifier {
+            led_count: Self::led_count(high_control_interface.get_device_info().number_of_led)?,
+            // Reuse the auth token from the high control interface to speed up authentication.
+            auth_token: Some(high_control_interface.auth_token()),
+            ..device
         })
     }
 
@@ -275,26 +652,18 @@ impl Discovery {
         }
     }
 
-    async fn fetch_gestalt_info(ip_address: Ipv4Addr) -> anyhow::Result<GestaltResponse> {
-        let url = format!("http://{}/xled/v1/gestalt", ip_address);
-        let client = reqwest::Client::new();
-        let response = client
-            .get(&url)
-            .send()
-            .await
-            .context("Failed to send request to device")?;
+    pub(crate) async fn fetch_gestalt_info(host: &str) -> Result<GestaltResponse> {
+        let url = format!("http://{}/xled/v1/gestalt", host);
+        let response = GESTALT_CLIENT.get(&url).send().await?;
 
         if response.status().is_success() {
-            let gestalt: GestaltResponse = response
-                .json()
-                .await
-                .context("Failed to parse JSON response")?;
+            let gestalt: GestaltResponse = serde_json::from_slice(&response.bytes().await?)?;
             Ok(gestalt)
         } else {
-            Err(anyhow::anyhow!(
-                "Received non-success status code: {}",
-                response.status()
-            ))
+            Err(Error::HttpStatus {
+                operation: "fetch gestalt info",
+                status: response.status(),
+            })
         }
     }
     pub fn pretty_print_devices(devices: &HashSet<DeviceIdentifier>) {
@@ -370,3 +739,83 @@ impl Discovery {
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A device found at `ip`, without an auth token.
+    pub(crate) fn device(mac: &str, ip: [u8; 4], name: &str) -> DeviceIdentifier {
+        DeviceIdentifier::new(
+            Ipv4Addr::from(ip),
+            format!("Twinkly_{}", mac),
+            mac.to_string(),
+            name.to_string(),
+            100,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_device_from_gestalt() {
+        let device_info = ControlInterface::new_mock_device_info_response(
+            "000000".to_string(),
+            "Porch".to_string(),
+            "02:00:00:00:00:01".to_string(),
+            210,
+        );
+        let gestalt_info: GestaltResponse =
+            serde_json::from_value(serde_json::to_value(&device_info).unwrap()).unwrap();
+        let response = DiscoveryResponse::new(Ipv4Addr::new(10, 0, 0, 7), "Twinkly_000001".into());
+        let device = Discovery::device_from_gestalt(response.clone(), gestalt_info).unwrap();
+
+        assert_eq!(device.mac_address, "02:00:00:00:00:01");
+        assert_eq!(device.device_name, "Porch");
+        assert_eq!(device.led_count, 210);
+        assert_eq!(device.product_code.as_deref(), Some("TWQ012STW"));
+        assert_eq!(device.fw_family.as_deref(), Some("T"));
+        assert_eq!(device.hardware_version.as_deref(), Some("500"));
+        assert_eq!(device.auth_token, None);
+
+        // Older firmware may leave out the product fields
+        let minimal: GestaltResponse = serde_json::from_str(
+            r#"{"mac": "02:00:00:00:00:02", "device_name": "Tree", "number_of_led": 100}"#,
+        )
+        .unwrap();
+        assert_eq!(minimal.product_code, None);
+
+        let oversized: GestaltResponse = serde_json::from_str(
+            r#"{"mac": "02:00:00:00:00:03", "device_name": "Wall", "number_of_led": 70000}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            Discovery::device_from_gestalt(response, oversized),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_target_pings() {
+        let any = Ipv4Addr::UNSPECIFIED;
+        let subnet = DiscoveryTarget::Subnet("192.168.10.0/24".parse().unwrap());
+        assert_eq!(
+            subnet.pings().unwrap(),
+            vec![(any, Ipv4Addr::new(192, 168, 10, 255))]
+        );
+
+        let range = DiscoveryTarget::Range("10.0.0.8/30".parse().unwrap());
+        assert_eq!(
+            range.pings().unwrap(),
+            vec![
+                (any, Ipv4Addr::new(10, 0, 0, 9)),
+                (any, Ipv4Addr::new(10, 0, 0, 10))
+            ]
+        );
+        assert!(DiscoveryTarget::Range("10.0.0.0/15".parse().unwrap())
+            .pings()
+            .is_err());
+        assert!(DiscoveryTarget::Interface("no-such-interface0".to_string())
+            .pings()
+            .is_err());
+    }
+}
@@ -0,0 +1,379 @@
+/*!
+Continuous discovery, tracking devices as they join and leave the network.
+
+A [DiscoveryWatcher] pings the network every interval and keeps a registry of the
+[DeviceIdentifier]s it found, keyed by MAC address. Changes between rounds are reported
+as [DiscoveryEvent]s, either returned by [DiscoveryWatcher::round] or sent on the channel
+returned by [DiscoveryWatcher::spawn].
+
+Devices seen for the first time are authenticated, unless [DiscoveryOptions::authenticate]
+is turned off. Known devices are recognized from their unauthenticated gestalt info.
+ */
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use futures::future::ready;
+use futures::stream::{self, StreamExt};
+use log::{debug, error};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+
+use crate::error::Result;
+use crate::util::discovery::{DeviceIdentifier, Discovery, DiscoveryOptions, DiscoveryResponse};
+
+/// The default time between the starts of two discovery rounds.
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The default time each round waits for replies.
+pub const DEFAULT_ROUND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The default number of rounds in a row a device may miss before it counts as gone.
+pub const DEFAULT_MISSED_ROUNDS: u32 = 3;
+
+/// A change in the devices seen by a [DiscoveryWatcher].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DiscoveryEvent {
+    /// A device was found that wasn't known before, or had disappeared.
+    Appeared { device: DeviceIdentifier },
+    /// A known device answered from another IP address.
+    IpChanged {
+        device: DeviceIdentifier,
+        previous_ip: Ipv4Addr,
+    },
+    /// A known device reported another name.
+    Renamed {
+        device: DeviceIdentifier,
+        previous_name: String,
+    },
+    /// A device missed too many rounds in a row and was removed from the registry.
+    Disappeared { device: DeviceIdentifier },
+}
+
+impl Display for DiscoveryEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiscoveryEvent::Appeared { device } => write!(
+                f,
+                "Appeared: {} ({}) at {}",
+                device.device_name, device.mac_address, device.ip_address
+            ),
+            DiscoveryEvent::IpChanged {
+                device,
+                previous_ip,
+            } => write!(
+                f,
+                "IP changed: {} ({}) moved from {} to {}",
+                device.device_name, device.mac_address, previous_ip, device.ip_address
+            ),
+            DiscoveryEvent::Renamed {
+                device,
+                previous_name,
+            } => write!(
+                f,
+                "Renamed: {} ({}) is now {}",
+                previous_name, device.mac_address, device.device_name
+            ),
+            DiscoveryEvent::Disappeared { device } => write!(
+                f,
+                "Disappeared: {} ({}), last seen at {}",
+                device.device_name, device.mac_address, device.ip_address
+            ),
+        }
+    }
+}
+
+/// A device in the registry, with the number of rounds in a row it didn't answer.
+#[derive(Debug, Clone)]
+struct WatchedDevice {
+    device: DeviceIdentifier,
+    missed_rounds: u32,
+}
+
+/**
+Re-runs discovery on an interval and reports the devices appearing, changing and disappearing.
+
+```no_run
+# use glow_control_lib::util::discovery_watcher::DiscoveryWatcher;
+# use std::time::Duration;
+# async fn watch() {
+let mut events = DiscoveryWatcher::new(Duration::from_secs(30))
+    .with_missed_rounds(2)
+    .spawn();
+while let Some(event) = events.recv().await {
+    println!("{}", event);
+}
+# }
+```
+ */
+#[derive(Debug, Clone)]
+pub struct DiscoveryWatcher {
+    interval: Duration,
+    options: DiscoveryOptions,
+    missed_rounds: u32,
+    devices: HashMap<String, WatchedDevice>,
+}
+
+impl DiscoveryWatcher {
+    /// Creates a watcher starting a discovery round every `interval`, with an empty registry.
+    pub fn new(interval: Duration) -> Self {
+        DiscoveryWatcher {
+            interval,
+            options: DiscoveryOptions::new(DEFAULT_ROUND_TIMEOUT.min(interval)),
+            missed_rounds: DEFAULT_MISSED_ROUNDS,
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Sets how long each round waits for replies, capped at the interval.
+    pub fn with_round_timeout(mut self, round_timeout: Duration) -> Self {
+        self.options.timeout = round_timeout.min(self.interval);
+        self
+    }
+
+    /// Sets where the pings are sent. The timeout is capped at the interval.
+    pub fn with_options(mut self, options: DiscoveryOptions) -> Self {
+        self.options = options;
+        self.options.timeout = self.options.timeout.min(self.interval);
+        self
+    }
+
+    /// Sets how many rounds in a row a device may miss before it's reported as disappeared.
+    pub fn with_missed_rounds(mut self, missed_rounds: u32) -> Self {
+        self.missed_rounds = missed_rounds.max(1);
+        self
+    }
+
+    /**
+    Starts with already known devices, e.g. from [Discovery::find_devices].
+
+    No [DiscoveryEvent::Appeared] is reported for them, and their auth tokens are kept.
+     */
+    pub fn with_devices(mut self, devices: impl IntoIterator<Item = DeviceIdentifier>) -> Self {
+        for device in devices {
+            self.devices.insert(
+                device.mac_address.clone(),
+                WatchedDevice {
+                    device,
+                    missed_rounds: 0,
+                },
+            );
+        }
+        self
+    }
+
+    /// The devices currently in the registry.
+    pub fn devices(&self) -> impl Iterator<Item = &DeviceIdentifier> {
+        self.devices.values().map(|watched| &watched.device)
+    }
+
+    /**
+    Runs one discovery round and updates the registry.
+
+    The devices are identified concurrently, up to [DiscoveryOptions::concurrency] at a time.
+    Devices failing to answer their gestalt request or to authenticate, or taking longer than
+    [DiscoveryOptions::identify_timeout], are logged and count as missing in this round.
+     */
+    pub async fn round(&mut self) -> Result<Vec<DiscoveryEvent>> {
+        let responses = Discovery::collect_responses(&self.options).await?;
+        let found: Vec<DeviceIdentifier> = stream::iter(responses)
+            .map(|response| Discovery::identify_within(&self.options, self.identify(response)))
+            .buffer_unordered(self.options.concurrency.max(1))
+            .filter_map(|device| {
+                ready(
+                    device
+                        .map_err(|e| error!("Failed to identify device: {}", e))
+                        .ok(),
+                )
+            })
+            .collect()
+            .await;
+        Ok(self.update(found))
+    }
+
+    /// Identifies a discovery response from its gestalt info, keeping the token of a known device.
+    async fn identify(&self, response: DiscoveryResponse) -> Result<DeviceIdentifier> {
+        let host = self.options.http_host(response.ip_address());
+        let gestalt_info = Discovery::fetch_gestalt_info(&host).await?;
+        let known_token = self
+            .devices
+            .get(&gestalt_info.mac)
+            .map(|watched| watched.device.auth_token.clone());
+        let device = Discovery::device_from_gestalt(response, gestalt_info)?;
+        match known_token {
+            Some(auth_token) => Ok(DeviceIdentifier {
+                auth_token,
+                ..device
+            }),
+            None if self.options.authenticate => {
+                Discovery::authenticate_device(device, &host).await
+            }
+            None => Ok(device),
+        }
+    }
+
+    /**
+    Updates the registry with the devices found in one round, returning the changes.
+
+    Devices not in `found` count one more missed round, and are removed and reported as
+    [DiscoveryEvent::Disappeared] once they reach the limit set by [Self::with_missed_rounds].
+     */
+    pub fn update(
+        &mut self,
+        found: impl IntoIterator<Item = DeviceIdentifier>,
+    ) -> Vec<DiscoveryEvent> {
+        let mut events = Vec::new();
+        let mut seen = HashSet::new();
+
+        for device in found {
+            seen.insert(device.mac_address.clone());
+            let Some(watched) = self.devices.get_mut(&device.mac_address) else {
+                events.push(DiscoveryEvent::Appeared {
+                    device: device.clone(),
+                });
+                self.devices.insert(
+                    device.mac_address.clone(),
+                    WatchedDevice {
+                        device,
+                        missed_rounds: 0,
+                    },
+                );
+                continue;
+            };
+            watched.missed_rounds = 0;
+            if watched.device.ip_address != device.ip_address {
+                events.push(DiscoveryEvent::IpChanged {
+                    device: device.clone(),
+                    previous_ip: watched.device.ip_address,
+                });
+            }
+            if watched.device.device_name != device.device_name {
+                events.push(DiscoveryEvent::Renamed {
+                    device: device.clone(),
+                    previous_name: watched.device.device_name.clone(),
+                });
+            }
+            watched.device = device;
+        }
+
+        let mut gone: Vec<String> = Vec::new();
+        for (mac, watched) in self.devices.iter_mut() {
+            if !seen.contains(mac) {
+                watched.missed_rounds += 1;
+                debug!(
+                    "Device {} missed {} round(s)",
+                    watched.device.device_name, watched.missed_rounds
+                );
+                if watched.missed_rounds >= self.missed_rounds {
+                    gone.push(mac.clone());
+                }
+            }
+        }
+        gone.sort();
+        for mac in gone {
+            if let Some(watched) = self.devices.remove(&mac) {
+                events.push(DiscoveryEvent::Disappeared {
+                    device: watched.device,
+                });
+            }
+        }
+        events
+    }
+
+    /**
+    Runs rounds in the background, sending the events on the returned channel.
+
+    The first round starts right away. The watcher stops when the receiver is dropped.
+    Rounds failing as a whole, e.g. because the broadcast couldn't be sent, are logged
+    and retried on the next interval.
+     */
+    pub fn spawn(mut self) -> mpsc::Receiver<DiscoveryEvent> {
+        let (sender, receiver) = mpsc::channel(64);
+        tokio::spawn(async move {
+            loop {
+                let next_round = Instant::now() + self.interval;
+                let events = tokio::select! {
+                    result = self.round() => result,
+                    _ = sender.closed() => return,
+                };
+                match events {
+                    Ok(events) => {
+                        for event in events {
+                            if sender.send(event).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => error!("Discovery round failed: {}", e),
+                }
+                tokio::select! {
+                    _ = sleep_until(next_round) => {}
+                    _ = sender.closed() => return,
+                }
+            }
+        });
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::discovery::tests::device;
+
+    #[test]
+    fn test_appear_change_and_rename() {
+        let mut watcher = DiscoveryWatcher::new(DEFAULT_WATCH_INTERVAL);
+        let porch = device("aa", [10, 0, 0, 2], "Porch");
+        assert_eq!(
+            watcher.update([porch.clone()]),
+            vec![DiscoveryEvent::Appeared {
+                device: porch.clone()
+            }]
+        );
+        assert!(watcher.update([porch.clone()]).is_empty());
+
+        let moved = device("aa", [10, 0, 0, 7], "Porch left");
+        assert_eq!(
+            watcher.update([moved.clone()]),
+            vec![
+                DiscoveryEvent::IpChanged {
+                    device: moved.clone(),
+                    previous_ip: porch.ip_address,
+                },
+                DiscoveryEvent::Renamed {
+                    device: moved.clone(),
+                    previous_name: "Porch".to_string(),
+                },
+            ]
+        );
+        assert_eq!(watcher.devices().collect::<Vec<_>>(), vec![&moved]);
+    }
+
+    #[test]
+    fn test_disappear_after_missed_rounds() {
+        let tree = device("bb", [10, 0, 0, 3], "Tree");
+        let mut watcher = DiscoveryWatcher::new(DEFAULT_WATCH_INTERVAL)
+            .with_missed_rounds(2)
+            .with_devices([tree.clone()]);
+
+        assert!(watcher.update([]).is_empty());
+        // Answering again resets the count
+        assert!(watcher.update([tree.clone()]).is_empty());
+        assert!(watcher.update([]).is_empty());
+        assert_eq!(
+            watcher.update([]),
+            vec![DiscoveryEvent::Disappeared {
+                device: tree.clone()
+            }]
+        );
+        assert_eq!(watcher.devices().count(), 0);
+        assert_eq!(
+            watcher.update([tree.clone()]),
+            vec![DiscoveryEvent::Appeared { device: tree }]
+        );
+    }
+}
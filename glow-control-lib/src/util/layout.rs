@@ -0,0 +1,490 @@
+/*!
+LED layouts as files, and the transformations to correct them before uploading.
+
+Two file formats are supported, chosen by the file extension when loading:
+
+- JSON: an object with `source` and `coordinates`, so the response of
+  [ControlInterface::fetch_layout](crate::control_interface::ControlInterface::fetch_layout) can
+  be loaded directly.
+- CSV: a header line `x,y,z`, or `x,y` for a [LayoutSource::TwoD] layout, followed by one line of
+  coordinates per LED. Empty lines and lines starting with `#` are skipped.
+ */
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::control_interface::{Axis, LayoutSource, LedCoordinate};
+use crate::error::{Error, Result};
+
+/// The file formats a [Layout] can be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LayoutFileFormat {
+    Json,
+    Csv,
+}
+
+impl LayoutFileFormat {
+    /// The format for the extension of `path`, CSV for `.csv` and JSON otherwise.
+    pub fn for_path<P: AsRef<Path>>(path: P) -> LayoutFileFormat {
+        match path.as_ref().extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => LayoutFileFormat::Csv,
+            _ => LayoutFileFormat::Json,
+        }
+    }
+}
+
+/// The positions of the LEDs, in the order of the LEDs on the string.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub source: LayoutSource,
+    pub coordinates: Vec<LedCoordinate>,
+}
+
+impl Layout {
+    /// Loads a layout in the format given by the extension of `path`, see [LayoutFileFormat::for_path].
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Layout> {
+        let reader = BufReader::new(File::open(&path)?);
+        match LayoutFileFormat::for_path(&path) {
+            LayoutFileFormat::Json => Self::read_json(reader),
+            LayoutFileFormat::Csv => Self::read_csv(reader),
+        }
+    }
+
+    pub fn read_json<R: Read>(reader: R) -> Result<Layout> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn read_csv<R: BufRead>(reader: R) -> Result<Layout> {
+        let mut lines = reader
+            .lines()
+            .enumerate()
+            .map(|(index, line)| line.map(|line| (index + 1, line)))
+            .filter(|line| {
+                line.as_ref().map_or(true, |(_, line)| {
+                    let line = line.trim();
+                    !line.is_empty() && !line.starts_with('#')
+                })
+            });
+
+        let header = match lines.next() {
+            Some(line) => line?.1,
+            None => return Err(Error::LayoutFormat("The CSV file is empty".to_string())),
+        };
+        let columns: Vec<String> = header
+            .split(',')
+            .map(|column| column.trim().to_lowercase())
+            .collect();
+        let source = match columns.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["x", "y", "z"] => LayoutSource::ThreeD,
+            ["x", "y"] => LayoutSource::TwoD,
+            _ => {
+                return Err(Error::LayoutFormat(format!(
+                    "Expected a header of x,y,z or x,y, got {}",
+                    header
+                )))
+            }
+        };
+
+        let mut coordinates = Vec::new();
+        for line in lines {
+            let (number, line) = line?;
+            let values = line
+                .split(',')
+                .map(|value| value.trim().parse::<f64>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .ok()
+                .filter(|values| values.len() == columns.len())
+                .ok_or_else(|| {
+                    Error::LayoutFormat(format!(
+                        "Line {}: expected {} numbers, got {}",
+                        number,
+                        columns.len(),
+                        line
+                    ))
+                })?;
+            coordinates.push(LedCoordinate {
+                x: values[0],
+                y: values[1],
+                z: values.get(2).copied().unwrap_or(0.0),
+            });
+        }
+        Ok(Layout {
+            source,
+            coordinates,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: LayoutFileFormat) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            LayoutFileFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, self)?;
+                writeln!(writer)?;
+            }
+            LayoutFileFormat::Csv => self.write_csv(&mut writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the CSV format, leaving out `z` for a [LayoutSource::TwoD] layout.
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let two_d = self.source == LayoutSource::TwoD;
+        writeln!(writer, "{}", if two_d { "x,y" } else { "x,y,z" })?;
+        for led in &self.coordinates {
+            if two_d {
+                writeln!(writer, "{},{}", led.x, led.y)?;
+            } else {
+                writeln!(writer, "{},{},{}", led.x, led.y, led.z)?;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    Checks that the layout can be uploaded to a device with `number_of_led` LEDs.
+
+    All coordinates must be finite and in the ranges of the [LayoutSource]. Every problem found is
+    listed in the [Error::LayoutFormat].
+     */
+    pub fn validate(&self, number_of_led: Option<usize>) -> Result<()> {
+        let mut problems = Vec::new();
+        if self.coordinates.is_empty() {
+            problems.push("The layout has no LEDs".to_string());
+        }
+        if let Some(number_of_led) = number_of_led {
+            if self.coordinates.len() != number_of_led {
+                problems.push(format!(
+                    "The layout has {} LEDs, but the device has {}",
+                    self.coordinates.len(),
+                    number_of_led
+                ));
+            }
+        }
+
+        let report = |problems: &mut Vec<String>, what: &str, leds: Vec<usize>| {
+            if let Some(first) = leds.first() {
+                problems.push(format!(
+                    "{} LEDs {}, the first is LED {}",
+                    leds.len(),
+                    what,
+                    first
+                ));
+            }
+        };
+        let leds_where = |check: &dyn Fn(&LedCoordinate) -> bool| {
+            self.coordinates
+                .iter()
+                .enumerate()
+                .filter(|(_, led)| check(led))
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>()
+        };
+        report(
+            &mut problems,
+            "have coordinates that aren't finite",
+            leds_where(&|led| !(led.x.is_finite() && led.y.is_finite() && led.z.is_finite())),
+        );
+        let in_range = |value: f64, min: f64| (min..=1.0).contains(&value);
+        match self.source {
+            LayoutSource::Linear => {}
+            LayoutSource::TwoD => {
+                report(
+                    &mut problems,
+                    "are outside of x in -1..=1 and y in 0..=1",
+                    leds_where(&|led| !(in_range(led.x, -1.0) && in_range(led.y, 0.0))),
+                );
+                report(
+                    &mut problems,
+                    "have a z other than 0 in a 2D layout",
+                    leds_where(&|led| led.z != 0.0),
+                );
+            }
+            LayoutSource::ThreeD => report(
+                &mut problems,
+                "are outside of x and z in -1..=1 and y in 0..=1",
+                leds_where(&|led| {
+                    !(in_range(led.x, -1.0) && in_range(led.y, 0.0) && in_range(led.z, -1.0))
+                }),
+            ),
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::LayoutFormat(problems.join("; ")))
+        }
+    }
+
+    /// Moves all LEDs by the given offsets.
+    pub fn translate(&mut self, dx: f64, dy: f64, dz: f64) {
+        for led in &mut self.coordinates {
+            led.x += dx;
+            led.y += dy;
+            led.z += dz;
+        }
+    }
+
+    /// Scales all coordinates by the given factors, relative to the origin.
+    pub fn scale(&mut self, sx: f64, sy: f64, sz: f64) {
+        for led in &mut self.coordinates {
+            led.x *= sx;
+            led.y *= sy;
+            led.z *= sz;
+        }
+    }
+
+    /**
+    Rotates the layout by `degrees` around `axis` through the center of its bounding box.
+
+    Positive angles turn counterclockwise when looking from the positive end of the axis towards
+    the origin.
+     */
+    pub fn rotate(&mut self, axis: Axis, degrees: f64) {
+        let center = self.center();
+        let (sin, cos) = (degrees * PI / 180.0).sin_cos();
+        for led in &mut self.coordinates {
+            let (x, y, z) = (led.x - center.x, led.y - center.y, led.z - center.z);
+            let (x, y, z) = match axis {
+                Axis::X => (x, y * cos - z * sin, y * sin + z * cos),
+                Axis::Y => (x * cos + z * sin, y, -x * sin + z * cos),
+                Axis::Z => (x * cos - y * sin, x * sin + y * cos, z),
+            };
+            led.x = x + center.x;
+            led.y = y + center.y;
+            led.z = z + center.z;
+        }
+    }
+
+    /// Flips the layout along `axis`, keeping its bounding box in place.
+    pub fn mirror(&mut self, axis: Axis) {
+        let (min, max) = self.bounds(axis);
+        for led in &mut self.coordinates {
+            match axis {
+                Axis::X => led.x = min + max - led.x,
+                Axis::Y => led.y = min + max - led.y,
+                Axis::Z => led.z = min + max - led.z,
+            }
+        }
+    }
+
+    /// Reverses the order of the LEDs, for strings wired from the other end.
+    pub fn reverse(&mut self) {
+        self.coordinates.reverse();
+    }
+
+    /**
+    Reorders the LEDs, so the LED at `order[i]` becomes LED `i`.
+
+    Fails with [Error::InvalidArgument] unless `order` contains every LED index exactly once.
+     */
+    pub fn reorder(&mut self, order: &[usize]) -> Result<()> {
+        let mut seen = vec![false; self.coordinates.len()];
+        for &index in order {
+            match seen.get_mut(index) {
+                Some(seen) if !*seen => *seen = true,
+                Some(_) => {
+                    return Err(Error::InvalidArgument(format!(
+                        "LED {} appears more than once in the order",
+                        index
+                    )))
+                }
+                None => {
+                    return Err(Error::InvalidArgument(format!(
+                        "LED {} is beyond the {} LEDs of the layout",
+                        index,
+                        self.coordinates.len()
+                    )))
+                }
+            }
+        }
+        if let Some(missing) = seen.iter().position(|seen| !seen) {
+            return Err(Error::InvalidArgument(format!(
+                "LED {} is missing from the order",
+                missing
+            )));
+        }
+        self.coordinates = order.iter().map(|&index| self.coordinates[index]).collect();
+        Ok(())
+    }
+
+    /**
+    Scales and moves the layout uniformly so it spans the ranges of its source: `y` from 0 to 1
+    at most, and `x` and `z` centered on 0 within -1..=1. Keeps the proportions of the layout.
+     */
+    pub fn normalize(&mut self) {
+        let (min_x, max_x) = self.bounds(Axis::X);
+        let (min_y, max_y) = self.bounds(Axis::Y);
+        let (min_z, max_z) = self.bounds(Axis::Z);
+        let extent = [(max_x - min_x) / 2.0, max_y - min_y, (max_z - min_z) / 2.0]
+            .into_iter()
+            .fold(0.0, f64::max);
+        let scale = if extent > 0.0 { 1.0 / extent } else { 1.0 };
+        let center = self.center();
+        for led in &mut self.coordinates {
+            led.x = (led.x - center.x) * scale;
+            led.y = (led.y - min_y) * scale;
+            led.z = (led.z - center.z) * scale;
+        }
+    }
+
+    /// The minimum and maximum of the coordinates along `axis`, zero for an empty layout.
+    pub fn bounds(&self, axis: Axis) -> (f64, f64) {
+        if self.coordinates.is_empty() {
+            return (0.0, 0.0);
+        }
+        self.coordinates
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), led| {
+                (min.min(led.along(axis)), max.max(led.along(axis)))
+            })
+    }
+
+    fn center(&self) -> LedCoordinate {
+        let middle = |axis| {
+            let (min, max) = self.bounds(axis);
+            (min + max) / 2.0
+        };
+        LedCoordinate {
+            x: middle(Axis::X),
+            y: middle(Axis::Y),
+            z: middle(Axis::Z),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn led(x: f64, y: f64, z: f64) -> LedCoordinate {
+        LedCoordinate { x, y, z }
+    }
+
+    fn layout(coordinates: Vec<LedCoordinate>) -> Layout {
+        Layout {
+            source: LayoutSource::ThreeD,
+            coordinates,
+        }
+    }
+
+    fn assert_close(actual: &[LedCoordinate], expected: &[LedCoordinate]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a.x - e.x).abs() < 1e-9 && (a.y - e.y).abs() < 1e-9 && (a.z - e.z).abs() < 1e-9,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let original = layout(vec![led(-0.5, 0.0, 0.25), led(1.0, 0.75, -1.0)]);
+        let mut csv = Vec::new();
+        original.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv.clone()).unwrap(),
+            "x,y,z\n-0.5,0,0.25\n1,0.75,-1\n"
+        );
+        assert_eq!(Layout::read_csv(&csv[..]).unwrap(), original);
+    }
+
+    #[test]
+    fn test_csv_2d_and_errors() {
+        let csv = "# measured by hand\nx, y\n\n0.5, 0.25\n-0.5,1\n";
+        let parsed = Layout::read_csv(csv.as_bytes()).unwrap();
+        assert_eq!(parsed.source, LayoutSource::TwoD);
+        assert_eq!(
+            parsed.coordinates,
+            vec![led(0.5, 0.25, 0.0), led(-0.5, 1.0, 0.0)]
+        );
+
+        assert!(Layout::read_csv("a,b\n1,2\n".as_bytes()).is_err());
+        let error = Layout::read_csv("x,y\n1,2\n1,2,3\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("Line 3"), "{}", error);
+    }
+
+    #[test]
+    fn test_json_accepts_the_device_response() {
+        let response = r#"{"source":"2d","synthesized":false,"uuid":"x","code":1000,
+            "coordinates":[{"x":0.0,"y":1.0,"z":0.0}]}"#;
+        let parsed = Layout::read_json(response.as_bytes()).unwrap();
+        assert_eq!(parsed.source, LayoutSource::TwoD);
+        assert_eq!(parsed.coordinates, vec![led(0.0, 1.0, 0.0)]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = layout(vec![led(-1.0, 0.0, 1.0), led(0.0, 1.0, 0.0)]);
+        assert!(valid.validate(Some(2)).is_ok());
+
+        let error = valid.validate(Some(3)).unwrap_err().to_string();
+        assert!(error.contains("3"), "{}", error);
+
+        let invalid = Layout {
+            source: LayoutSource::TwoD,
+            coordinates: vec![led(0.0, 0.0, 0.0), led(2.0, 0.5, 0.0), led(0.0, 0.5, 0.1)],
+        };
+        let error = invalid.validate(None).unwrap_err().to_string();
+        assert!(
+            error.contains("1 LEDs are outside") && error.contains("the first is LED 1"),
+            "{}",
+            error
+        );
+        assert!(error.contains("z other than 0"), "{}", error);
+    }
+
+    #[test]
+    fn test_transforms() {
+        let mut transformed = layout(vec![led(0.0, 0.0, 0.0), led(2.0, 1.0, 0.0)]);
+        transformed.rotate(Axis::Z, 90.0);
+        // Rotated around the center (1, 0.5, 0)
+        assert_close(
+            &transformed.coordinates,
+            &[led(1.5, -0.5, 0.0), led(0.5, 1.5, 0.0)],
+        );
+
+        transformed.mirror(Axis::X);
+        assert_close(
+            &transformed.coordinates,
+            &[led(0.5, -0.5, 0.0), led(1.5, 1.5, 0.0)],
+        );
+
+        transformed.normalize();
+        assert_close(
+            &transformed.coordinates,
+            &[led(-0.25, 0.0, 0.0), led(0.25, 1.0, 0.0)],
+        );
+        assert!(transformed.validate(None).is_ok());
+
+        transformed.scale(2.0, 1.0, 1.0);
+        transformed.translate(0.0, 0.0, 0.5);
+        assert_close(
+            &transformed.coordinates,
+            &[led(-0.5, 0.0, 0.5), led(0.5, 1.0, 0.5)],
+        );
+    }
+
+    #[test]
+    fn test_reorder() {
+        let leds = vec![led(0.0, 0.0, 0.0), led(0.0, 0.5, 0.0), led(0.0, 1.0, 0.0)];
+        let mut reordered = layout(leds.clone());
+        reordered.reorder(&[2, 0, 1]).unwrap();
+        assert_eq!(reordered.coordinates, vec![leds[2], leds[0], leds[1]]);
+
+        reordered.reverse();
+        assert_eq!(reordered.coordinates, vec![leds[1], leds[0], leds[2]]);
+
+        assert!(reordered.reorder(&[0, 1]).is_err());
+        assert!(reordered.reorder(&[0, 1, 1]).is_err());
+        assert!(reordered.reorder(&[0, 1, 3]).is_err());
+    }
+}
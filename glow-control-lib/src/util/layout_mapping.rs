@@ -0,0 +1,609 @@
+/*!
+Mapping the LED layout from photos, without the Twinkly app.
+
+Mapping takes two steps:
+
+1. [ControlInterface::play_mapping_sequence] lights the LEDs in a [MappingSequence] in real-time
+   mode, while the user takes a photo of every step from the same spot, with any camera.
+2. [LedMapping::from_photos] finds the lit LEDs in the photos and
+   [LedMapping::to_layout] turns their positions into a 2D [Layout] for
+   [ControlInterface::upload_layout].
+
+The second step only works on files, so it can run on another machine, or on synthetic images.
+ */
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use image::GrayImage;
+
+use crate::control_interface::{ControlInterface, LayoutSource, LedCoordinate};
+use crate::error::{Error, Result};
+use crate::util::layout::Layout;
+use crate::util::movie_image::image_error;
+
+/// The file extensions of photos picked up by [photo_paths].
+const PHOTO_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+/**
+How the LEDs are told apart in the photos.
+
+Both sequences start with a photo of the dark LEDs, which is subtracted from the other photos so
+that lamps, reflections and screens in the background are ignored.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum MappingSequence {
+    /// One photo per LED, with only that LED lit. Slow, but tolerant of blurry photos.
+    Indexed,
+    /**
+    A photo with all LEDs lit, then one photo per bit of the LED numbers, with the LEDs lit whose
+    number, counted from 1, has that bit set. 250 LEDs need 10 photos.
+     */
+    #[default]
+    Binary,
+}
+
+impl MappingSequence {
+    /// The number of photos needed to map `number_of_led` LEDs.
+    pub fn step_count(&self, number_of_led: usize) -> usize {
+        match self {
+            MappingSequence::Indexed => 1 + number_of_led,
+            MappingSequence::Binary => 2 + code_bits(number_of_led),
+        }
+    }
+
+    /// The frame shown for each step, with the lit LEDs in `color`.
+    pub fn frames(&self, number_of_led: usize, color: (u8, u8, u8)) -> Vec<Vec<(u8, u8, u8)>> {
+        let dark = vec![(0, 0, 0); number_of_led];
+        let lit_where = |lit: &dyn Fn(usize) -> bool| {
+            (0..number_of_led)
+                .map(|led| if lit(led) { color } else { (0, 0, 0) })
+                .collect::<Vec<_>>()
+        };
+        let mut frames = vec![dark];
+        match self {
+            MappingSequence::Indexed => {
+                frames.extend((0..number_of_led).map(|step| lit_where(&|led| led == step)));
+            }
+            MappingSequence::Binary => {
+                frames.push(lit_where(&|_| true));
+                frames.extend(
+                    (0..code_bits(number_of_led))
+                        .map(|bit| lit_where(&|led| (led + 1) >> bit & 1 == 1)),
+                );
+            }
+        }
+        frames
+    }
+}
+
+/// How lit LEDs are detected in the photos.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MappingOptions {
+    /// How much brighter than in the dark photo a pixel must be to count as lit, out of 255.
+    pub threshold: u8,
+    /// Spots with fewer lit pixels are taken for noise.
+    pub min_spot_pixels: usize,
+}
+
+impl Default for MappingOptions {
+    fn default() -> Self {
+        MappingOptions {
+            threshold: 40,
+            min_spot_pixels: 4,
+        }
+    }
+}
+
+/// The positions of the LEDs found in the photos, in pixels from the top left corner.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedMapping {
+    /// The position of each LED, `None` for LEDs that weren't found.
+    pub positions: Vec<Option<(f64, f64)>>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl LedMapping {
+    /// Locates the LEDs in photo files, one per step of `sequence` in order, see [photo_paths].
+    pub fn from_photos<P: AsRef<Path>>(
+        paths: &[P],
+        sequence: MappingSequence,
+        number_of_led: usize,
+        options: &MappingOptions,
+    ) -> Result<LedMapping> {
+        let photos = paths
+            .iter()
+            .map(|path| Ok(image::open(path).map_err(image_error)?.into_luma8()));
+        Self::analyze(photos, paths.len(), sequence, number_of_led, options)
+    }
+
+    /// Locates the LEDs in photos already loaded, one per step of `sequence` in order.
+    pub fn from_images(
+        images: &[GrayImage],
+        sequence: MappingSequence,
+        number_of_led: usize,
+        options: &MappingOptions,
+    ) -> Result<LedMapping> {
+        Self::analyze(
+            images.iter().map(Ok),
+            images.len(),
+            sequence,
+            number_of_led,
+            options,
+        )
+    }
+
+    /// The LEDs that weren't found in the photos.
+    pub fn missing(&self) -> Vec<usize> {
+        self.positions
+            .iter()
+            .enumerate()
+            .filter(|(_, position)| position.is_none())
+            .map(|(led, _)| led)
+            .collect()
+    }
+
+    /**
+    Converts the positions into a normalized 2D layout, with `y` pointing up.
+
+    Missing LEDs are placed between their nearest found neighbors on the string. Fails with
+    [Error::LayoutFormat] if no LED was found at all.
+     */
+    pub fn to_layout(&self) -> Result<Layout> {
+        let found: Vec<(usize, (f64, f64))> = self
+            .positions
+            .iter()
+            .enumerate()
+            .filter_map(|(led, position)| position.map(|position| (led, position)))
+            .collect();
+        if found.is_empty() {
+            return Err(Error::LayoutFormat(
+                "No LEDs were found in the photos".to_string(),
+            ));
+        }
+
+        let coordinates = (0..self.positions.len())
+            .map(|led| {
+                let after = found.partition_point(|&(found_led, _)| found_led < led);
+                let (x, y) = match (after.checked_sub(1).map(|i| found[i]), found.get(after)) {
+                    (_, Some(&(next, position))) if next == led => position,
+                    (Some((previous, (x0, y0))), Some(&(next, (x1, y1)))) => {
+                        let t = (led - previous) as f64 / (next - previous) as f64;
+                        (x0 + (x1 - x0) * t, y0 + (y1 - y0) * t)
+                    }
+                    (Some((_, position)), None) | (None, Some(&(_, position))) => position,
+                    (None, None) => unreachable!("at least one LED was found"),
+                };
+                LedCoordinate {
+                    x,
+                    y: self.height as f64 - y,
+                    z: 0.0,
+                }
+            })
+            .collect();
+        let mut layout = Layout {
+            source: LayoutSource::TwoD,
+            coordinates,
+        };
+        layout.normalize();
+        Ok(layout)
+    }
+
+    fn analyze<I, B>(
+        mut photos: I,
+        photo_count: usize,
+        sequence: MappingSequence,
+        number_of_led: usize,
+        options: &MappingOptions,
+    ) -> Result<LedMapping>
+    where
+        I: Iterator<Item = Result<B>>,
+        B: Borrow<GrayImage>,
+    {
+        let expected = sequence.step_count(number_of_led);
+        if photo_count != expected {
+            return Err(Error::InvalidArgument(format!(
+                "Mapping {} LEDs with the {:?} sequence needs {} photos, got {}",
+                number_of_led, sequence, expected, photo_count
+            )));
+        }
+        let Some(dark) = photos.next() else {
+            return Err(Error::InvalidArgument("No photos given".to_string()));
+        };
+        let dark = dark?;
+        let dark = dark.borrow();
+        let (width, height) = dark.dimensions();
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidArgument(
+                "The first photo has no pixels".to_string(),
+            ));
+        }
+        let mut brightening = |step: usize| -> Result<Vec<u8>> {
+            let photo = photos.next().expect("the photo count was checked")?;
+            let photo = photo.borrow();
+            if photo.dimensions() != (width, height) {
+                return Err(Error::InvalidArgument(format!(
+                    "Photo {} is {}x{} pixels, but the first photo is {}x{}",
+                    step,
+                    photo.width(),
+                    photo.height(),
+                    width,
+                    height
+                )));
+            }
+            Ok(photo
+                .as_raw()
+                .iter()
+                .zip(dark.as_raw())
+                .map(|(&lit, &dark)| lit.saturating_sub(dark))
+                .collect())
+        };
+
+        let mut positions = vec![None; number_of_led];
+        match sequence {
+            MappingSequence::Indexed => {
+                for (led, position) in positions.iter_mut().enumerate() {
+                    let diff = brightening(led + 1)?;
+                    let (brightest, &peak) = diff
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|&(_, value)| value)
+                        .expect("photos have pixels");
+                    if peak < options.threshold {
+                        continue;
+                    }
+                    let mut visited = vec![false; diff.len()];
+                    let spot = flood(&diff, width, brightest, peak.div_ceil(2), &mut visited);
+                    if spot.len() >= options.min_spot_pixels {
+                        *position = Some(centroid(&spot, &diff, width));
+                    }
+                }
+            }
+            MappingSequence::Binary => {
+                let all_lit = brightening(1)?;
+                let spots = find_spots(&all_lit, width, options);
+                let brightness: Vec<f64> = spots.iter().map(|spot| mean(spot, &all_lit)).collect();
+                let mut codes = vec![0usize; spots.len()];
+                for bit in 0..code_bits(number_of_led) {
+                    let diff = brightening(bit + 2)?;
+                    for ((spot, brightness), code) in spots.iter().zip(&brightness).zip(&mut codes)
+                    {
+                        if mean(spot, &diff) > brightness / 2.0 {
+                            *code |= 1 << bit;
+                        }
+                    }
+                }
+
+                // With several spots decoding to the same LED, e.g. a reflection, keep the brightest
+                let mut strongest = vec![0.0; number_of_led];
+                for ((spot, code), brightness) in spots.iter().zip(codes).zip(brightness) {
+                    let Some(led) = code.checked_sub(1).filter(|&led| led < number_of_led) else {
+                        continue;
+                    };
+                    let strength = brightness * spot.len() as f64;
+                    if strength > strongest[led] {
+                        strongest[led] = strength;
+                        positions[led] = Some(centroid(spot, &all_lit, width));
+                    }
+                }
+            }
+        }
+        Ok(LedMapping {
+            positions,
+            width,
+            height,
+        })
+    }
+}
+
+impl ControlInterface {
+    /**
+    Shows the steps of `sequence` one after the other in real-time mode.
+
+    After each step is shown, `next` is awaited with the step index and the number of steps, e.g.
+    to wait until the user took a photo. The step stays lit until `next` returns. The previous
+    mode is restored at the end.
+     */
+    pub async fn play_mapping_sequence<F, Fut>(
+        &self,
+        sequence: MappingSequence,
+        color: (u8, u8, u8),
+        mut next: F,
+    ) -> Result<()>
+    where
+        F: FnMut(usize, usize) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let frames = sequence.frames(self.get_device_info().number_of_led, color);
+        let mut session = self.realtime_session().await?;
+        for (step, frame) in frames.iter().enumerate() {
+            session.send_frame(&self.pack_frame(frame)).await?;
+            next(step, frames.len()).await?;
+        }
+        session.close().await
+    }
+}
+
+/**
+The photos in `dir` in the order of their file names, which is the order cameras take them in.
+
+Numbers in the names are compared by value, so `IMG_9999` comes before `IMG_10000`.
+ */
+pub fn photo_paths<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_photo = path.extension().is_some_and(|extension| {
+            PHOTO_EXTENSIONS
+                .iter()
+                .any(|photo| extension.eq_ignore_ascii_case(photo))
+        });
+        if is_photo && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    Ok(paths)
+}
+
+/// Compares names like a person would, with runs of digits compared as numbers.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(first_a), Some(first_b)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        let ordering = if first_a.is_ascii_digit() && first_b.is_ascii_digit() {
+            let (digits_a, rest_a) = split_digits(a);
+            let (digits_b, rest_b) = split_digits(b);
+            (a, b) = (rest_a, rest_b);
+            let (value_a, value_b) = (
+                digits_a.trim_start_matches('0'),
+                digits_b.trim_start_matches('0'),
+            );
+            value_a
+                .len()
+                .cmp(&value_b.len())
+                .then_with(|| value_a.cmp(value_b))
+                .then_with(|| digits_a.len().cmp(&digits_b.len()))
+        } else {
+            (a, b) = (&a[first_a.len_utf8()..], &b[first_b.len_utf8()..]);
+            first_a.cmp(&first_b)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Splits the leading digits off `name`.
+fn split_digits(name: &str) -> (&str, &str) {
+    name.split_at(
+        name.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(name.len()),
+    )
+}
+
+/// The number of bits in the LED numbers 1 to `number_of_led`.
+fn code_bits(number_of_led: usize) -> usize {
+    (usize::BITS - number_of_led.leading_zeros()) as usize
+}
+
+/// The pixels connected to `start` that are at least `min` bright, marking them as visited.
+fn flood(diff: &[u8], width: u32, start: usize, min: u8, visited: &mut [bool]) -> Vec<usize> {
+    let width = width as usize;
+    let mut spot = Vec::new();
+    let mut stack = vec![start];
+    visited[start] = true;
+    while let Some(pixel) = stack.pop() {
+        spot.push(pixel);
+        let (x, y) = (pixel % width, pixel / width);
+        let neighbors = [
+            (x > 0).then(|| pixel - 1),
+            (x + 1 < width).then(|| pixel + 1),
+            (y > 0).then(|| pixel - width),
+            (pixel + width < diff.len()).then(|| pixel + width),
+        ];
+        for neighbor in neighbors.into_iter().flatten() {
+            if !visited[neighbor] && diff[neighbor] >= min {
+                visited[neighbor] = true;
+                stack.push(neighbor);
+            }
+        }
+    }
+    spot
+}
+
+/// The separate spots of lit pixels.
+fn find_spots(diff: &[u8], width: u32, options: &MappingOptions) -> Vec<Vec<usize>> {
+    let mut visited = vec![false; diff.len()];
+    let mut spots = Vec::new();
+    for pixel in 0..diff.len() {
+        if !visited[pixel] && diff[pixel] >= options.threshold {
+            let spot = flood(diff, width, pixel, options.threshold, &mut visited);
+            if spot.len() >= options.min_spot_pixels {
+                spots.push(spot);
+            }
+        }
+    }
+    spots
+}
+
+/// The center of the pixels, weighted by their brightness.
+fn centroid(spot: &[usize], diff: &[u8], width: u32) -> (f64, f64) {
+    let width = width as usize;
+    let (mut sum_x, mut sum_y, mut total) = (0.0, 0.0, 0.0);
+    for &pixel in spot {
+        let weight = diff[pixel] as f64;
+        // The center of the pixel
+        sum_x += ((pixel % width) as f64 + 0.5) * weight;
+        sum_y += ((pixel / width) as f64 + 0.5) * weight;
+        total += weight;
+    }
+    (sum_x / total, sum_y / total)
+}
+
+fn mean(spot: &[usize], diff: &[u8]) -> f64 {
+    spot.iter().map(|&pixel| diff[pixel] as f64).sum::<f64>() / spot.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEDS: [(f64, f64); 5] = [
+        (10.5, 40.5),
+        (20.5, 30.5),
+        (30.5, 20.5),
+        (45.5, 12.5),
+        (55.5, 36.5),
+    ];
+
+    /// A photo of the lit LEDs as soft spots, over a textured background.
+    fn photo(frame: &[(u8, u8, u8)], leds: &[(f64, f64)]) -> GrayImage {
+        GrayImage::from_fn(64, 48, |x, y| {
+            let background = 20.0 + ((x * 7 + y * 13) % 5) as f64;
+            let glow: f64 = frame
+                .iter()
+                .zip(leds)
+                .filter(|(color, _)| **color != (0, 0, 0))
+                .map(|(_, &(led_x, led_y))| {
+                    let distance2 =
+                        (x as f64 + 0.5 - led_x).powi(2) + (y as f64 + 0.5 - led_y).powi(2);
+                    200.0 * (-distance2 / 4.5).exp()
+                })
+                .sum();
+            image::Luma([(background + glow).min(255.0) as u8])
+        })
+    }
+
+    fn photos(sequence: MappingSequence, leds: &[(f64, f64)]) -> Vec<GrayImage> {
+        sequence
+            .frames(LEDS.len(), (255, 255, 255))
+            .iter()
+            .map(|frame| photo(frame, leds))
+            .collect()
+    }
+
+    fn assert_found(mapping: &LedMapping, leds: &[usize]) {
+        for &led in leds {
+            let (x, y) = mapping.positions[led].expect("LED not found");
+            assert!(
+                (x - LEDS[led].0).abs() < 0.25 && (y - LEDS[led].1).abs() < 0.25,
+                "LED {} found at {:?}, expected {:?}",
+                led,
+                (x, y),
+                LEDS[led]
+            );
+        }
+    }
+
+    #[test]
+    fn test_sequences() {
+        let indexed = MappingSequence::Indexed.frames(3, (1, 1, 1));
+        assert_eq!(indexed.len(), MappingSequence::Indexed.step_count(3));
+        assert_eq!(indexed[2], vec![(0, 0, 0), (1, 1, 1), (0, 0, 0)]);
+
+        let binary = MappingSequence::Binary.frames(3, (1, 1, 1));
+        assert_eq!(binary.len(), 4);
+        assert_eq!(MappingSequence::Binary.step_count(250), 10);
+        // LEDs 1, 2 and 3 blink 01, 10 and 11
+        assert_eq!(binary[2], vec![(1, 1, 1), (0, 0, 0), (1, 1, 1)]);
+        assert_eq!(binary[3], vec![(0, 0, 0), (1, 1, 1), (1, 1, 1)]);
+    }
+
+    #[test]
+    fn test_indexed_mapping() {
+        let sequence = MappingSequence::Indexed;
+        let mapping = LedMapping::from_images(
+            &photos(sequence, &LEDS),
+            sequence,
+            LEDS.len(),
+            &MappingOptions::default(),
+        )
+        .unwrap();
+
+        assert_found(&mapping, &[0, 1, 2, 3, 4]);
+        assert_eq!((mapping.width, mapping.height), (64, 48));
+    }
+
+    #[test]
+    fn test_binary_mapping() {
+        let sequence = MappingSequence::Binary;
+        let mapping = LedMapping::from_images(
+            &photos(sequence, &LEDS),
+            sequence,
+            LEDS.len(),
+            &MappingOptions::default(),
+        )
+        .unwrap();
+
+        assert_found(&mapping, &[0, 1, 2, 3, 4]);
+        assert!(mapping.missing().is_empty());
+    }
+
+    #[test]
+    fn test_hidden_leds_are_interpolated() {
+        // LED 2 is hidden behind a branch, far outside of the photo
+        let mut leds = LEDS;
+        leds[2] = (-100.0, -100.0);
+        let sequence = MappingSequence::Binary;
+        let mapping = LedMapping::from_images(
+            &photos(sequence, &leds),
+            sequence,
+            LEDS.len(),
+            &MappingOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(mapping.missing(), vec![2]);
+
+        let layout = mapping.to_layout().unwrap();
+        assert!(layout.validate(Some(LEDS.len())).is_ok());
+        let hidden = layout.coordinates[2];
+        let (before, after) = (layout.coordinates[1], layout.coordinates[3]);
+        assert!((hidden.x - (before.x + after.x) / 2.0).abs() < 1e-9);
+        assert!((hidden.y - (before.y + after.y) / 2.0).abs() < 1e-9);
+        // The photo's y points down, the layout's up
+        assert!(layout.coordinates[0].y < layout.coordinates[1].y);
+    }
+
+    #[test]
+    fn test_photo_count_and_size_are_checked() {
+        let sequence = MappingSequence::Binary;
+        let mut images = photos(sequence, &LEDS);
+        images.pop();
+        assert!(matches!(
+            LedMapping::from_images(&images, sequence, LEDS.len(), &MappingOptions::default()),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        images.push(GrayImage::new(10, 10));
+        assert!(matches!(
+            LedMapping::from_images(&images, sequence, LEDS.len(), &MappingOptions::default()),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        // Empty photos, e.g. from corrupt files, are rejected instead of panicking
+        let empty = vec![GrayImage::new(0, 0); sequence.step_count(LEDS.len())];
+        assert!(matches!(
+            LedMapping::from_images(&empty, sequence, LEDS.len(), &MappingOptions::default()),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_photos_in_natural_order() {
+        let mut names = vec!["IMG_10000.jpg", "IMG_9999.jpg", "IMG_0002.jpg", "img_1.jpg"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec!["IMG_0002.jpg", "IMG_9999.jpg", "IMG_10000.jpg", "img_1.jpg"]
+        );
+        assert_eq!(natural_cmp("a01", "a1"), Ordering::Greater);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+    }
+}
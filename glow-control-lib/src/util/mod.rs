@@ -1,6 +1,12 @@
 pub mod auth;
+pub mod device_registry;
 pub mod discovery;
+pub mod discovery_watcher;
 pub mod rc4;
 
+pub mod layout;
+pub mod layout_mapping;
 pub mod movie;
+pub mod movie_image;
+pub mod movie_preview;
 pub mod traits;
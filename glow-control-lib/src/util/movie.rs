@@ -1,98 +1,244 @@
+/*!
+Movies as sequences of frames, and the files they are stored in.
+
+Two file formats are supported, told apart by their first bytes when loading:
+
+- The text format: a header line `<frames> <leds> <bytes per LED> <fps>`, followed by one line of
+  hex encoded LED colors per frame.
+- The binary format, which is half the size and also records the layout and a title:
+
+| Size              | Content                                                            |
+|-------------------|--------------------------------------------------------------------|
+| 8                 | The magic bytes `GLOWMOVI`                                         |
+| 1                 | The format version, currently 1                                    |
+| 8                 | fps, little endian `f64`                                           |
+| 4                 | Number of frames, little endian `u32`                              |
+| 4                 | Number of LEDs, little endian `u32`                                |
+| 1                 | Bytes per LED, 3 for [LedProfile::RGB] or 4 for [LedProfile::RGBW] |
+| 1                 | Flags, bit 0: a layout UUID follows, bit 1: a title follows        |
+| 16                | The layout UUID, if flagged                                        |
+| 2 + n             | The title length as little endian `u16` and UTF-8 title, if flagged |
+| frames × LEDs × b | The frames, in the byte layout sent to the device                  |
+ */
+
 use crate::control_interface::LedProfile;
-use anyhow::Result;
+use crate::error::{Error, Result};
+use crate::led::rgbw;
+use crate::led::rgbw::WhiteExtraction;
+use clap::ValueEnum;
 use std::fs::File;
 use std::io;
 use std::io::Write;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, BufWriter, Read};
 use std::path::Path;
+use uuid::Uuid;
+
+/// The first bytes of a binary movie file.
+pub const BINARY_MOVIE_MAGIC: &[u8; 8] = b"GLOWMOVI";
+
+/// The version of the binary movie format written by [Movie::save_binary_movie].
+pub const BINARY_MOVIE_VERSION: u8 = 1;
+
+/// The most LEDs a binary movie file may have, far more than any device has.
+const MAX_BINARY_MOVIE_LEDS: usize = u16::MAX as usize;
 
+const FLAG_LAYOUT_UUID: u8 = 1;
+const FLAG_TITLE: u8 = 1 << 1;
+
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Movie {
     pub frames: Vec<Vec<(u8, u8, u8)>>,
     pub fps: f64,
+    /// The UUID of the LED layout the movie was made for, see
+    /// [LayoutResponse::uuid](crate::control_interface::LayoutResponse::uuid).
+    /// Only kept by the binary format.
+    pub layout_uuid: Option<Uuid>,
+    /// Only kept by the binary format.
+    pub title: Option<String>,
+}
+
+/// The file formats a [Movie] can be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MovieFileFormat {
+    /// A header line and one line of hex per frame.
+    Text,
+    /// The versioned binary container.
+    Binary,
 }
+
+/// The header of a movie file, read without loading the frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovieFileHeader {
+    pub format: MovieFileFormat,
+    pub frame_count: usize,
+    pub led_count: usize,
+    /// The profile matching the bytes per LED stored in the file.
+    pub led_profile: LedProfile,
+    pub fps: f64,
+    /// Always `None` for the text format.
+    pub layout_uuid: Option<Uuid>,
+    /// Always `None` for the text format.
+    pub title: Option<String>,
+}
+
 impl Movie {
     // ...
 
-    /// Converts a vector of frames into a binary movie format.
-    /// This function handles both RGB and RGBW LED profiles.
+    /**
+    Converts a vector of frames into a binary movie format.
+    This function handles both RGB and RGBW LED profiles, using [WhiteExtraction::MinSubtraction].
+    See [rgbw::pack_frames] for the other strategies.
+     */
     pub fn to_movie(frames: Vec<Vec<(u8, u8, u8)>>, led_profile: LedProfile) -> Vec<u8> {
-        let mut movie_data = Vec::new();
-        for frame in frames {
-            for &(r, g, b) in &frame {
-                match led_profile {
-                    LedProfile::RGB => {
-                        movie_data.push(r);
-                        movie_data.push(g);
-                        movie_data.push(b);
-                    }
-                    LedProfile::RGBW => {
-                        // Calculate the white component as the minimum of r, g, b
-                        let w = r.min(g).min(b);
-                        movie_data.push(r - w);
-                        movie_data.push(g - w);
-                        movie_data.push(b - w);
-                        movie_data.push(w);
-                    }
-                }
+        rgbw::pack_frames(&frames, led_profile, WhiteExtraction::default())
+    }
+
+    /**
+    Loads a movie file in either format, see [Self::detect_format].
+
+    Text files are decoded with `led_profile`. Binary files record their profile, which is used instead.
+    RGBW colors are decoded with [WhiteExtraction::MinSubtraction], see [Self::load] for the others.
+     */
+    pub fn load_movie<P: AsRef<Path>>(path: P, led_profile: LedProfile) -> Result<Self> {
+        Self::load(path, led_profile, WhiteExtraction::default())
+    }
+
+    /// Like [Self::load_movie], decoding RGBW colors with `white_extraction`.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        led_profile: LedProfile,
+        white_extraction: WhiteExtraction,
+    ) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        match detect_format(&mut reader)? {
+            MovieFileFormat::Text => Self::read_text_movie(reader, led_profile, white_extraction),
+            MovieFileFormat::Binary => Self::read_binary_movie(reader, white_extraction),
+        }
+    }
+
+    /// Tells the format of a movie file by its first bytes.
+    pub fn detect_format<P: AsRef<Path>>(path: P) -> Result<MovieFileFormat> {
+        detect_format(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Reads the header of a movie file in either format, without loading the frames.
+    pub fn read_header<P: AsRef<Path>>(path: P) -> Result<MovieFileHeader> {
+        let mut reader = BufReader::new(File::open(path)?);
+        match detect_format(&mut reader)? {
+            MovieFileFormat::Text => {
+                let (frame_count, led_count, bytes_per_led, fps) = read_text_header(&mut reader)?;
+                Ok(MovieFileHeader {
+                    format: MovieFileFormat::Text,
+                    frame_count,
+                    led_count,
+                    led_profile: led_profile_for(bytes_per_led)?,
+                    fps,
+                    layout_uuid: None,
+                    title: None,
+                })
             }
+            MovieFileFormat::Binary => read_binary_header(&mut reader),
         }
-        movie_data
     }
 
-    pub fn load_movie<P: AsRef<Path>>(path: P, led_profile: LedProfile) -> Result<Self> {
-        let file = File::open(path)?;
-        let mut reader = BufReader::new(file);
-
-        // Read the header
-        let mut header = String::new();
-        reader.read_line(&mut header)?;
-        let header_parts: Vec<&str> = header.split_whitespace().collect();
-        if header_parts.len() != 4 {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid header format").into());
+    /// Reads a movie in the text format.
+    pub fn read_text_movie<R: BufRead>(
+        mut reader: R,
+        led_profile: LedProfile,
+        white_extraction: WhiteExtraction,
+    ) -> Result<Self> {
+        let (num_frames, num_leds, bytes_per_led, fps) = read_text_header(&mut reader)?;
+        if bytes_per_led != led_profile.bytes_per_led() {
+            return Err(Error::MovieFormat(format!(
+                "The movie has {} bytes per LED, the {:?} profile uses {}",
+                bytes_per_led,
+                led_profile,
+                led_profile.bytes_per_led()
+            )));
         }
 
-        let num_frames: usize = header_parts[0].parse()?;
-        let num_leds: usize = header_parts[1].parse()?;
-        let bytes_per_led: usize = header_parts[2].parse()?;
-        let fps: f64 = header_parts[3].parse()?;
+        let frame_len = num_leds
+            .checked_mul(bytes_per_led)
+            .ok_or_else(|| Error::MovieFormat("The frame size overflows".to_string()))?;
 
-        // Read the frames
-        let mut frames = Vec::with_capacity(num_frames);
+        // Read the frames. The frame count isn't trusted for preallocation.
+        let mut frames = Vec::new();
         for _ in 0..num_frames {
             let mut frame_hex = String::new();
-            reader.read_line(&mut frame_hex)?;
-            let frame_bytes = hex::decode(frame_hex.trim())?;
-
-            // Convert frame data to RGB or RGBW tuples
-            let mut frame = Vec::with_capacity(num_leds);
-            for chunk in frame_bytes.chunks(bytes_per_led) {
-                let rgb_tuple = match led_profile {
-                    LedProfile::RGB => (chunk[0], chunk[1], chunk[2]),
-                    LedProfile::RGBW => {
-                        // Assuming the white component is the last byte
-                        let w = chunk[3];
-                        (chunk[0] + w, chunk[1] + w, chunk[2] + w)
-                    }
-                };
-                frame.push(rgb_tuple);
+            if reader.read_line(&mut frame_hex)? == 0 {
+                return Err(Error::MovieFormat("Truncated movie file".to_string()));
+            }
+            let frame_bytes = hex::decode(frame_hex.trim())
+                .map_err(|e| Error::MovieFormat(format!("Invalid frame data: {}", e)))?;
+            if frame_bytes.len() != frame_len {
+                return Err(Error::MovieFormat(format!(
+                    "Frame {} has {} bytes, expected {}",
+                    frames.len(),
+                    frame_bytes.len(),
+                    frame_len
+                )));
             }
-            frames.push(frame);
+            frames.push(rgbw::unpack_frame(
+                &frame_bytes,
+                led_profile,
+                white_extraction,
+            ));
         }
 
-        Ok(Movie { frames, fps })
+        Ok(Movie {
+            frames,
+            fps,
+            ..Movie::default()
+        })
     }
 
-    /// Saves a movie to a file in a text-based format.
+    /// Reads a movie in the binary format.
+    pub fn read_binary_movie<R: Read>(
+        mut reader: R,
+        white_extraction: WhiteExtraction,
+    ) -> Result<Self> {
+        let header = read_binary_header(&mut reader)?;
+        let frame_len = header
+            .led_count
+            .checked_mul(header.led_profile.bytes_per_led())
+            .ok_or_else(|| Error::MovieFormat("The frame size overflows".to_string()))?;
+        let mut frame_bytes = vec![0; frame_len];
+        // The frame count isn't trusted for preallocation, a corrupt file may claim any number
+        let mut frames = Vec::new();
+        for _ in 0..header.frame_count {
+            read_exact(&mut reader, &mut frame_bytes)?;
+            frames.push(rgbw::unpack_frame(
+                &frame_bytes,
+                header.led_profile,
+                white_extraction,
+            ));
+        }
+
+        Ok(Movie {
+            frames,
+            fps: header.fps,
+            layout_uuid: header.layout_uuid,
+            title: header.title,
+        })
+    }
+
+    /// Saves a movie to a file in a text-based format, using [WhiteExtraction::MinSubtraction].
     pub fn save_movie<P: AsRef<Path>>(&self, path: P, led_profile: LedProfile) -> io::Result<()> {
-        let mut file = File::create(path)?;
+        self.save_text_movie(path, led_profile, WhiteExtraction::default())
+    }
+
+    fn save_text_movie<P: AsRef<Path>>(
+        &self,
+        path: P,
+        led_profile: LedProfile,
+        white_extraction: WhiteExtraction,
+    ) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
 
         // Write the header
         let num_frames = self.frames.len();
         let num_leds = self.frames.first().map_or(0, Vec::len);
-        let bytes_per_led = match led_profile {
-            LedProfile::RGB => 3,
-            LedProfile::RGBW => 4,
-        };
+        let bytes_per_led = led_profile.bytes_per_led();
         writeln!(
             file,
             "{} {} {} {}",
@@ -100,24 +246,391 @@ impl Movie {
         )?;
 
         // Write the frames
+        let mut frame_bytes = Vec::new();
         for frame in &self.frames {
-            for &(r, g, b) in frame {
-                match led_profile {
-                    LedProfile::RGB => {
-                        write!(file, "{:02X}{:02X}{:02X}", r, g, b)?;
-                    }
-                    LedProfile::RGBW => {
-                        // Calculate the white component as the minimum of r, g, b
-                        let w = r.min(g).min(b);
-                        write!(file, "{:02X}{:02X}{:02X}{:02X}", r - w, g - w, b - w, w)?;
-                    }
-                }
+            frame_bytes.clear();
+            rgbw::pack_frame(frame, led_profile, white_extraction, &mut frame_bytes);
+            writeln!(file, "{}", hex::encode_upper(&frame_bytes))?; // Newline after each frame
+        }
+
+        file.flush()
+    }
+
+    /**
+    Saves a movie to a file in the binary format, see the [module documentation](self),
+    using [WhiteExtraction::MinSubtraction].
+     */
+    pub fn save_binary_movie<P: AsRef<Path>>(
+        &self,
+        path: P,
+        led_profile: LedProfile,
+    ) -> Result<()> {
+        self.save_binary(path, led_profile, WhiteExtraction::default())
+    }
+
+    fn save_binary<P: AsRef<Path>>(
+        &self,
+        path: P,
+        led_profile: LedProfile,
+        white_extraction: WhiteExtraction,
+    ) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_binary_movie(&mut writer, led_profile, white_extraction)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Saves a movie to a file in `format`, encoding RGBW colors with `white_extraction`.
+    pub fn save<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: MovieFileFormat,
+        led_profile: LedProfile,
+        white_extraction: WhiteExtraction,
+    ) -> Result<()> {
+        match format {
+            MovieFileFormat::Text => {
+                Ok(self.save_text_movie(path, led_profile, white_extraction)?)
             }
-            writeln!(file)?; // Newline after each frame
+            MovieFileFormat::Binary => self.save_binary(path, led_profile, white_extraction),
         }
+    }
+
+    /**
+    Writes a movie in the binary format.
+
+    # Errors
+    [Error::MovieFormat] if the frames differ in length, or the counts or title don't fit the header.
+     */
+    pub fn write_binary_movie<W: Write>(
+        &self,
+        mut writer: W,
+        led_profile: LedProfile,
+        white_extraction: WhiteExtraction,
+    ) -> Result<()> {
+        let led_count = self.frames.first().map_or(0, Vec::len);
+        if self.frames.iter().any(|frame| frame.len() != led_count) {
+            return Err(Error::MovieFormat(
+                "All frames must have the same number of LEDs".to_string(),
+            ));
+        }
+        let frame_count = header_count(self.frames.len(), "frames")?;
+        let led_count = header_count(led_count, "LEDs")?;
+        let title = match &self.title {
+            Some(title) => Some(u16::try_from(title.len()).map_err(|_| {
+                Error::MovieFormat(format!("Title too long: {} bytes", title.len()))
+            })?),
+            None => None,
+        };
 
+        let mut flags = 0;
+        if self.layout_uuid.is_some() {
+            flags |= FLAG_LAYOUT_UUID;
+        }
+        if title.is_some() {
+            flags |= FLAG_TITLE;
+        }
+        writer.write_all(BINARY_MOVIE_MAGIC)?;
+        writer.write_all(&[BINARY_MOVIE_VERSION])?;
+        writer.write_all(&self.fps.to_le_bytes())?;
+        writer.write_all(&frame_count.to_le_bytes())?;
+        writer.write_all(&led_count.to_le_bytes())?;
+        writer.write_all(&[led_profile.bytes_per_led() as u8, flags])?;
+        if let Some(layout_uuid) = &self.layout_uuid {
+            writer.write_all(layout_uuid.as_bytes())?;
+        }
+        if let (Some(title_len), Some(title)) = (title, &self.title) {
+            writer.write_all(&title_len.to_le_bytes())?;
+            writer.write_all(title.as_bytes())?;
+        }
+        writer.write_all(&rgbw::pack_frames(
+            &self.frames,
+            led_profile,
+            white_extraction,
+        ))?;
         Ok(())
     }
 
     // ... Additional methods ...
 }
+
+fn parse_header_field<T: std::str::FromStr>(value: &str, field: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::MovieFormat(format!("Invalid {} in header: {}", field, value)))
+}
+
+/// Tells the format by the buffered first bytes, without consuming them.
+fn detect_format<R: BufRead>(reader: &mut R) -> Result<MovieFileFormat> {
+    let start = reader.fill_buf()?;
+    if start.starts_with(BINARY_MOVIE_MAGIC) {
+        Ok(MovieFileFormat::Binary)
+    } else {
+        Ok(MovieFileFormat::Text)
+    }
+}
+
+/// Reads the text header line as frames, LEDs, bytes per LED and fps.
+fn read_text_header<R: BufRead>(reader: &mut R) -> Result<(usize, usize, usize, f64)> {
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let header_parts: Vec<&str> = header.split_whitespace().collect();
+    if header_parts.len() != 4 {
+        return Err(Error::MovieFormat(format!(
+            "Invalid header format: {}",
+            header.trim()
+        )));
+    }
+
+    Ok((
+        parse_header_field(header_parts[0], "frame count")?,
+        parse_header_field(header_parts[1], "LED count")?,
+        parse_header_field(header_parts[2], "bytes per LED")?,
+        parse_header_field(header_parts[3], "fps")?,
+    ))
+}
+
+fn read_binary_header<R: Read>(reader: &mut R) -> Result<MovieFileHeader> {
+    let mut magic = [0; 8];
+    read_exact(reader, &mut magic)?;
+    if &magic != BINARY_MOVIE_MAGIC {
+        return Err(Error::MovieFormat("Not a binary movie file".to_string()));
+    }
+    let [version] = read_array(reader)?;
+    if version != BINARY_MOVIE_VERSION {
+        return Err(Error::MovieFormat(format!(
+            "Unsupported binary movie version: {}",
+            version
+        )));
+    }
+    let fps = f64::from_le_bytes(read_array(reader)?);
+    let frame_count = u32::from_le_bytes(read_array(reader)?) as usize;
+    let led_count = u32::from_le_bytes(read_array(reader)?) as usize;
+    if led_count > MAX_BINARY_MOVIE_LEDS {
+        return Err(Error::MovieFormat(format!(
+            "The movie has {} LEDs, at most {} are supported",
+            led_count, MAX_BINARY_MOVIE_LEDS
+        )));
+    }
+    let [bytes_per_led, flags] = read_array(reader)?;
+    let layout_uuid = if flags & FLAG_LAYOUT_UUID != 0 {
+        Some(Uuid::from_bytes(read_array(reader)?))
+    } else {
+        None
+    };
+    let title = if flags & FLAG_TITLE != 0 {
+        let mut title = vec![0; u16::from_le_bytes(read_array(reader)?) as usize];
+        read_exact(reader, &mut title)?;
+        Some(
+            String::from_utf8(title)
+                .map_err(|_| Error::MovieFormat("Title is not valid UTF-8".to_string()))?,
+        )
+    } else {
+        None
+    };
+
+    Ok(MovieFileHeader {
+        format: MovieFileFormat::Binary,
+        frame_count,
+        led_count,
+        led_profile: led_profile_for(bytes_per_led as usize)?,
+        fps,
+        layout_uuid,
+        title,
+    })
+}
+
+fn led_profile_for(bytes_per_led: usize) -> Result<LedProfile> {
+    match bytes_per_led {
+        3 => Ok(LedProfile::RGB),
+        4 => Ok(LedProfile::RGBW),
+        _ => Err(Error::MovieFormat(format!(
+            "Unsupported bytes per LED: {}",
+            bytes_per_led
+        ))),
+    }
+}
+
+fn header_count(count: usize, what: &str) -> Result<u32> {
+    u32::try_from(count).map_err(|_| Error::MovieFormat(format!("Too many {}: {}", what, count)))
+}
+
+/// Like [Read::read_exact], reporting a truncated file as [Error::MovieFormat].
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => Error::MovieFormat("Truncated movie file".to_string()),
+        _ => Error::Io(e),
+    })
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut buf = [0; N];
+    read_exact(reader, &mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_movie() -> Movie {
+        Movie {
+            frames: vec![
+                vec![(255, 0, 0), (10, 20, 30), (255, 255, 255)],
+                vec![(0, 0, 0), (1, 2, 3), (40, 50, 60)],
+            ],
+            fps: 12.5,
+            layout_uuid: Some(Uuid::from_u128(0x1234)),
+            title: Some("Sample".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        for led_profile in [LedProfile::RGB, LedProfile::RGBW] {
+            let movie = sample_movie();
+            let mut data = Vec::new();
+            movie
+                .write_binary_movie(&mut data, led_profile, WhiteExtraction::default())
+                .unwrap();
+
+            assert!(data.starts_with(BINARY_MOVIE_MAGIC));
+            assert_eq!(
+                Movie::read_binary_movie(data.as_slice(), WhiteExtraction::default()).unwrap(),
+                movie
+            );
+        }
+    }
+
+    #[test]
+    fn test_binary_without_metadata() {
+        let movie = Movie {
+            layout_uuid: None,
+            title: None,
+            ..sample_movie()
+        };
+        let mut data = Vec::new();
+        movie
+            .write_binary_movie(&mut data, LedProfile::RGB, WhiteExtraction::default())
+            .unwrap();
+
+        // Magic, version, fps, counts, bytes per LED, flags and 2 frames of 3 RGB LEDs
+        assert_eq!(data.len(), 8 + 1 + 8 + 4 + 4 + 1 + 1 + 18);
+        assert_eq!(
+            Movie::read_binary_movie(data.as_slice(), WhiteExtraction::default()).unwrap(),
+            movie
+        );
+    }
+
+    #[test]
+    fn test_binary_rejects_unknown_version_and_truncation() {
+        let mut data = Vec::new();
+        sample_movie()
+            .write_binary_movie(&mut data, LedProfile::RGB, WhiteExtraction::default())
+            .unwrap();
+
+        let truncated = &data[..data.len() - 1];
+        assert!(matches!(
+            Movie::read_binary_movie(truncated, WhiteExtraction::default()),
+            Err(Error::MovieFormat(_))
+        ));
+
+        data[BINARY_MOVIE_MAGIC.len()] = BINARY_MOVIE_VERSION + 1;
+        assert!(matches!(
+            Movie::read_binary_movie(data.as_slice(), WhiteExtraction::default()),
+            Err(Error::MovieFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_binary_rejects_huge_counts() {
+        let mut data = BINARY_MOVIE_MAGIC.to_vec();
+        data.push(BINARY_MOVIE_VERSION);
+        data.extend_from_slice(&10.0f64.to_le_bytes());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&[3, 0]);
+        assert!(matches!(
+            Movie::read_binary_movie(data.as_slice(), WhiteExtraction::default()),
+            Err(Error::MovieFormat(_))
+        ));
+
+        // A plausible LED count with a huge frame count fails on the missing frames
+        let led_count = data.len() - 6;
+        data[led_count..led_count + 4].copy_from_slice(&3u32.to_le_bytes());
+        assert!(matches!(
+            Movie::read_binary_movie(data.as_slice(), WhiteExtraction::default()),
+            Err(Error::MovieFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_format_is_detected_on_load() {
+        let dir = std::env::temp_dir();
+        let text_path = dir.join(format!("movie-detect-{}.txt", std::process::id()));
+        let binary_path = dir.join(format!("movie-detect-{}.bin", std::process::id()));
+        let movie = sample_movie();
+        movie.save_movie(&text_path, LedProfile::RGB).unwrap();
+        movie
+            .save_binary_movie(&binary_path, LedProfile::RGBW)
+            .unwrap();
+
+        let from_text = Movie::load_movie(&text_path, LedProfile::RGB).unwrap();
+        let from_binary = Movie::load_movie(&binary_path, LedProfile::RGB).unwrap();
+        let binary_header = Movie::read_header(&binary_path).unwrap();
+        let text_format = Movie::detect_format(&text_path).unwrap();
+        std::fs::remove_file(&text_path).unwrap();
+        std::fs::remove_file(&binary_path).unwrap();
+
+        assert_eq!(text_format, MovieFileFormat::Text);
+        assert_eq!(from_text.frames, movie.frames);
+        assert_eq!(from_text.title, None);
+        assert_eq!(from_binary, movie);
+        assert_eq!(binary_header.led_profile, LedProfile::RGBW);
+        assert_eq!((binary_header.frame_count, binary_header.led_count), (2, 3));
+    }
+
+    #[test]
+    fn test_rgbw_text_decoding_saturates() {
+        let text = "1 2 4 10\nFFFFFFFF80808080\n";
+        let movie = Movie::read_text_movie(
+            text.as_bytes(),
+            LedProfile::RGBW,
+            WhiteExtraction::default(),
+        )
+        .unwrap();
+
+        assert_eq!(movie.frames, vec![vec![(255, 255, 255), (255, 255, 255)]]);
+    }
+
+    #[test]
+    fn test_text_rejects_huge_counts() {
+        for text in [
+            "18446744073709551615 1 3 10\n102030\n",
+            "1 18446744073709551615 3 10\n102030\n",
+            "18446744073709551615 0 3 10\n\n",
+        ] {
+            assert!(matches!(
+                Movie::read_text_movie(
+                    text.as_bytes(),
+                    LedProfile::RGB,
+                    WhiteExtraction::default()
+                ),
+                Err(Error::MovieFormat(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_text_profile_must_match_header() {
+        let text = "1 1 3 10\n102030\n";
+        assert!(matches!(
+            Movie::read_text_movie(
+                text.as_bytes(),
+                LedProfile::RGBW,
+                WhiteExtraction::default()
+            ),
+            Err(Error::MovieFormat(_))
+        ));
+    }
+}
@@ -0,0 +1,393 @@
+/*!
+Conversion between movies and images, using the positions of the LEDs from the layout.
+
+The LEDs are projected onto the image plane by dropping one coordinate, see [ImageProjection].
+ */
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, RgbaImage};
+
+use crate::control_interface::{Axis, LedCoordinate};
+use crate::error::{Error, Result};
+use crate::led::animation::check_fps;
+use crate::led::led_color::LedColor;
+use crate::util::movie::Movie;
+
+/// The frame rate of GIFs without frame delays, which browsers show at 10 fps as well.
+const DEFAULT_GIF_FPS: f64 = 10.0;
+
+/// How the bounding box of the LEDs is fitted onto an image of a different aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum FitMode {
+    /// The whole image is shown, LEDs beyond its edges stay dark.
+    Contain,
+    /// The image covers all LEDs, its edges beyond the LEDs are cropped.
+    #[default]
+    Cover,
+    /// The image is stretched to the LEDs, ignoring its aspect ratio.
+    Stretch,
+}
+
+/**
+Projects LED coordinates onto an image.
+
+The `horizontal` axis of the layout runs left to right in the image,
+the `vertical` axis bottom to top.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageProjection {
+    pub horizontal: Axis,
+    pub vertical: Axis,
+    pub fit: FitMode,
+}
+
+impl Default for ImageProjection {
+    /// The front view of a layout, with `y` pointing up.
+    fn default() -> Self {
+        ImageProjection {
+            horizontal: Axis::X,
+            vertical: Axis::Y,
+            fit: FitMode::default(),
+        }
+    }
+}
+
+impl ImageProjection {
+    /**
+    The pixel positions of the LEDs in an image of `width` by `height` pixels.
+
+    Positions may be outside the image with [FitMode::Contain].
+     */
+    pub fn pixel_positions(
+        &self,
+        coordinates: &[LedCoordinate],
+        width: u32,
+        height: u32,
+    ) -> Vec<(f64, f64)> {
+        let (min_h, max_h) = bounds(coordinates, self.horizontal);
+        let (min_v, max_v) = bounds(coordinates, self.vertical);
+        let (range_h, range_v) = (max_h - min_h, max_v - min_v);
+        let (width, height) = (width as f64, height as f64);
+
+        // Pixels per layout unit, for an axis along which the LEDs are spread out
+        let scale = |pixels: f64, range: f64| (range > 0.0).then(|| pixels / range);
+        let (scale_h, scale_v) = match (scale(width, range_h), scale(height, range_v)) {
+            (Some(h), Some(v)) => match self.fit {
+                FitMode::Contain => (h.max(v), h.max(v)),
+                FitMode::Cover => (h.min(v), h.min(v)),
+                FitMode::Stretch => (h, v),
+            },
+            // With the LEDs on a line, the other axis has nothing to fit
+            (Some(h), None) => (h, h),
+            (None, Some(v)) => (v, v),
+            (None, None) => (0.0, 0.0),
+        };
+
+        let (center_h, center_v) = ((min_h + max_h) / 2.0, (min_v + max_v) / 2.0);
+        coordinates
+            .iter()
+            .map(|led| {
+                (
+                    width / 2.0 + (led.along(self.horizontal) - center_h) * scale_h,
+                    height / 2.0 - (led.along(self.vertical) - center_v) * scale_v,
+                )
+            })
+            .collect()
+    }
+
+    /**
+    Samples the colors of `image` at the LED positions, converted with [LedColor::image_to_led_rgb].
+
+    Transparent pixels are blended onto black, LEDs outside the image are black.
+     */
+    pub fn sample(
+        &self,
+        image: &RgbaImage,
+        coordinates: &[LedCoordinate],
+        led_color: &LedColor,
+    ) -> Vec<(u8, u8, u8)> {
+        let (width, height) = image.dimensions();
+        self.pixel_positions(coordinates, width, height)
+            .into_iter()
+            .map(
+                |(x, y)| match pixel_index(x, width).zip(pixel_index(y, height)) {
+                    Some((x, y)) => {
+                        let [r, g, b, a] = image.get_pixel(x, y).0;
+                        let opaque = |c: u8| (c as u16 * a as u16 / 255) as u8;
+                        led_color.image_to_led_rgb(opaque(r), opaque(g), opaque(b))
+                    }
+                    None => (0, 0, 0),
+                },
+            )
+            .collect()
+    }
+}
+
+impl Movie {
+    /// Creates a movie with one frame per image, sampled at the LED positions, see [ImageProjection::sample].
+    pub fn from_images(
+        images: &[RgbaImage],
+        coordinates: &[LedCoordinate],
+        projection: &ImageProjection,
+        fps: f64,
+    ) -> Movie {
+        let led_color = LedColor::new();
+        Movie {
+            frames: images
+                .iter()
+                .map(|image| projection.sample(image, coordinates, &led_color))
+                .collect(),
+            fps,
+            ..Movie::default()
+        }
+    }
+
+    /**
+    Imports an animated GIF.
+
+    Without `fps`, every GIF frame becomes one movie frame, played at the average rate of the GIF.
+    With `fps`, the GIF is resampled, so frames with different delays keep their timing.
+     */
+    pub fn from_gif<P: AsRef<Path>>(
+        path: P,
+        coordinates: &[LedCoordinate],
+        projection: &ImageProjection,
+        fps: Option<f64>,
+    ) -> Result<Movie> {
+        let decoder = GifDecoder::new(BufReader::new(File::open(path)?)).map_err(image_error)?;
+        let mut images = Vec::new();
+        let mut delays = Vec::new();
+        for frame in decoder.into_frames() {
+            let frame = frame.map_err(image_error)?;
+            delays.push(Duration::from(frame.delay()));
+            images.push(frame.into_buffer());
+        }
+        if images.is_empty() {
+            return Err(Error::MovieFormat("The GIF has no frames".to_string()));
+        }
+
+        // GIFs without delays play at the default rate
+        if delays.iter().all(Duration::is_zero) {
+            delays.fill(Duration::from_secs_f64(1.0 / DEFAULT_GIF_FPS));
+        }
+        let total = delays.iter().sum::<Duration>().as_secs_f64();
+        match fps {
+            None => {
+                let fps = images.len() as f64 / total;
+                Ok(Self::from_images(&images, coordinates, projection, fps))
+            }
+            Some(fps) => {
+                check_fps(fps)?;
+                let frame_count = ((total * fps).round() as usize).max(1);
+                let resampled: Vec<RgbaImage> = (0..frame_count)
+                    .map(|i| images[gif_frame_at(&delays, i as f64 / fps)].clone())
+                    .collect();
+                Ok(Self::from_images(&resampled, coordinates, projection, fps))
+            }
+        }
+    }
+
+    /**
+    Imports a sequence of still images, e.g. numbered PNG files, as consecutive frames.
+
+    # Errors
+    [Error::InvalidArgument] for a frame rate that isn't positive.
+     */
+    pub fn from_image_files<P: AsRef<Path>>(
+        paths: &[P],
+        coordinates: &[LedCoordinate],
+        projection: &ImageProjection,
+        fps: f64,
+    ) -> Result<Movie> {
+        check_fps(fps)?;
+        let images = paths
+            .iter()
+            .map(|path| Ok(image::open(path).map_err(image_error)?.into_rgba8()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::from_images(&images, coordinates, projection, fps))
+    }
+}
+
+/// The minimum and maximum of the coordinates along `axis`.
+fn bounds(coordinates: &[LedCoordinate], axis: Axis) -> (f64, f64) {
+    if coordinates.is_empty() {
+        return (0.0, 0.0);
+    }
+    coordinates
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), led| {
+            (min.min(led.along(axis)), max.max(led.along(axis)))
+        })
+}
+
+/// The pixel containing `position`, counting LEDs right on the far edge to the last pixel.
+fn pixel_index(position: f64, size: u32) -> Option<u32> {
+    if position < 0.0 || position > size as f64 || size == 0 {
+        return None;
+    }
+    Some((position as u32).min(size - 1))
+}
+
+/// The index of the GIF frame shown `seconds` after the start.
+fn gif_frame_at(delays: &[Duration], seconds: f64) -> usize {
+    let mut end = 0.0;
+    for (index, delay) in delays.iter().enumerate() {
+        end += delay.as_secs_f64();
+        if seconds < end {
+            return index;
+        }
+    }
+    delays.len() - 1
+}
+
+pub(crate) fn image_error(error: image::ImageError) -> Error {
+    match error {
+        image::ImageError::IoError(e) => Error::Io(e),
+        other => Error::MovieFormat(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, Rgba};
+
+    fn led(x: f64, y: f64) -> LedCoordinate {
+        LedCoordinate { x, y, z: 0.0 }
+    }
+
+    /// An image with a red left half and a blue right half.
+    fn split_image(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, _| {
+            if x < width / 2 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_fit_modes() {
+        // A square layout on a wide image
+        let corners = [led(0.0, 0.0), led(1.0, 1.0)];
+        let position = |fit| ImageProjection {
+            fit,
+            ..ImageProjection::default()
+        };
+
+        assert_eq!(
+            position(FitMode::Stretch).pixel_positions(&corners, 200, 100),
+            vec![(0.0, 100.0), (200.0, 0.0)]
+        );
+        assert_eq!(
+            position(FitMode::Cover).pixel_positions(&corners, 200, 100),
+            vec![(50.0, 100.0), (150.0, 0.0)]
+        );
+        assert_eq!(
+            position(FitMode::Contain).pixel_positions(&corners, 200, 100),
+            vec![(0.0, 150.0), (200.0, -50.0)]
+        );
+    }
+
+    #[test]
+    fn test_sampling_uses_the_layout() {
+        let leds = [led(0.0, 0.5), led(0.4, 0.0), led(1.0, 1.0)];
+        let movie = Movie::from_images(
+            &[split_image(10, 10)],
+            &leds,
+            &ImageProjection::default(),
+            5.0,
+        );
+        let led_color = LedColor::new();
+
+        let red = led_color.image_to_led_rgb(255, 0, 0);
+        let blue = led_color.image_to_led_rgb(0, 0, 255);
+        assert_eq!(movie.frames, vec![vec![red, red, blue]]);
+        assert_eq!(movie.fps, 5.0);
+    }
+
+    #[test]
+    fn test_leds_outside_the_image_are_dark() {
+        let leds = [led(0.0, 0.0), led(0.0, 0.5), led(1.0, 1.0)];
+        let projection = ImageProjection {
+            fit: FitMode::Contain,
+            ..ImageProjection::default()
+        };
+        let frame = projection.sample(&split_image(20, 10), &leds, &LedColor::new());
+
+        assert_eq!(frame[0], (0, 0, 0));
+        assert_eq!(frame[1], LedColor::new().image_to_led_rgb(255, 0, 0));
+        assert_eq!(frame[2], (0, 0, 0));
+    }
+
+    #[test]
+    fn test_leds_on_a_line() {
+        let leds = [led(0.0, 0.0), led(1.0, 0.0)];
+        let positions = ImageProjection::default().pixel_positions(&leds, 10, 10);
+
+        assert_eq!(positions, vec![(0.0, 5.0), (10.0, 5.0)]);
+    }
+
+    #[test]
+    fn test_gif_import() {
+        let path = std::env::temp_dir().join(format!("movie-import-{}.gif", std::process::id()));
+        {
+            let mut encoder = GifEncoder::new(File::create(&path).unwrap());
+            let delays = [100, 300];
+            let colors = [[255, 0, 0, 255], [0, 255, 0, 255]];
+            for (delay, color) in delays.into_iter().zip(colors) {
+                let image = RgbaImage::from_pixel(4, 4, Rgba(color));
+                encoder
+                    .encode_frame(Frame::from_parts(
+                        image,
+                        0,
+                        0,
+                        Delay::from_numer_denom_ms(delay, 1),
+                    ))
+                    .unwrap();
+            }
+        }
+        let leds = [led(0.0, 0.0), led(1.0, 1.0)];
+        let projection = ImageProjection::default();
+
+        let movie = Movie::from_gif(&path, &leds, &projection, None).unwrap();
+        let resampled = Movie::from_gif(&path, &leds, &projection, Some(10.0)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(movie.frames.len(), 2);
+        assert_eq!(movie.fps, 5.0);
+        assert_eq!(resampled.frames.len(), 4);
+        assert_eq!(resampled.frames[0], movie.frames[0]);
+        assert_eq!(
+            resampled.frames[1..],
+            [
+                movie.frames[1].clone(),
+                movie.frames[1].clone(),
+                movie.frames[1].clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_files_need_a_valid_frame_rate() {
+        for fps in [0.0, -1.0, f64::NAN] {
+            assert!(matches!(
+                Movie::from_image_files::<&str>(
+                    &[],
+                    &[led(0.0, 0.0)],
+                    &ImageProjection::default(),
+                    fps
+                ),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,305 @@
+/*!
+Previews of movies, drawing every LED as a dot at its position in the layout.
+
+A movie can be rendered to an animated GIF, or to a PNG contact sheet showing its frames side by
+side, so effects can be reviewed without a device.
+ */
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Duration;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, Rgba, RgbaImage};
+
+use crate::control_interface::LedCoordinate;
+use crate::error::{Error, Result};
+use crate::led::led_color::LedColor;
+use crate::util::movie::Movie;
+use crate::util::movie_image::{image_error, FitMode, ImageProjection};
+
+/// Draws movie frames as images, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct MoviePreview {
+    /// The width of a frame in pixels.
+    pub width: u32,
+    /// The height of a frame in pixels.
+    pub height: u32,
+    /// The radius of the dot drawn for each LED, in pixels.
+    pub dot_radius: f64,
+    /// The color around the dots.
+    pub background: (u8, u8, u8),
+    /// How the layout is projected onto the frame. With [FitMode::Cover], all LEDs are drawn.
+    pub projection: ImageProjection,
+    /// Turns the LED values into display colors with [LedColor::led_to_image_rgb].
+    pub led_color: LedColor,
+}
+
+impl Default for MoviePreview {
+    /// A 400 by 400 pixel front view of the whole layout on a dark gray background.
+    fn default() -> Self {
+        MoviePreview {
+            width: 400,
+            height: 400,
+            dot_radius: 4.0,
+            background: (16, 16, 16),
+            projection: ImageProjection {
+                fit: FitMode::Cover,
+                ..ImageProjection::default()
+            },
+            led_color: LedColor::new(),
+        }
+    }
+}
+
+impl MoviePreview {
+    /// Draws one frame with the LED colors in the order of `coordinates`.
+    pub fn render_frame(&self, frame: &[(u8, u8, u8)], coordinates: &[LedCoordinate]) -> RgbaImage {
+        let (r, g, b) = self.background;
+        let mut image = RgbaImage::from_pixel(self.width, self.height, Rgba([r, g, b, 255]));
+
+        // Keep the dots at the edges of the layout inside the image
+        let margin = self.dot_radius.ceil().max(0.0);
+        let inner = |size: u32| (size as f64 - 2.0 * margin).max(1.0) as u32;
+        let positions =
+            self.projection
+                .pixel_positions(coordinates, inner(self.width), inner(self.height));
+        for (&(x, y), &(r, g, b)) in positions.iter().zip(frame) {
+            let color = self.led_color.led_to_image_rgb(r, g, b);
+            self.draw_dot(&mut image, x + margin, y + margin, color);
+        }
+        image
+    }
+
+    /**
+    Writes the movie as an animated GIF looping forever, with every `step`th frame.
+
+    GIFs store delays in hundredths of a second, so the frame rate may be slightly off.
+     */
+    pub fn write_gif<P: AsRef<Path>>(
+        &self,
+        movie: &Movie,
+        coordinates: &[LedCoordinate],
+        step: usize,
+        path: P,
+    ) -> Result<()> {
+        check_movie(movie, step)?;
+        let delay =
+            Delay::from_saturating_duration(Duration::from_secs_f64(step as f64 / movie.fps));
+        let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+        encoder.set_repeat(Repeat::Infinite).map_err(image_error)?;
+        for frame in movie.frames.iter().step_by(step) {
+            let image = self.render_frame(frame, coordinates);
+            encoder
+                .encode_frame(Frame::from_parts(image, 0, 0, delay))
+                .map_err(image_error)?;
+        }
+        Ok(())
+    }
+
+    /// Draws every `step`th frame of the movie in a grid of `columns` frames per row, row by row.
+    pub fn contact_sheet(
+        &self,
+        movie: &Movie,
+        coordinates: &[LedCoordinate],
+        columns: u32,
+        step: usize,
+    ) -> Result<RgbaImage> {
+        check_movie(movie, step)?;
+        if columns == 0 {
+            return Err(Error::InvalidArgument(
+                "A contact sheet needs at least one column".to_string(),
+            ));
+        }
+        let frames: Vec<_> = movie.frames.iter().step_by(step).collect();
+        let columns = columns.min(frames.len() as u32);
+        let rows = (frames.len() as u32).div_ceil(columns);
+
+        let mut sheet = RgbaImage::new(columns * self.width, rows * self.height);
+        for (index, frame) in frames.into_iter().enumerate() {
+            let index = index as u32;
+            let image = self.render_frame(frame, coordinates);
+            image::imageops::replace(
+                &mut sheet,
+                &image,
+                ((index % columns) * self.width) as i64,
+                ((index / columns) * self.height) as i64,
+            );
+        }
+        Ok(sheet)
+    }
+
+    /// Writes the [contact sheet](Self::contact_sheet) of the movie as a PNG file.
+    pub fn write_contact_sheet<P: AsRef<Path>>(
+        &self,
+        movie: &Movie,
+        coordinates: &[LedCoordinate],
+        columns: u32,
+        step: usize,
+        path: P,
+    ) -> Result<()> {
+        self.contact_sheet(movie, coordinates, columns, step)?
+            .save_with_format(path, image::ImageFormat::Png)
+            .map_err(image_error)
+    }
+
+    /// Draws an anti-aliased dot, blending its edge with what is below it.
+    fn draw_dot(&self, image: &mut RgbaImage, x: f64, y: f64, (r, g, b): (u8, u8, u8)) {
+        let radius = self.dot_radius.max(0.5);
+        let reach = radius + 1.0;
+        let clamp = |value: f64, size: u32| value.clamp(0.0, size as f64) as u32;
+        for py in clamp(y - reach, image.height())..clamp(y + reach, image.height()) {
+            for px in clamp(x - reach, image.width())..clamp(x + reach, image.width()) {
+                let distance = (px as f64 + 0.5 - x).hypot(py as f64 + 0.5 - y);
+                let coverage = (radius + 0.5 - distance).clamp(0.0, 1.0);
+                if coverage > 0.0 {
+                    let pixel = image.get_pixel_mut(px, py);
+                    let blend = |below: u8, above: u8| {
+                        (below as f64 + (above as f64 - below as f64) * coverage).round() as u8
+                    };
+                    pixel.0 = [
+                        blend(pixel.0[0], r),
+                        blend(pixel.0[1], g),
+                        blend(pixel.0[2], b),
+                        255,
+                    ];
+                }
+            }
+        }
+    }
+}
+
+fn check_movie(movie: &Movie, step: usize) -> Result<()> {
+    if movie.frames.is_empty() {
+        return Err(Error::MovieFormat("The movie has no frames".to_string()));
+    }
+    if step == 0 {
+        return Err(Error::InvalidArgument(
+            "The frame step must be at least 1".to_string(),
+        ));
+    }
+    if !movie.fps.is_finite() || movie.fps <= 0.0 {
+        return Err(Error::InvalidArgument(format!(
+            "Invalid frame rate: {}",
+            movie.fps
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifDecoder;
+    use image::AnimationDecoder;
+    use std::io::BufReader;
+
+    fn leds() -> Vec<LedCoordinate> {
+        vec![
+            LedCoordinate {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            LedCoordinate {
+                x: 1.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        ]
+    }
+
+    fn movie() -> Movie {
+        Movie {
+            frames: vec![
+                vec![(255, 0, 0), (0, 0, 0)],
+                vec![(0, 0, 0), (0, 255, 0)],
+                vec![(0, 0, 255), (0, 0, 255)],
+            ],
+            fps: 10.0,
+            ..Movie::default()
+        }
+    }
+
+    fn preview() -> MoviePreview {
+        MoviePreview {
+            width: 40,
+            height: 40,
+            dot_radius: 3.0,
+            background: (0, 0, 0),
+            ..MoviePreview::default()
+        }
+    }
+
+    #[test]
+    fn test_dots_at_the_led_positions() {
+        let preview = preview();
+        let image = preview.render_frame(&movie().frames[0], &leds());
+        let red = preview.led_color.led_to_image_rgb(255, 0, 0);
+
+        // The first LED is at the bottom left, inset by the dot radius
+        assert_eq!(image.get_pixel(3, 36).0, [red.0, red.1, red.2, 255]);
+        assert_eq!(image.get_pixel(36, 3).0, [0, 0, 0, 255]);
+        assert_eq!(image.get_pixel(20, 20).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_wide_layout_fits_the_image() {
+        // A 3D layout twice as wide as high, on a square image
+        let leds: Vec<_> = [(-1.0, 0.0), (1.0, 0.0), (-1.0, 1.0), (1.0, 1.0), (0.0, 0.5)]
+            .into_iter()
+            .map(|(x, y)| LedCoordinate { x, y, z: 0.0 })
+            .collect();
+        let white = vec![(255, 255, 255); leds.len()];
+        let preview = preview();
+        let image = preview.render_frame(&white, &leds);
+
+        let color = preview.led_color.led_to_image_rgb(255, 255, 255);
+        let lit = [color.0, color.1, color.2, 255];
+        // Every LED is drawn inside the image, the corners just inside the dot radius
+        for (x, y) in [(3, 13), (36, 13), (3, 26), (36, 26), (20, 20)] {
+            assert_eq!(image.get_pixel(x, y).0, lit, "no LED at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn test_contact_sheet() {
+        let preview = preview();
+        let sheet = preview.contact_sheet(&movie(), &leds(), 2, 1).unwrap();
+        assert_eq!(sheet.dimensions(), (80, 80));
+
+        // The third frame starts the second row
+        let blue = preview.led_color.led_to_image_rgb(0, 0, 255);
+        assert_eq!(sheet.get_pixel(36, 43).0, [blue.0, blue.1, blue.2, 255]);
+        // The unused tile stays transparent
+        assert_eq!(sheet.get_pixel(60, 60).0[3], 0);
+
+        let every_other = preview.contact_sheet(&movie(), &leds(), 4, 2).unwrap();
+        assert_eq!(every_other.dimensions(), (80, 40));
+        assert!(preview.contact_sheet(&movie(), &leds(), 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_gif_preview() {
+        let path = std::env::temp_dir().join(format!("movie-preview-{}.gif", std::process::id()));
+        preview().write_gif(&movie(), &leds(), 1, &path).unwrap();
+
+        let decoder = GifDecoder::new(BufReader::new(File::open(&path).unwrap())).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!(
+            Duration::from(frames[0].delay()),
+            Duration::from_millis(100)
+        );
+        assert_eq!(frames[0].buffer().dimensions(), (40, 40));
+    }
+
+    #[test]
+    fn test_empty_movie() {
+        let empty = Movie::default();
+        assert!(preview().contact_sheet(&empty, &leds(), 2, 1).is_err());
+    }
+}
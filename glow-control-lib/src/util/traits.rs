@@ -16,16 +16,39 @@ impl ResponseCode {
     ///
     /// Use this function instead of comparing the code to "1000",
     /// it can be expanded if it becomes clearer (reverse-engineered) what other codes mean "Ok".
+    /// Besides [OK], some firmware answers successful requests with [OK2] or [OK3].
     ///
     /// That could be context-dependent.
     pub fn is_ok(&self) -> bool {
-        self.code == OK.code
+        [OK.code, OK2.code, OK3.code].contains(&self.code)
     }
 
     /// This is a code which means "Error".
     pub fn is_error(&self) -> bool {
         !self.is_ok()
     }
+
+    /// Looks up the known response code for `code`.
+    ///
+    /// Unknown codes are kept as they are, with a generic message, so they can still be told apart.
+    pub fn from_code(code: u32) -> ResponseCode {
+        match code {
+            1000 => OK,
+            1001 => ERROR,
+            1101 => ERROR_INVALID_ARGUMENT,
+            1102 => ERROR2,
+            1103 => ERROR_VALUE_WRONG_MISSING_KEY,
+            1104 => ERROR_MALFORMED_JSON_INPUT,
+            1105 => ERROR_INVALID_ARGUMENT_KEY,
+            1107 => OK2,
+            1108 => OK3,
+            1205 => FIRMWARE_UPGRADE_ERROR,
+            _ => ResponseCode {
+                code,
+                message: "Unknown response code",
+            },
+        }
+    }
 }
 
 // Errors codes from https://xled-docs.readthedocs.io/en/latest/rest_api.html#http-responses.
@@ -89,18 +112,28 @@ pub trait ResponseCodeTrait {
     fn response_code(&self) -> ResponseCode;
 
     fn map_response_code(code: u32) -> ResponseCode {
-        match code {
-            1000 => OK,
-            1001 => ERROR,
-            1101 => ERROR_INVALID_ARGUMENT,
-            1102 => ERROR2,
-            1103 => ERROR_VALUE_WRONG_MISSING_KEY,
-            1104 => ERROR_MALFORMED_JSON_INPUT,
-            1105 => ERROR_INVALID_ARGUMENT_KEY,
-            1107 => OK2,
-            1108 => OK3,
-            1205 => FIRMWARE_UPGRADE_ERROR,
-            _ => ERROR,
-        }
+        ResponseCode::from_code(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_code_is_kept() {
+        assert_eq!(ResponseCode::from_code(1104), ERROR_MALFORMED_JSON_INPUT);
+        let unknown = ResponseCode::from_code(1234);
+        assert_eq!(unknown.code, 1234);
+        assert!(unknown.is_error());
+        assert_ne!(unknown, ERROR);
+    }
+
+    #[test]
+    fn test_alternative_ok_codes() {
+        assert!(ResponseCode::from_code(1000).is_ok());
+        assert!(ResponseCode::from_code(1107).is_ok());
+        assert!(ResponseCode::from_code(1108).is_ok());
+        assert!(ResponseCode::from_code(1001).is_error());
     }
 }
@@ -0,0 +1,853 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use glow_control_lib::control_interface::{
+    Axis, ControlInterface, DeviceMode, HardwareVersion, HttpExchange, LedProfile, PlaylistItem,
+    TraceHook,
+};
+use glow_control_lib::emulator::{DeviceEmulator, EmulatorConfig};
+use glow_control_lib::led::animation::{Animation, Solid};
+use glow_control_lib::util::device_registry::DeviceRegistry;
+use glow_control_lib::util::discovery::{
+    DeviceIdentifier, Discovery, DiscoveryOptions, DiscoveryResponse, DiscoveryTarget,
+};
+use glow_control_lib::util::layout::Layout;
+use glow_control_lib::util::layout_mapping::MappingSequence;
+use glow_control_lib::util::movie::Movie;
+use glow_control_lib::util::traits;
+use glow_control_lib::Error;
+use tokio::net::UdpSocket;
+
+async fn start_emulator() -> DeviceEmulator {
+    DeviceEmulator::start(EmulatorConfig::default())
+        .await
+        .expect("failed to start emulator")
+}
+
+#[tokio::test]
+async fn test_authentication_and_gestalt() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+
+    assert_eq!(emulator.state().logins, 1);
+    assert_eq!(control.get_device_info(), &emulator.state().device_info);
+}
+
+#[tokio::test]
+async fn test_invalid_token_is_rejected() {
+    let emulator = start_emulator().await;
+    let device_info = emulator.state().device_info.clone();
+    // With the wrong MAC address, authenticating again after the 401 fails as well.
+    let control = ControlInterface::new_mock_control_interface(
+        emulator.host(),
+        "02:00:00:00:00:02".to_string(),
+        "AAAAAAAAAAA=".to_string(),
+        device_info,
+    );
+
+    assert!(matches!(
+        control.get_mode().await,
+        Err(Error::Authentication(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_rejected_token_is_renewed() {
+    let emulator = start_emulator().await;
+    let control = ControlInterface::new(
+        &emulator.host(),
+        &emulator.mac(),
+        Some("AAAAAAAAAAA=".to_string()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(control.get_mode().await.unwrap(), DeviceMode::Movie);
+    assert_eq!(emulator.state().logins, 1);
+    assert!(emulator.state().is_token_valid(&control.auth_token()));
+}
+
+#[tokio::test]
+async fn test_expiring_token_is_renewed() {
+    let config = EmulatorConfig {
+        token_lifetime: Duration::from_secs(1),
+        ..EmulatorConfig::default()
+    };
+    let emulator = DeviceEmulator::start(config).await.unwrap();
+    let control = emulator.control_interface().await.unwrap();
+    let mut session = control.realtime_session().await.unwrap();
+    let first_token = control.auth_token();
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(!emulator.state().is_token_valid(&first_token));
+
+    // The real-time packets pick up the renewed token without the caller doing anything.
+    let frame = vec![0x30; 250 * 3];
+    session.send_frame(&frame).await.unwrap();
+    for _ in 0..50 {
+        if emulator.state().rt_frame == frame {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(emulator.state().rt_frame, frame);
+    assert_eq!(emulator.state().logins, 2);
+    assert_ne!(control.auth_token(), first_token);
+}
+
+#[tokio::test]
+async fn test_trace_hook() {
+    let emulator = start_emulator().await;
+    let exchanges = Arc::new(Mutex::new(Vec::new()));
+    let recorded = exchanges.clone();
+    let control = emulator.control_interface().await.unwrap().with_trace_hook(
+        move |exchange: &HttpExchange| recorded.lock().unwrap().push(exchange.clone()),
+    );
+
+    control.set_mode(DeviceMode::Color).await.unwrap();
+    control.get_mode().await.unwrap();
+
+    let exchanges = exchanges.lock().unwrap();
+    assert_eq!(exchanges.len(), 2);
+    assert_eq!(exchanges[0].method, "POST");
+    assert!(exchanges[0].url.ends_with("/xled/v1/led/mode"));
+    assert_eq!(
+        exchanges[0].request_body.as_deref(),
+        Some(&br#"{"mode":"color"}"#[..])
+    );
+    assert_eq!(exchanges[1].status, Some(reqwest::StatusCode::OK));
+    let body: serde_json::Value =
+        serde_json::from_slice(exchanges[1].response_body.as_ref().unwrap()).unwrap();
+    assert_eq!(body["mode"], "color");
+}
+
+#[tokio::test]
+async fn test_trace_hook_redacts_tokens() {
+    let emulator = start_emulator().await;
+    let exchanges = Arc::new(Mutex::new(Vec::new()));
+    let recorded = exchanges.clone();
+    let hook: TraceHook =
+        Arc::new(move |exchange: &HttpExchange| recorded.lock().unwrap().push(exchange.clone()));
+    let control =
+        ControlInterface::new_with_trace_hook(&emulator.host(), &emulator.mac(), None, Some(hook))
+            .await
+            .unwrap();
+
+    let exchanges = exchanges.lock().unwrap();
+    let token = control.auth_token();
+    assert!(exchanges.iter().any(|e| e.url.ends_with("/xled/v1/login")));
+    for exchange in exchanges.iter() {
+        for body in [&exchange.request_body, &exchange.response_body] {
+            let body = String::from_utf8_lossy(body.as_deref().unwrap_or_default());
+            assert!(!body.contains(token.as_str()), "{}", body);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_mode_brightness_and_timer() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+
+    control.set_mode(DeviceMode::Color).await.unwrap();
+    assert_eq!(control.get_mode().await.unwrap(), DeviceMode::Color);
+
+    control.set_brightness(42).await.unwrap();
+    assert_eq!(control.get_brightness().await.unwrap().value, 42);
+
+    control
+        .set_formatted_timer("18:30", "23:00:30")
+        .await
+        .unwrap();
+    let timer = control.get_timer().await.unwrap();
+    assert_eq!((timer.time_on, timer.time_off), (66_600, 82_830));
+}
+
+#[tokio::test]
+async fn test_device_error_code() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+
+    // The device answers with HTTP 200, the error is only in the response code.
+    let result = control.set_brightness(150).await;
+    assert!(
+        matches!(result, Err(Error::Device(code)) if code == traits::ERROR_INVALID_ARGUMENT),
+        "{:?}",
+        result
+    );
+    assert_eq!(control.get_brightness().await.unwrap().value, 100);
+}
+
+#[tokio::test]
+async fn test_layout_and_playlist() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+
+    let layout = control.fetch_layout().await.unwrap();
+    assert_eq!(layout.coordinates.len(), 250);
+    assert_eq!(layout, emulator.state().layout);
+
+    assert!(control.get_playlist().await.unwrap().entries.is_empty());
+}
+
+#[tokio::test]
+async fn test_layout_upload() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+    let original = control.fetch_layout().await.unwrap();
+
+    let mut layout = Layout::read_json(serde_json::to_vec(&original).unwrap().as_slice()).unwrap();
+    layout.mirror(Axis::X);
+    layout.reverse();
+    control.upload_layout(&layout).await.unwrap();
+
+    let uploaded = control.fetch_layout().await.unwrap();
+    // JSON keeps the coordinates up to rounding
+    assert_eq!(uploaded.coordinates.len(), layout.coordinates.len());
+    for (uploaded, expected) in uploaded.coordinates.iter().zip(&layout.coordinates) {
+        assert!((uploaded.x - expected.x).abs() < 1e-12);
+        assert!((uploaded.y - expected.y).abs() < 1e-12);
+        assert!((uploaded.z - expected.z).abs() < 1e-12);
+    }
+    assert!(!uploaded.synthesized);
+    assert_ne!(uploaded.uuid, original.uuid);
+
+    // Layouts not fitting the device aren't sent
+    layout.coordinates.pop();
+    assert!(matches!(
+        control.upload_layout(&layout).await,
+        Err(Error::LayoutFormat(_))
+    ));
+    assert_eq!(emulator.state().layout, uploaded);
+}
+
+#[tokio::test]
+async fn test_movie_upload_and_clear() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+    let capacity = control.get_device_capacity().await.unwrap();
+
+    let path = std::env::temp_dir().join(format!("emulator-movie-{}.txt", std::process::id()));
+    let frame = "102030".repeat(250);
+    std::fs::write(&path, format!("2 250 3 10\n{}\n{}\n", frame, frame)).unwrap();
+    let id = control
+        .upload_movie(&path, LedProfile::RGB, None, false)
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(emulator.state().movies[0].info.id, id);
+    assert_eq!(emulator.state().movies[0].info.frames_number, 2);
+    let config = control.get_movie_config().await.unwrap();
+    assert_eq!(
+        (config.frame_delay, config.leds_number, config.frames_number),
+        (100, 250, 2)
+    );
+    assert_eq!(control.get_device_capacity().await.unwrap(), capacity - 2);
+
+    control.clear_movies().await.unwrap();
+    assert!(emulator.state().movies.is_empty());
+}
+
+#[tokio::test]
+async fn test_named_movies() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+    let movie = Movie {
+        frames: vec![vec![(255, 0, 0); 250]; 3],
+        fps: 10.0,
+        ..Movie::default()
+    };
+
+    let red = control
+        .upload_named_movie(&movie, "red", LedProfile::RGB, None)
+        .await
+        .unwrap();
+    let blue = control
+        .upload_named_movie(
+            &movie,
+            "blue",
+            LedProfile::RGB,
+            Some(Duration::from_millis(40)),
+        )
+        .await
+        .unwrap();
+    assert_eq!((red.name.as_str(), red.frames_number), ("red", 3));
+    assert_eq!((red.fps, blue.fps), (10.0, 25.0));
+    assert_eq!(red.leds_per_frame, 250);
+
+    let movies = control.get_movies().await.unwrap();
+    assert_eq!(movies.movies, vec![red.clone(), blue.clone()]);
+    assert_eq!(movies.available_frames, movies.max_capacity - 6);
+
+    control.set_current_movie(blue.id).await.unwrap();
+    let current = control.get_current_movie().await.unwrap();
+    assert_eq!((current.id, &current.unique_id), (blue.id, &blue.unique_id));
+
+    control.delete_movie(red.id).await.unwrap();
+    assert_eq!(
+        control.get_movies().await.unwrap().movies,
+        vec![blue.clone()]
+    );
+    assert!(matches!(
+        control.delete_movie(red.id).await,
+        Err(Error::Device(_))
+    ));
+
+    control.delete_movie(blue.id).await.unwrap();
+    assert!(matches!(
+        control.get_current_movie().await,
+        Err(Error::Device(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_playlist() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+    let movie = Movie {
+        frames: vec![vec![(0, 255, 0); 250]; 2],
+        fps: 10.0,
+        ..Movie::default()
+    };
+    let first = control
+        .upload_named_movie(&movie, "first", LedProfile::RGB, None)
+        .await
+        .unwrap();
+    let second = control
+        .upload_named_movie(&movie, "second", LedProfile::RGB, None)
+        .await
+        .unwrap();
+
+    let playlist = control
+        .set_playlist(
+            "evening",
+            &[
+                PlaylistItem {
+                    movie_id: second.id,
+                    duration: Duration::from_secs(30),
+                },
+                PlaylistItem {
+                    movie_id: first.id,
+                    duration: Duration::from_secs(10),
+                },
+            ],
+        )
+        .await
+        .unwrap();
+    assert_eq!(playlist.name, "evening");
+    let entries: Vec<_> = playlist
+        .entries
+        .iter()
+        .map(|entry| (entry.handle, entry.name.as_str(), entry.duration))
+        .collect();
+    assert_eq!(
+        entries,
+        vec![(second.id, "second", 30), (first.id, "first", 10)]
+    );
+
+    control.set_current_playlist_entry(1).await.unwrap();
+    let current = control.get_current_playlist_entry().await.unwrap();
+    assert_eq!((current.id, current.unique_id), (1, first.unique_id));
+    assert!(matches!(
+        control.set_current_playlist_entry(2).await,
+        Err(Error::Device(_))
+    ));
+
+    assert!(matches!(
+        control
+            .set_playlist(
+                "missing",
+                &[PlaylistItem {
+                    movie_id: 42,
+                    duration: Duration::from_secs(1),
+                }],
+            )
+            .await,
+        Err(Error::InvalidArgument(_))
+    ));
+
+    control.delete_playlist().await.unwrap();
+    assert!(control.get_playlist().await.unwrap().entries.is_empty());
+    assert_eq!(emulator.state().movies.len(), 2);
+}
+
+#[tokio::test]
+async fn test_movie_not_matching_device() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+
+    let path = std::env::temp_dir().join(format!("emulator-mismatch-{}.txt", std::process::id()));
+    let frame = "102030".repeat(100);
+    std::fs::write(&path, format!("1 100 3 10\n{}\n", frame)).unwrap();
+    let too_few_leds = control
+        .upload_movie(&path, LedProfile::RGB, None, false)
+        .await;
+    let frame = "10203040".repeat(250);
+    std::fs::write(&path, format!("1 250 4 10\n{}\n", frame)).unwrap();
+    let wrong_profile = control
+        .upload_movie(&path, LedProfile::RGBW, None, false)
+        .await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(too_few_leds, Err(Error::MovieFormat(_))));
+    assert!(matches!(wrong_profile, Err(Error::MovieFormat(_))));
+    assert!(emulator.state().movies.is_empty());
+}
+
+#[tokio::test]
+async fn test_movie_exceeding_capacity() {
+    let mut config = EmulatorConfig::default();
+    config.device_info.movie_capacity = 1;
+    let emulator = DeviceEmulator::start(config).await.unwrap();
+    let control = emulator.control_interface().await.unwrap();
+
+    let path = std::env::temp_dir().join(format!("emulator-capacity-{}.txt", std::process::id()));
+    let frame = "102030".repeat(250);
+    std::fs::write(&path, format!("2 250 3 10\n{}\n{}\n", frame, frame)).unwrap();
+    let result = control
+        .upload_movie(&path, LedProfile::RGB, Some(25.0), false)
+        .await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(
+        result,
+        Err(Error::Capacity {
+            required: 2,
+            available: 1
+        })
+    ));
+}
+
+#[tokio::test]
+async fn test_unreachable_device() {
+    // Nothing listens on the emulator's port once it's dropped.
+    let host = start_emulator().await.host();
+    let result = ControlInterface::new(&host, "02:00:00:00:00:01", None).await;
+    assert!(matches!(result, Err(Error::Transport(_))));
+}
+
+#[tokio::test]
+async fn test_realtime_frame() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+
+    let frame: Vec<u8> = (0..250 * 3).map(|i| (i % 256) as u8).collect();
+    control.show_rt_frame(&frame).await.unwrap();
+
+    // The UDP packets arrive asynchronously.
+    for _ in 0..50 {
+        if emulator.state().rt_frame == frame {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let state = emulator.state();
+    assert_eq!(state.mode, DeviceMode::RealTime);
+    assert_eq!(state.rt_frame, frame);
+    assert!(state.rt_packets.iter().all(|packet| packet.version == 3));
+}
+
+/// Sends one frame to an emulated device with the given firmware, returning the packet versions received.
+async fn rt_versions_for(firmware_version: &str, number_of_led: usize) -> Vec<u8> {
+    let mut config = EmulatorConfig::default();
+    config.device_info.number_of_led = number_of_led;
+    config.firmware_version = firmware_version.to_string();
+    let emulator = DeviceEmulator::start(config).await.unwrap();
+    let control = emulator.control_interface().await.unwrap();
+
+    let frame = vec![0x55; number_of_led * 3];
+    control.show_rt_frame(&frame).await.unwrap();
+    for _ in 0..50 {
+        if emulator.state().rt_frame == frame {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(emulator.state().rt_frame, frame);
+    let versions = emulator
+        .state()
+        .rt_packets
+        .iter()
+        .map(|packet| packet.version)
+        .collect();
+    versions
+}
+
+#[tokio::test]
+async fn test_rt_protocol_follows_firmware() {
+    assert_eq!(rt_versions_for("2.3.8", 200).await, vec![1]);
+    assert_eq!(rt_versions_for("2.4.16", 200).await, vec![2]);
+    assert_eq!(rt_versions_for("2.3.8", 300).await, vec![2]);
+    assert_eq!(rt_versions_for("2.8.18", 600).await, vec![3, 3]);
+}
+
+#[tokio::test]
+async fn test_rt_protocol_override() {
+    let mut config = EmulatorConfig::default();
+    config.device_info.number_of_led = 300;
+    let emulator = DeviceEmulator::start(config).await.unwrap();
+    let control = emulator
+        .control_interface()
+        .await
+        .unwrap()
+        .with_rt_protocol(HardwareVersion::Version1);
+
+    // Version 1 can't address 300 LEDs, which must not be silently truncated.
+    assert!(control.show_rt_frame(&[0; 900]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_discovery_reply() {
+    let config = EmulatorConfig {
+        discovery_addr: Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)),
+        advertised_ip: Some(Ipv4Addr::new(10, 0, 0, 7)),
+        ..EmulatorConfig::default()
+    };
+    let emulator = DeviceEmulator::start(config).await.unwrap();
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket
+        .send_to(b"\x01discover", emulator.discovery_addr().unwrap())
+        .await
+        .unwrap();
+    let mut buffer = [0u8; 64];
+    let (number_of_bytes, _) =
+        tokio::time::timeout(Duration::from_secs(2), socket.recv_from(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+
+    let reply = Discovery::decode_discovery_response(&buffer[..number_of_bytes]).unwrap();
+    assert_eq!(
+        reply,
+        DiscoveryResponse::new(Ipv4Addr::new(10, 0, 0, 7), "Twinkly_000001".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_discovery_unicast_targets() {
+    let config = EmulatorConfig {
+        discovery_addr: Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)),
+        advertised_ip: Some(Ipv4Addr::new(10, 0, 0, 7)),
+        ..EmulatorConfig::default()
+    };
+    let emulator = DeviceEmulator::start(config).await.unwrap();
+    let expected = DiscoveryResponse::new(Ipv4Addr::new(10, 0, 0, 7), "Twinkly_000001".to_string());
+
+    for targets in [
+        vec![DiscoveryTarget::Host(Ipv4Addr::LOCALHOST)],
+        // The host twice, and the other addresses of the range which don't answer
+        vec![
+            DiscoveryTarget::Host(Ipv4Addr::LOCALHOST),
+            DiscoveryTarget::Range("127.0.0.0/30".parse().unwrap()),
+        ],
+    ] {
+        let options = DiscoveryOptions {
+            targets,
+            port: emulator.discovery_addr().unwrap().port(),
+            ..DiscoveryOptions::new(Duration::from_millis(300))
+        };
+        let responses = Discovery::collect_responses(&options).await.unwrap();
+        assert_eq!(
+            responses.into_iter().collect::<Vec<_>>(),
+            vec![expected.clone()]
+        );
+    }
+
+    let too_large = DiscoveryOptions {
+        targets: vec![DiscoveryTarget::Range("10.0.0.0/8".parse().unwrap())],
+        ..DiscoveryOptions::new(Duration::from_millis(300))
+    };
+    assert!(matches!(
+        Discovery::collect_responses(&too_large).await,
+        Err(Error::InvalidArgument(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_discovery_reports_failures_per_device() {
+    // The device can be pinged, but the gestalt request goes to port 80, where nothing listens
+    let config = EmulatorConfig {
+        discovery_addr: Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)),
+        advertised_ip: Some(Ipv4Addr::LOCALHOST),
+        ..EmulatorConfig::default()
+    };
+    let emulator = DeviceEmulator::start(config).await.unwrap();
+    let options = DiscoveryOptions {
+        targets: vec![DiscoveryTarget::Host(Ipv4Addr::LOCALHOST)],
+        port: emulator.discovery_addr().unwrap().port(),
+        ..DiscoveryOptions::new(Duration::from_millis(300))
+    };
+
+    let result = Discovery::find_new_devices_with(&options, None)
+        .await
+        .unwrap();
+    assert!(result.new_devices.is_empty());
+    assert_eq!(result.failed_devices.len(), 1);
+    assert_eq!(
+        result.failed_devices[0].response,
+        DiscoveryResponse::new(Ipv4Addr::LOCALHOST, "Twinkly_000001".to_string())
+    );
+    assert!(matches!(
+        result.failed_devices[0].error,
+        Error::Transport(_)
+    ));
+}
+
+#[tokio::test]
+async fn test_discovery_gives_up_on_silent_devices() {
+    // The gestalt request is accepted, but never answered
+    let silent = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let http_port = silent.local_addr().unwrap().port();
+    let config = EmulatorConfig {
+        discovery_addr: Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)),
+        advertised_ip: Some(Ipv4Addr::LOCALHOST),
+        ..EmulatorConfig::default()
+    };
+    let emulator = DeviceEmulator::start(config).await.unwrap();
+    let options = DiscoveryOptions {
+        targets: vec![DiscoveryTarget::Host(Ipv4Addr::LOCALHOST)],
+        port: emulator.discovery_addr().unwrap().port(),
+        http_port,
+        identify_timeout: Duration::from_millis(200),
+        ..DiscoveryOptions::new(Duration::from_millis(300))
+    };
+
+    let result = Discovery::find_new_devices_with(&options, None)
+        .await
+        .unwrap();
+    assert!(result.new_devices.is_empty());
+    assert_eq!(result.failed_devices.len(), 1);
+    assert!(matches!(
+        result.failed_devices[0].error,
+        Error::Timeout { .. }
+    ));
+    drop(silent);
+}
+
+/**
+Starts an emulated device with its HTTP API and discovery responder on `ip`.
+Like some firmware, it accepts challenge responses made with any MAC address.
+ */
+async fn start_emulator_at(
+    ip: Ipv4Addr,
+    mac: &str,
+    http_port: u16,
+    discovery_port: u16,
+) -> DeviceEmulator {
+    let device_info = ControlInterface::new_mock_device_info_response(
+        mac.replace(':', ""),
+        format!("Twinkly {}", mac),
+        mac.to_string(),
+        250,
+    );
+    let config = EmulatorConfig {
+        http_addr: SocketAddr::new(IpAddr::V4(ip), http_port),
+        discovery_addr: Some(SocketAddr::new(IpAddr::V4(ip), discovery_port)),
+        advertised_ip: Some(ip),
+        check_challenge_response: false,
+        ..EmulatorConfig::new(device_info)
+    };
+    DeviceEmulator::start(config).await.unwrap()
+}
+
+// Other loopback addresses than 127.0.0.1 only work without setup on Linux
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_registry_finds_moved_device() {
+    const PORCH: &str = "02:00:00:00:00:0a";
+    // Another device now holds the porch's registered address, on the same ports
+    let other = start_emulator_at(Ipv4Addr::new(127, 0, 0, 2), "02:00:00:00:00:0b", 0, 0).await;
+    let http_port = other.http_addr().port();
+    let discovery_port = other.discovery_addr().unwrap().port();
+    let porch = start_emulator_at(
+        Ipv4Addr::new(127, 0, 0, 3),
+        PORCH,
+        http_port,
+        discovery_port,
+    )
+    .await;
+
+    let mut registry = DeviceRegistry::default();
+    registry.update(&DeviceIdentifier::new(
+        Ipv4Addr::new(127, 0, 0, 2),
+        "Twinkly_porch".to_string(),
+        PORCH.to_string(),
+        "Porch".to_string(),
+        250,
+        None,
+    ));
+    registry.set_alias("porch", PORCH).unwrap();
+    let options = DiscoveryOptions {
+        targets: vec![
+            DiscoveryTarget::Host(Ipv4Addr::new(127, 0, 0, 2)),
+            DiscoveryTarget::Host(Ipv4Addr::new(127, 0, 0, 3)),
+        ],
+        port: discovery_port,
+        http_port,
+        ..DiscoveryOptions::new(Duration::from_millis(300))
+    };
+
+    // The device answering at the old address isn't the porch, so it's searched for
+    let control = registry.connect("porch", &options, None).await.unwrap();
+    assert_eq!(control.get_device_info().mac, PORCH);
+    assert_eq!(porch.state().logins, 1);
+    assert_eq!(
+        registry.find("porch").unwrap().ip_address,
+        Ipv4Addr::new(127, 0, 0, 3)
+    );
+    assert_eq!(registry.devices().len(), 2);
+
+    // Nothing answering at the old address works the same
+    let mut stale = registry.find("porch").unwrap().clone();
+    stale.ip_address = Ipv4Addr::new(127, 0, 0, 4);
+    let mut registry = DeviceRegistry::default();
+    registry.update(&DeviceIdentifier::new(
+        stale.ip_address,
+        "Twinkly_porch".to_string(),
+        stale.mac_address,
+        stale.device_name,
+        250,
+        None,
+    ));
+    let control = registry.connect(PORCH, &options, None).await.unwrap();
+    assert_eq!(control.get_device_info().mac, PORCH);
+    assert_eq!(
+        registry.find(PORCH).unwrap().ip_address,
+        Ipv4Addr::new(127, 0, 0, 3)
+    );
+
+    // Without finding it elsewhere, the mismatch at the old address is reported
+    let mut registry = DeviceRegistry::default();
+    registry.update(&DeviceIdentifier::new(
+        Ipv4Addr::new(127, 0, 0, 2),
+        "Twinkly_porch".to_string(),
+        PORCH.to_string(),
+        "Porch".to_string(),
+        250,
+        None,
+    ));
+    let only_old_address = DiscoveryOptions {
+        targets: vec![DiscoveryTarget::Host(Ipv4Addr::new(127, 0, 0, 2))],
+        ..options
+    };
+    assert!(matches!(
+        registry.connect(PORCH, &only_old_address, None).await,
+        Err(Error::DeviceMismatch { expected, .. }) if expected == PORCH
+    ));
+}
+
+#[tokio::test]
+async fn test_realtime_session_paces_and_restores_mode() {
+    let mut config = EmulatorConfig::default();
+    config.device_info.frame_rate = 50.0;
+    config.device_info.measured_frame_rate = 25.0;
+    let emulator = DeviceEmulator::start(config).await.unwrap();
+    let control = emulator.control_interface().await.unwrap();
+    control.set_mode(DeviceMode::Color).await.unwrap();
+
+    let mut session = control.realtime_session().await.unwrap();
+    assert_eq!(session.frame_interval(), Duration::from_millis(40));
+    assert_eq!(session.previous_mode(), DeviceMode::Color);
+    assert_eq!(emulator.state().mode, DeviceMode::RealTime);
+
+    let started = tokio::time::Instant::now();
+    let frame = vec![0x10; 250 * 3];
+    for _ in 0..4 {
+        session.send_frame(&frame).await.unwrap();
+    }
+    assert!(started.elapsed() >= Duration::from_millis(120));
+
+    session.close().await.unwrap();
+    assert_eq!(emulator.state().mode, DeviceMode::Color);
+}
+
+#[tokio::test]
+async fn test_realtime_session_keepalive() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+    let mut session = control
+        .realtime_session_with_keepalive(Duration::from_millis(20))
+        .await
+        .unwrap();
+
+    session.send_frame(&[0x20; 250 * 3]).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    // The frame fits in one packet, anything beyond that was repeated by the keepalive.
+    assert!(emulator.state().rt_packets.len() > 2);
+
+    drop(session);
+    for _ in 0..50 {
+        if emulator.state().mode != DeviceMode::RealTime {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(emulator.state().mode, DeviceMode::Movie);
+}
+
+#[tokio::test]
+async fn test_play_animation() {
+    let emulator = start_emulator().await;
+    let control = emulator.control_interface().await.unwrap();
+
+    let mut animation = Solid::new((0xff, 0, 0))
+        .limit(0.2)
+        .then(Solid::new((0, 0, 0xff)).limit(0.2));
+    let started = tokio::time::Instant::now();
+    control.play_animation(&mut animation, 20.0).await.unwrap();
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+
+    let blue = [0, 0, 0xff].repeat(250);
+    for _ in 0..50 {
+        if emulator.state().rt_frame == blue {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let state = emulator.state();
+    assert_eq!(state.rt_frame, blue);
+    // Both parts of the sequence were shown, and the previous mode is back
+    assert!(state.rt_packets.len() >= 6);
+    assert_eq!(state.mode, DeviceMode::Movie);
+}
+
+#[tokio::test]
+async fn test_mapping_sequence() {
+    let mut config = EmulatorConfig::default();
+    config.device_info.number_of_led = 5;
+    let emulator = DeviceEmulator::start(config).await.unwrap();
+    let control = emulator.control_interface().await.unwrap();
+    let shown = Arc::new(Mutex::new(Vec::new()));
+
+    let emulator_ref = &emulator;
+    control
+        .play_mapping_sequence(MappingSequence::Binary, (9, 9, 9), |step, steps| {
+            let shown = shown.clone();
+            async move {
+                assert_eq!(steps, 5);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                shown
+                    .lock()
+                    .unwrap()
+                    .push((step, emulator_ref.state().rt_frame.clone()));
+                Ok(())
+            }
+        })
+        .await
+        .unwrap();
+
+    let expected = MappingSequence::Binary.frames(5, (9, 9, 9));
+    let shown = shown.lock().unwrap();
+    assert_eq!(shown.len(), expected.len());
+    for ((step, frame), expected) in shown.iter().zip(&expected) {
+        assert_eq!(frame, &control.pack_frame(expected), "step {}", step);
+    }
+    assert_eq!(emulator.state().mode, DeviceMode::Movie);
+}
@@ -1,13 +1,43 @@
 use std::collections::HashSet;
+use std::fs::File;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand, ValueEnum};
+use ipnet::Ipv4Net;
+use serde::Deserialize;
 
 use glow_control_lib::control_interface::{
-    CliColors, CliDeviceMode, ControlInterface, RtStdinErrorMode, RtStdinFormat, RGB,
+    Axis, CliColors, CliDeviceMode, ControlInterface, DeviceMode, HardwareVersion, HttpExchange,
+    LayoutResponse, LayoutSource, LedCoordinate, LedProfile, PlaylistItem, PlaylistResponse,
+    RtStdinErrorMode, RtStdinFormat, TraceHook, RGB,
 };
-use glow_control_lib::util::discovery::Discovery;
+use glow_control_lib::emulator::{DeviceEmulator, EmulatorConfig};
+use glow_control_lib::led::animation::{Animation, ShineAnimation, SpatialAnimation};
+use glow_control_lib::led::color_meander::MeanderStyle;
+use glow_control_lib::led::led_color::LedColor;
+use glow_control_lib::led::pattern_animation::{
+    Alternating, MeanderDrift, MeanderMode, RandomPattern, RotatingSpectrum, Sprinkles,
+};
+use glow_control_lib::led::rgbw::WhiteExtraction;
+use glow_control_lib::led::spatial::{
+    ExpandingSphere, Fire, NoiseField, PlaneSweep, RotatingRainbow, SpatialEffect, Twinkle,
+};
+use glow_control_lib::util::device_registry::DeviceRegistry;
+use glow_control_lib::util::discovery::{
+    DeviceIdentifier, Discovery, DiscoveryOptions, DiscoveryTarget, DEFAULT_DISCOVERY_CONCURRENCY,
+};
+use glow_control_lib::util::discovery_watcher::{DiscoveryEvent, DiscoveryWatcher};
+use glow_control_lib::util::layout::{Layout, LayoutFileFormat};
+use glow_control_lib::util::layout_mapping::{
+    photo_paths, LedMapping, MappingOptions, MappingSequence,
+};
+use glow_control_lib::util::movie::{Movie, MovieFileFormat};
+use glow_control_lib::util::movie_image::{FitMode, ImageProjection};
+use glow_control_lib::util::movie_preview::MoviePreview;
 
 // Function to generate a random challenge
 
@@ -30,6 +60,10 @@ async fn main() -> Result<()> {
     version = "0.3.3"
 )]
 pub struct Cli {
+    /// The device registry file, by default devices.json in the glow-control configuration directory
+    #[clap(long, global = true)]
+    pub registry: Option<PathBuf>,
+
     #[clap(subcommand)]
     pub command: Commands,
 }
@@ -52,12 +86,28 @@ pub enum Commands {
     #[clap(name = "device-call")]
     DeviceCall {
         /// Sets the IP address of the Twinkly device
-        #[clap(long)]
-        ip: String,
+        #[clap(long, required_unless_present = "device", requires = "mac")]
+        ip: Option<String>,
 
         /// Sets the MAC address of the Twinkly device
+        #[clap(long, required_unless_present = "device", requires = "ip")]
+        mac: Option<String>,
+
+        /// A registered device by alias or MAC address, instead of --ip and --mac, see `devices`
+        #[clap(long, conflicts_with_all = ["ip", "mac"])]
+        device: Option<String>,
+
+        /// Overrides the real-time protocol detected from the device
+        #[clap(long, value_enum)]
+        rt_protocol: Option<HardwareVersion>,
+
+        /// Prints every HTTP request and response to stderr
         #[clap(long)]
-        mac: String,
+        trace_http: bool,
+
+        /// How colors are split for RGBW devices: min, luminance, or the white LED's color temperature like 2700K
+        #[clap(long, default_value = "min")]
+        white_extraction: WhiteExtraction,
 
         #[clap(subcommand)]
         action: DeviceAction,
@@ -69,9 +119,98 @@ pub enum Commands {
         #[clap(short, long, value_enum, default_value_t = OutputFormat::Plaintext)]
         output: OutputFormat,
 
-        /// Search timeout in milliseconds
+        /// Search timeout in milliseconds, or the time each round waits for replies with --watch
         #[clap(short = 't', long = "timeout", default_value_t = 5000)]
         timeout: u64,
+
+        /// Keeps discovering and prints devices appearing, changing and disappearing until interrupted
+        #[clap(long)]
+        watch: bool,
+
+        /// Milliseconds between the discovery rounds with --watch
+        #[clap(long, default_value_t = 10000, requires = "watch")]
+        interval: u64,
+
+        /// Rounds in a row a device may miss before it's reported as disappeared with --watch
+        #[clap(long, default_value_t = 3, requires = "watch")]
+        missed_rounds: u32,
+
+        /// Broadcasts from the IPv4 addresses of this network interface, can be repeated
+        #[clap(long = "interface")]
+        interfaces: Vec<String>,
+
+        /// Sends a directed broadcast to this subnet as ADDRESS/PREFIX, can be repeated
+        #[clap(long = "subnet")]
+        subnets: Vec<Ipv4Net>,
+
+        /// Pings every host of this range as ADDRESS/PREFIX, for networks blocking broadcasts, can be repeated
+        #[clap(long = "range")]
+        ranges: Vec<Ipv4Net>,
+
+        /// Pings this host, can be repeated
+        #[clap(long = "host")]
+        hosts: Vec<Ipv4Addr>,
+
+        /// How many devices are identified and authenticated at the same time
+        #[clap(long, default_value_t = DEFAULT_DISCOVERY_CONCURRENCY)]
+        concurrency: usize,
+
+        /// Identifies devices from their gestalt info alone, without authenticating them
+        #[clap(long)]
+        no_auth: bool,
+    },
+    /// Subcommand for working with layout files, without a device
+    #[clap(name = "layout")]
+    Layout {
+        #[clap(subcommand)]
+        action: LayoutFileAction,
+    },
+    /// Subcommand for working with movie files, without a device
+    #[clap(name = "movie")]
+    Movie {
+        #[clap(subcommand)]
+        action: MovieFileAction,
+    },
+    /// Subcommand for managing the registry of discovered devices and their aliases
+    #[clap(name = "devices")]
+    Devices {
+        #[clap(subcommand)]
+        action: DevicesAction,
+    },
+    /// Runs an emulated Twinkly device on this machine, for testing without hardware
+    #[clap(name = "emulate")]
+    Emulate {
+        /// The IP address to listen on
+        #[clap(long, default_value = "0.0.0.0")]
+        bind: IpAddr,
+
+        /// The port of the HTTP API
+        #[clap(long, default_value_t = 80)]
+        http_port: u16,
+
+        /// The UDP port for real-time frames
+        #[clap(long, default_value_t = 7777)]
+        rt_port: u16,
+
+        /// The UDP port answering discovery pings
+        #[clap(long, default_value_t = 5555)]
+        discovery_port: u16,
+
+        /// The name of the emulated device
+        #[clap(long, default_value = "Emulated Twinkly")]
+        name: String,
+
+        /// The MAC address of the emulated device
+        #[clap(long, default_value = "02:00:00:00:00:01")]
+        mac: String,
+
+        /// The number of LEDs of the emulated device
+        #[clap(long, default_value_t = 250)]
+        leds: usize,
+
+        /// The LED profile of the emulated device
+        #[clap(long, value_enum, default_value_t = LedProfile::RGB)]
+        led_profile: LedProfile,
     },
 }
 
@@ -121,7 +260,987 @@ pub enum RtEffect {
         /// The frame rate for updating LED states
         #[clap(long)]
         frame_rate: f64,
+
+        /// Seeds the random generator so that the same seed shows the same frames
+        #[clap(long)]
+        seed: Option<u64>,
+    },
+    /// Shows an effect computed from the positions of the LEDs in the layout.
+    #[clap(name = "spatial")]
+    Spatial {
+        /// The frame rate for updating LED states
+        #[clap(long, default_value_t = 25.0)]
+        frame_rate: f64,
+
+        /// Stops after this many milliseconds instead of running until interrupted
+        #[clap(long, value_parser = parse_duration)]
+        duration: Option<Duration>,
+
+        #[clap(subcommand)]
+        effect: SpatialEffectKind,
+    },
+    /// Shows a color pattern, moving or changing over time.
+    #[clap(name = "pattern")]
+    Pattern {
+        /// The frame rate for updating LED states
+        #[clap(long, default_value_t = 25.0)]
+        frame_rate: f64,
+
+        /// Stops after this many milliseconds instead of running until interrupted
+        #[clap(long, value_parser = parse_duration)]
+        duration: Option<Duration>,
+
+        #[clap(subcommand)]
+        effect: PatternEffect,
+    },
+}
+
+/// Color patterns, see [glow_control_lib::led::pattern_animation].
+#[derive(Subcommand)]
+pub enum PatternEffect {
+    /// A color spectrum over all LEDs, turning around them.
+    #[clap(name = "spectrum")]
+    Spectrum {
+        /// Seconds for the spectrum to move along all LEDs, negative to move the other way, 0 to stand still
+        #[clap(long, default_value_t = 10.0, allow_negative_numbers = true)]
+        period: f64,
+
+        /// The lightness, from -1 for black over 0 for full colors to 1 for white
+        #[clap(long, default_value_t = 0.0, allow_negative_numbers = true)]
+        lightness: f64,
+    },
+    /// Colors repeating along the LEDs.
+    #[clap(name = "alternating")]
+    Alternating {
+        /// The colors to repeat
+        #[clap(long, use_value_delimiter = true, default_value = "red,green")]
+        colors: Vec<CliColors>,
+
+        /// Seconds between moving the pattern by one LED, 0 to stand still
+        #[clap(long, default_value_t = 0.0)]
+        step_interval: f64,
     },
+    /// Random blends of two colors.
+    #[clap(name = "random-blend")]
+    RandomBlend {
+        /// The first color
+        #[clap(long, value_enum, default_value_t = CliColors::Blue)]
+        from: CliColors,
+
+        /// The second color
+        #[clap(long, value_enum, default_value_t = CliColors::Purple)]
+        to: CliColors,
+
+        /// Seconds between new random patterns, keeps the first one if not set
+        #[clap(long)]
+        interval: Option<f64>,
+
+        /// Seeds the random generator so that the same seed shows the same frames
+        #[clap(long)]
+        seed: Option<u64>,
+    },
+    /// Random colors in ranges of hue, saturation and lightness.
+    #[clap(name = "random-hsl")]
+    RandomHsl {
+        /// The range of hues as MIN,MAX, from 0 to 1
+        #[clap(long, value_parser = parse_range)]
+        hue: Option<(f64, f64)>,
+
+        /// The range of saturations as MIN,MAX, from 0 to 1
+        #[clap(long, value_parser = parse_range)]
+        saturation: Option<(f64, f64)>,
+
+        /// The range of lightness as MIN,MAX, from -1 for black to 1 for white
+        #[clap(long, value_parser = parse_range, allow_hyphen_values = true)]
+        lightness: Option<(f64, f64)>,
+
+        /// Seconds between new random patterns, keeps the first one if not set
+        #[clap(long)]
+        interval: Option<f64>,
+
+        /// Seeds the random generator so that the same seed shows the same frames
+        #[clap(long)]
+        seed: Option<u64>,
+    },
+    /// Random LEDs lighting up at an interval and fading out.
+    #[clap(name = "sprinkle")]
+    Sprinkle {
+        /// The colors to sprinkle
+        #[clap(long, use_value_delimiter = true, default_value = "yellow,orange")]
+        colors: Vec<CliColors>,
+
+        /// The average number of LEDs lit by each sprinkle
+        #[clap(long, default_value_t = 10.0)]
+        frequency: f64,
+
+        /// Seconds between sprinkles
+        #[clap(long, default_value_t = 0.5)]
+        interval: f64,
+
+        /// The color between the sprinkles, black if not set
+        #[clap(long, value_enum)]
+        background: Option<CliColors>,
+
+        /// Seeds the random generator so that the same seed shows the same frames
+        #[clap(long)]
+        seed: Option<u64>,
+    },
+    /// Colors drifting smoothly through color space.
+    #[clap(name = "meander")]
+    Meander {
+        /// The shape of the color space the meander moves in
+        #[clap(long, value_enum, default_value_t = MeanderStyle::Surface)]
+        style: MeanderStyle,
+
+        /// How the color and its complement are spread over the LEDs
+        #[clap(long, value_enum, default_value_t = MeanderMode::Solid)]
+        mode: MeanderMode,
+
+        /// The length of each meander step
+        #[clap(long, default_value_t = 0.02)]
+        speed: f64,
+
+        /// How much the direction changes randomly in each step
+        #[clap(long, default_value_t = 0.1)]
+        noise: f64,
+
+        /// Meander steps per second
+        #[clap(long, default_value_t = 25.0)]
+        steps_per_second: f64,
+
+        /// Seeds the random generator so that the same seed shows the same frames
+        #[clap(long)]
+        seed: Option<u64>,
+    },
+}
+
+impl PatternEffect {
+    fn into_animation(self) -> Result<Box<dyn Animation>> {
+        Ok(match self {
+            PatternEffect::Spectrum { period, lightness } => Box::new(RotatingSpectrum {
+                lightness,
+                ..RotatingSpectrum::new(period)
+            }),
+            PatternEffect::Alternating {
+                colors,
+                step_interval,
+            } => Box::new(Alternating::new(
+                colors.into_iter().map(cli_color).collect(),
+                step_interval,
+            )?),
+            PatternEffect::RandomBlend {
+                from,
+                to,
+                interval,
+                seed,
+            } => seeded(
+                RandomPattern::blend(cli_color(from), cli_color(to), interval),
+                seed,
+                RandomPattern::with_seed,
+            ),
+            PatternEffect::RandomHsl {
+                hue,
+                saturation,
+                lightness,
+                interval,
+                seed,
+            } => seeded(
+                RandomPattern::hsl(hue, saturation, lightness, LedColor::new(), interval)?,
+                seed,
+                RandomPattern::with_seed,
+            ),
+            PatternEffect::Sprinkle {
+                colors,
+                frequency,
+                interval,
+                background,
+                seed,
+            } => {
+                let mut sprinkles = Sprinkles::new(
+                    colors.into_iter().map(cli_color).collect(),
+                    frequency,
+                    interval,
+                )?;
+                if let Some(background) = background {
+                    sprinkles.background = cli_color(background);
+                }
+                seeded(sprinkles, seed, Sprinkles::with_seed)
+            }
+            PatternEffect::Meander {
+                style,
+                mode,
+                speed,
+                noise,
+                steps_per_second,
+                seed,
+            } => seeded(
+                MeanderDrift::new(style, speed, noise, mode, steps_per_second)?,
+                seed,
+                MeanderDrift::with_seed,
+            ),
+        })
+    }
+}
+
+/// Applies `with_seed` if a seed was given and boxes the animation.
+fn seeded<A: Animation + 'static>(
+    animation: A,
+    seed: Option<u64>,
+    with_seed: fn(A, u64) -> A,
+) -> Box<dyn Animation> {
+    match seed {
+        Some(seed) => Box::new(with_seed(animation, seed)),
+        None => Box::new(animation),
+    }
+}
+
+/// Spatial effects, see [glow_control_lib::led::spatial].
+#[derive(Subcommand)]
+pub enum SpatialEffectKind {
+    /// A band of light sweeping through the layout.
+    #[clap(name = "sweep")]
+    Sweep {
+        /// The direction the band moves in, as `x,y,z`
+        #[clap(long, value_parser = parse_vector, default_value = "0,1,0")]
+        direction: (f64, f64, f64),
+
+        /// The color of the band
+        #[clap(long, value_enum, default_value_t = CliColors::Cyan)]
+        color: CliColors,
+
+        /// Seconds for one sweep
+        #[clap(long, default_value_t = 3.0)]
+        period: f64,
+
+        /// The thickness of the band, relative to the layout
+        #[clap(long, default_value_t = 0.2)]
+        width: f64,
+    },
+    /// A shell of light growing from the center of the layout.
+    #[clap(name = "sphere")]
+    Sphere {
+        /// The color of the shell
+        #[clap(long, value_enum, default_value_t = CliColors::Magenta)]
+        color: CliColors,
+
+        /// Seconds for the sphere to grow through the layout
+        #[clap(long, default_value_t = 2.0)]
+        period: f64,
+
+        /// The thickness of the shell, relative to the layout
+        #[clap(long, default_value_t = 0.15)]
+        thickness: f64,
+    },
+    /// Rainbows turning around an axis through the center of the layout.
+    #[clap(name = "rainbow")]
+    Rainbow {
+        /// The axis the rainbows turn around
+        #[clap(long, value_enum, default_value_t = Axis::Y)]
+        axis: Axis,
+
+        /// Seconds for one turn, negative to turn the other way
+        #[clap(long, default_value_t = 4.0, allow_negative_numbers = true)]
+        period: f64,
+
+        /// How many rainbows fit around the axis
+        #[clap(long, default_value_t = 1.0)]
+        bands: f64,
+    },
+    /// Colors drifting through the layout like clouds.
+    #[clap(name = "noise")]
+    Noise {
+        /// The colors to blend between
+        #[clap(long, use_value_delimiter = true, default_value = "blue,cyan,purple")]
+        colors: Vec<CliColors>,
+
+        /// The number of color patches across the layout
+        #[clap(long, default_value_t = 2.0)]
+        scale: f64,
+
+        /// How fast the patches drift
+        #[clap(long, default_value_t = 0.5)]
+        speed: f64,
+
+        /// The seed of the noise
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Flames rising from the bottom of the layout.
+    #[clap(name = "fire")]
+    Fire {
+        /// How high the flames reach, relative to the layout
+        #[clap(long, default_value_t = 0.8)]
+        height: f64,
+
+        /// How fast the flames rise
+        #[clap(long, default_value_t = 0.6)]
+        speed: f64,
+
+        /// The seed of the flicker
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// LEDs twinkling at random, more often towards one end of the layout.
+    #[clap(name = "twinkle")]
+    Twinkle {
+        /// The color of the twinkles
+        #[clap(long, value_enum, default_value_t = CliColors::Beige)]
+        color: CliColors,
+
+        /// Twinkles per second of an LED where they are most frequent
+        #[clap(long, default_value_t = 0.5)]
+        rate: f64,
+
+        /// The axis along which the density changes
+        #[clap(long, value_enum, default_value_t = Axis::Y)]
+        axis: Axis,
+
+        /// The density at the low end of the axis, from 0 to 1
+        #[clap(long, default_value_t = 1.0)]
+        from: f64,
+
+        /// The density at the high end of the axis, from 0 to 1
+        #[clap(long, default_value_t = 1.0)]
+        to: f64,
+
+        /// The seed of the twinkles
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
+}
+
+impl SpatialEffectKind {
+    fn into_effect(self) -> Box<dyn SpatialEffect> {
+        match self {
+            SpatialEffectKind::Sweep {
+                direction: (x, y, z),
+                color,
+                period,
+                width,
+            } => Box::new(PlaneSweep {
+                period,
+                width,
+                ..PlaneSweep::new([x, y, z], cli_color(color))
+            }),
+            SpatialEffectKind::Sphere {
+                color,
+                period,
+                thickness,
+            } => Box::new(ExpandingSphere {
+                period,
+                thickness,
+                ..ExpandingSphere::new(cli_color(color))
+            }),
+            SpatialEffectKind::Rainbow {
+                axis,
+                period,
+                bands,
+            } => Box::new(RotatingRainbow {
+                axis,
+                period,
+                bands,
+                ..RotatingRainbow::default()
+            }),
+            SpatialEffectKind::Noise {
+                colors,
+                scale,
+                speed,
+                seed,
+            } => {
+                let mut noise = NoiseField::new(colors.into_iter().map(cli_color).collect(), seed);
+                noise.scale = scale;
+                noise.speed = speed;
+                Box::new(noise)
+            }
+            SpatialEffectKind::Fire {
+                height,
+                speed,
+                seed,
+            } => {
+                let mut fire = Fire::new(seed);
+                fire.height = height;
+                fire.speed = speed;
+                Box::new(fire)
+            }
+            SpatialEffectKind::Twinkle {
+                color,
+                rate,
+                axis,
+                from,
+                to,
+                seed,
+            } => Box::new(Twinkle {
+                rate,
+                density: Twinkle::density_along(axis, from, to),
+                ..Twinkle::new(cli_color(color), seed)
+            }),
+        }
+    }
+}
+
+/// Movie operations on the device.
+#[derive(Subcommand)]
+pub enum MovieAction {
+    /// Lists the movies stored on the device.
+    #[clap(name = "list")]
+    List,
+    /// Shows the movie played in movie mode.
+    #[clap(name = "current")]
+    Current,
+    /// Selects the movie played in movie mode.
+    #[clap(name = "select")]
+    Select {
+        /// The ID of the movie, as shown by `list`
+        id: u32,
+    },
+    /// Uploads a movie file, keeping the movies already stored.
+    #[clap(name = "upload")]
+    Upload {
+        /// The movie file to upload
+        path: PathBuf,
+
+        /// The name of the movie on the device
+        #[clap(long)]
+        name: String,
+
+        /// The LED profile of the movie file, defaults to the device's profile
+        #[clap(long, value_enum)]
+        led_profile: Option<LedProfile>,
+
+        /// Time between frames in milliseconds, defaults to the fps of the movie file
+        #[clap(long, value_parser = parse_duration)]
+        frame_delay: Option<Duration>,
+    },
+    /// Deletes one movie from the device.
+    #[clap(name = "delete")]
+    Delete {
+        /// The ID of the movie, as shown by `list`
+        id: u32,
+    },
+}
+
+/// Operations on movie files.
+#[derive(Subcommand)]
+pub enum MovieFileAction {
+    /// Converts a movie file between the text and the binary format.
+    #[clap(name = "convert")]
+    Convert {
+        /// The movie file to convert, in either format
+        input: PathBuf,
+
+        /// The file to write
+        output: PathBuf,
+
+        /// The format to write, defaults to the format the input isn't in
+        #[clap(long, value_enum)]
+        to: Option<MovieFileFormat>,
+
+        /// The LED profile to write, defaults to the profile of the input
+        #[clap(long, value_enum)]
+        led_profile: Option<LedProfile>,
+
+        /// Sets the title, only kept by the binary format
+        #[clap(long)]
+        title: Option<String>,
+
+        /// Sets the UUID of the layout the movie was made for, only kept by the binary format
+        #[clap(long)]
+        layout_uuid: Option<String>,
+
+        /// How colors are split for RGBW files: min, luminance, or the white LED's color temperature like 2700K
+        #[clap(long, default_value = "min")]
+        white_extraction: WhiteExtraction,
+    },
+    /// Imports an animated GIF or a sequence of images, sampled at the LED positions of a layout.
+    #[clap(name = "import-gif")]
+    ImportGif {
+        /// An animated GIF, or the images of a sequence in playing order
+        #[clap(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// The movie file to write
+        #[clap(long)]
+        output: Option<PathBuf>,
+
+        /// The format of the movie file
+        #[clap(long, value_enum, default_value_t = MovieFileFormat::Binary)]
+        format: MovieFileFormat,
+
+        /// A JSON file with the layout as returned by the device, or with a list of coordinates
+        #[clap(long)]
+        layout: Option<PathBuf>,
+
+        /// The IP address of the device to fetch the layout from and to upload to
+        #[clap(long, requires = "mac")]
+        ip: Option<String>,
+
+        /// The MAC address of the device
+        #[clap(long, requires = "ip")]
+        mac: Option<String>,
+
+        /// The layout axis running left to right in the images
+        #[clap(long, value_enum, default_value_t = Axis::X)]
+        horizontal_axis: Axis,
+
+        /// The layout axis running bottom to top in the images
+        #[clap(long, value_enum, default_value_t = Axis::Y)]
+        vertical_axis: Axis,
+
+        /// How the images are fitted onto the LEDs
+        #[clap(long, value_enum, default_value_t = FitMode::Cover)]
+        fit: FitMode,
+
+        /// Frames per second, GIFs are resampled to it; image sequences default to 10
+        #[clap(long)]
+        fps: Option<f64>,
+
+        /// The LED profile of the movie, defaults to the device's profile or RGB
+        #[clap(long, value_enum)]
+        led_profile: Option<LedProfile>,
+
+        /// The title of the movie, only kept by the binary format
+        #[clap(long)]
+        title: Option<String>,
+
+        /// Uploads the movie to the device under this name
+        #[clap(long, requires = "ip")]
+        upload: Option<String>,
+    },
+    /// Renders a pattern or spatial effect into a movie.
+    #[clap(name = "render")]
+    Render {
+        /// The movie file to write
+        #[clap(long)]
+        output: Option<PathBuf>,
+
+        /// The format of the movie file
+        #[clap(long, value_enum, default_value_t = MovieFileFormat::Binary)]
+        format: MovieFileFormat,
+
+        /// The length of the movie in milliseconds
+        #[clap(long, value_parser = parse_duration)]
+        duration: Duration,
+
+        /// Frames per second
+        #[clap(long, default_value_t = 25.0)]
+        fps: f64,
+
+        /// The number of LEDs for pattern effects, defaults to the device's
+        #[clap(long)]
+        leds: Option<usize>,
+
+        /// The layout for spatial effects, a JSON file as returned by the device or with a list of coordinates
+        #[clap(long)]
+        layout: Option<PathBuf>,
+
+        /// The IP address of the device to take the LEDs and layout from and to upload to
+        #[clap(long, requires = "mac")]
+        ip: Option<String>,
+
+        /// The MAC address of the device
+        #[clap(long, requires = "ip")]
+        mac: Option<String>,
+
+        /// The LED profile of the movie, defaults to the device's profile or RGB
+        #[clap(long, value_enum)]
+        led_profile: Option<LedProfile>,
+
+        /// The title of the movie, only kept by the binary format
+        #[clap(long)]
+        title: Option<String>,
+
+        /// Uploads the movie to the device under this name
+        #[clap(long, requires = "ip")]
+        upload: Option<String>,
+
+        #[clap(subcommand)]
+        effect: RenderEffect,
+    },
+    /// Draws a movie as dots at the LED positions, to an animated GIF or a PNG contact sheet.
+    Preview {
+        /// The movie file, in the text or binary format
+        input: PathBuf,
+
+        /// The image to write, an animated GIF for a .gif extension, else a PNG contact sheet
+        output: PathBuf,
+
+        /// A JSON file with the layout as returned by the device, or with a list of coordinates
+        #[clap(long)]
+        layout: Option<PathBuf>,
+
+        /// The IP address of the device to fetch the layout from
+        #[clap(long, requires = "mac")]
+        ip: Option<String>,
+
+        /// The MAC address of the device
+        #[clap(long, requires = "ip")]
+        mac: Option<String>,
+
+        /// The layout axis running left to right in the images
+        #[clap(long, value_enum, default_value_t = Axis::X)]
+        horizontal_axis: Axis,
+
+        /// The layout axis running bottom to top in the images
+        #[clap(long, value_enum, default_value_t = Axis::Y)]
+        vertical_axis: Axis,
+
+        /// The width of a frame in pixels
+        #[clap(long, default_value_t = 400)]
+        width: u32,
+
+        /// The height of a frame in pixels
+        #[clap(long, default_value_t = 400)]
+        height: u32,
+
+        /// The radius of the LED dots in pixels
+        #[clap(long, default_value_t = 4.0)]
+        dot_radius: f64,
+
+        /// The number of frames per row of the contact sheet
+        #[clap(long, default_value_t = 8)]
+        columns: u32,
+
+        /// Only draws every nth frame
+        #[clap(long, default_value_t = 1)]
+        step: usize,
+
+        /// How RGBW movies were split into colors: min, luminance, or the white LED's color temperature like 2700K
+        #[clap(long, default_value = "min")]
+        white_extraction: WhiteExtraction,
+    },
+}
+
+/// The effects `movie-file render` can render.
+#[derive(Subcommand)]
+pub enum RenderEffect {
+    /// A color pattern.
+    #[clap(name = "pattern")]
+    Pattern {
+        #[clap(subcommand)]
+        effect: PatternEffect,
+    },
+    /// An effect following the layout of the LEDs.
+    #[clap(name = "spatial")]
+    Spatial {
+        #[clap(subcommand)]
+        effect: SpatialEffectKind,
+    },
+}
+
+/**
+Operations on the device registry.
+
+Devices are added to the registry by `discover`, and keep their aliases when their IP
+address changes.
+ */
+#[derive(Subcommand)]
+pub enum DevicesAction {
+    /// Lists the registered devices with their last known IP addresses and aliases
+    #[clap(name = "list")]
+    List,
+    /// Gives a registered device an alias, moving it from another device if needed
+    #[clap(name = "alias")]
+    Alias {
+        /// The alias, e.g. porch
+        alias: String,
+
+        /// The device, by its MAC address or another alias
+        device: String,
+    },
+    /// Removes an alias
+    #[clap(name = "unalias")]
+    Unalias {
+        /// The alias to remove
+        alias: String,
+    },
+    /// Removes a device and its aliases from the registry
+    #[clap(name = "forget")]
+    Forget {
+        /// The device, by its MAC address or an alias
+        device: String,
+    },
+}
+
+/// Operations on layout files.
+#[derive(Subcommand)]
+pub enum LayoutFileAction {
+    /**
+    Corrects a layout file.
+
+    The transformations are applied in this order: reorder, reverse, mirror, rotate, scale,
+    translate and normalize.
+     */
+    #[clap(name = "transform")]
+    Transform {
+        /// The layout file to read
+        input: PathBuf,
+
+        /// The layout file to write
+        output: PathBuf,
+
+        /// The format of the output, by default chosen by its extension
+        #[clap(long, value_enum)]
+        format: Option<LayoutFileFormat>,
+
+        /// Overrides the source of the layout
+        #[clap(long, value_enum)]
+        source: Option<LayoutSource>,
+
+        /// The new order of the LEDs as indices and ranges of the old order, e.g. 0-99,249-100
+        #[clap(long, value_parser = parse_led_order)]
+        reorder: Option<LedOrder>,
+
+        /// Reverses the order of the LEDs
+        #[clap(long)]
+        reverse: bool,
+
+        /// Flips the layout along an axis, can be repeated
+        #[clap(long, value_enum)]
+        mirror: Vec<Axis>,
+
+        /// Rotates the layout around its center by AXIS:DEGREES, can be repeated
+        #[clap(long, value_parser = parse_rotation, allow_hyphen_values = true)]
+        rotate: Vec<(Axis, f64)>,
+
+        /// Scales the layout by X,Y,Z or by a single factor
+        #[clap(long, value_parser = parse_vector, allow_hyphen_values = true)]
+        scale: Option<(f64, f64, f64)>,
+
+        /// Moves the layout by X,Y,Z
+        #[clap(long, value_parser = parse_vector, allow_hyphen_values = true)]
+        translate: Option<(f64, f64, f64)>,
+
+        /// Fits the layout into the ranges the device accepts, keeping its proportions
+        #[clap(long)]
+        normalize: bool,
+    },
+    /// Maps the layout from the photos taken during `device-call map-layout`.
+    #[clap(name = "map")]
+    Map {
+        /// The directory with the photos, taken in the order of their file names
+        photos: PathBuf,
+
+        /// The layout file to write
+        output: PathBuf,
+
+        /// The number of LEDs of the device
+        #[clap(long)]
+        leds: usize,
+
+        /// The sequence the photos were taken of
+        #[clap(long, value_enum, default_value_t = MappingSequence::Binary)]
+        sequence: MappingSequence,
+
+        /// How much brighter than in the dark photo a pixel must be to count as lit, out of 255
+        #[clap(long, default_value_t = MappingOptions::default().threshold)]
+        threshold: u8,
+
+        /// Spots with fewer lit pixels are taken for noise
+        #[clap(long, default_value_t = MappingOptions::default().min_spot_pixels)]
+        min_spot_pixels: usize,
+
+        /// The format of the output, by default chosen by its extension
+        #[clap(long, value_enum)]
+        format: Option<LayoutFileFormat>,
+    },
+    /// Checks a layout file for problems before uploading it.
+    #[clap(name = "validate")]
+    Validate {
+        /// The layout file to check
+        input: PathBuf,
+
+        /// The number of LEDs of the device
+        #[clap(long)]
+        leds: Option<usize>,
+    },
+}
+
+/// A layout file, either the device's response or only the coordinates.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LayoutFile {
+    Response(LayoutResponse),
+    Coordinates(Vec<LedCoordinate>),
+}
+
+/// Layout operations on the device.
+#[derive(Subcommand)]
+pub enum LayoutAction {
+    /// Saves the layout of the device to a JSON or CSV file.
+    #[clap(name = "download")]
+    Download {
+        /// The layout file to write
+        output: PathBuf,
+
+        /// The format of the file, by default chosen by its extension
+        #[clap(long, value_enum)]
+        format: Option<LayoutFileFormat>,
+    },
+    /// Validates a layout from a JSON or CSV file and uploads it to the device.
+    #[clap(name = "upload")]
+    Upload {
+        /// The layout file to read
+        input: PathBuf,
+
+        /// Overrides the source recorded in the file
+        #[clap(long, value_enum)]
+        source: Option<LayoutSource>,
+    },
+}
+
+/// Playlist operations on the device.
+#[derive(Subcommand)]
+pub enum PlaylistAction {
+    /// Shows the playlist entries.
+    #[clap(name = "show")]
+    Show,
+    /// Replaces the playlist with movies already stored on the device.
+    #[clap(name = "set")]
+    Set {
+        /// The name of the playlist
+        #[clap(long, default_value = "")]
+        name: String,
+
+        /// An entry as MOVIE_ID:SECONDS, in playing order
+        #[clap(long = "entry", required = true, value_parser = parse_playlist_item)]
+        entries: Vec<PlaylistItem>,
+    },
+    /// Uploads the movies of a YAML playlist file that aren't stored yet and installs the playlist.
+    #[clap(name = "install")]
+    Install {
+        /// The playlist file
+        path: PathBuf,
+
+        /// The LED profile of the movie files, defaults to the device's profile
+        #[clap(long, value_enum)]
+        led_profile: Option<LedProfile>,
+
+        /// Switches the device to playlist mode afterwards
+        #[clap(long)]
+        play: bool,
+    },
+    /// Shows the entry played in playlist mode.
+    #[clap(name = "current")]
+    Current,
+    /// Switches to another entry of the playlist.
+    #[clap(name = "select")]
+    Select {
+        /// The ID of the entry, as shown by `show`
+        id: u32,
+    },
+    /// Deletes the playlist, keeping its movies.
+    #[clap(name = "delete")]
+    Delete,
+}
+
+/**
+A playlist file for `playlist install`, e.g.
+
+```yaml
+name: evening
+entries:
+  - movie: sparkle.txt
+    duration: 30
+  - movie: fade.txt
+    name: slow fade
+    duration: 60
+    frame_delay: 40
+```
+
+Movie paths are relative to the playlist file.
+ */
+#[derive(Deserialize)]
+struct PlaylistFile {
+    #[serde(default)]
+    name: String,
+    entries: Vec<PlaylistFileEntry>,
+}
+
+#[derive(Deserialize)]
+struct PlaylistFileEntry {
+    /// The movie file.
+    movie: PathBuf,
+    /// The name of the movie on the device, defaults to the file name without extension.
+    /// A stored movie with this name is reused instead of uploading the file.
+    name: Option<String>,
+    /// Seconds the movie plays.
+    duration: u64,
+    /// Time between frames in milliseconds, defaults to the fps of the movie file.
+    frame_delay: Option<u64>,
+}
+
+fn parse_playlist_item(s: &str) -> Result<PlaylistItem, &'static str> {
+    let (movie_id, seconds) = s.split_once(':').ok_or("expected MOVIE_ID:SECONDS")?;
+    Ok(PlaylistItem {
+        movie_id: movie_id.parse().map_err(|_| "could not parse movie ID")?,
+        duration: Duration::from_secs(seconds.parse().map_err(|_| "could not parse seconds")?),
+    })
+}
+
+fn parse_rotation(s: &str) -> Result<(Axis, f64), String> {
+    let (axis, degrees) = s.split_once(':').ok_or("expected AXIS:DEGREES")?;
+    Ok((
+        Axis::from_str(axis, true)?,
+        degrees.parse().map_err(|_| "could not parse degrees")?,
+    ))
+}
+
+/// Parses a range of numbers as `min,max`.
+fn parse_range(s: &str) -> Result<(f64, f64), &'static str> {
+    let (min, max) = s.split_once(',').ok_or("expected MIN,MAX")?;
+    let parse = |value: &str| value.trim().parse::<f64>();
+    Ok((
+        parse(min).map_err(|_| "could not parse minimum")?,
+        parse(max).map_err(|_| "could not parse maximum")?,
+    ))
+}
+
+/// The color of a [CliColors] value as an RGB tuple.
+fn cli_color(color: CliColors) -> (u8, u8, u8) {
+    let rgb: RGB = color.into();
+    (rgb.red, rgb.green, rgb.blue)
+}
+
+/// Parses `x,y,z`, or a single number for all three axes.
+fn parse_vector(s: &str) -> Result<(f64, f64, f64), &'static str> {
+    let values = s
+        .split(',')
+        .map(|value| value.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "could not parse number")?;
+    match values[..] {
+        [value] => Ok((value, value, value)),
+        [x, y, z] => Ok((x, y, z)),
+        _ => Err("expected X,Y,Z or a single number"),
+    }
+}
+
+/// LED indices in a new order, an alias so clap takes them as one value rather than many.
+type LedOrder = Vec<usize>;
+
+/// Parses a comma separated list of LED indices and ranges like `0-99,249-100`.
+fn parse_led_order(s: &str) -> Result<LedOrder, &'static str> {
+    let index = |value: &str| {
+        value
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| "could not parse LED index")
+    };
+    let mut order = Vec::new();
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (index(first)?, index(last)?);
+                if first <= last {
+                    order.extend(first..=last);
+                } else {
+                    order.extend((last..=first).rev());
+                }
+            }
+            None => order.push(index(part)?),
+        }
+    }
+    Ok(order)
 }
 
 fn parse_duration(s: &str) -> Result<Duration, &'static str> {
@@ -130,6 +1249,30 @@ fn parse_duration(s: &str) -> Result<Duration, &'static str> {
         .map_err(|_| "could not parse duration in milliseconds")?;
     Ok(Duration::from_millis(millis))
 }
+
+/// Prints an HTTP exchange to stderr, keeping stdout for the command output.
+fn print_http_exchange(exchange: &HttpExchange) {
+    let body = |body: Option<&[u8]>| {
+        body.map(|body| String::from_utf8_lossy(body).into_owned())
+            .unwrap_or_default()
+    };
+    eprintln!(
+        "> {} {} {}",
+        exchange.method,
+        exchange.url,
+        body(exchange.request_body.as_deref())
+    );
+    match exchange.status {
+        Some(status) => eprintln!(
+            "< {} ({:?}) {}",
+            status,
+            exchange.elapsed,
+            body(exchange.response_body.as_deref())
+        ),
+        None => eprintln!("< no response ({:?})", exchange.elapsed),
+    }
+}
+
 /// Actions available under the `device-call` subcommand
 #[derive(Subcommand)]
 pub enum DeviceAction {
@@ -152,10 +1295,45 @@ pub enum DeviceAction {
     /// Fetches the LED layout.
     #[clap(name = "fetch-layout")]
     FetchLayout,
+    /// Subcommand for downloading and uploading the LED layout.
+    #[clap(name = "layout")]
+    Layout {
+        #[clap(subcommand)]
+        action: LayoutAction,
+    },
+    /// Lights the LEDs step by step for taking the photos to map the layout from, see `layout map`.
+    #[clap(name = "map-layout")]
+    MapLayout {
+        /// How the LEDs are told apart in the photos
+        #[clap(long, value_enum, default_value_t = MappingSequence::Binary)]
+        sequence: MappingSequence,
+
+        /// The brightness of the lit LEDs, lower it if the photos are overexposed
+        #[clap(long, default_value_t = 255)]
+        brightness: u8,
+
+        /// Moves to the next step after this many milliseconds, instead of waiting for Enter
+        #[clap(long, value_parser = parse_duration)]
+        interval: Option<Duration>,
+    },
     /// Clears all uploaded movies from the device.
     #[clap(name = "clear-movies")]
     ClearMovies,
 
+    /// Subcommand for managing the stored movies.
+    #[clap(name = "movies")]
+    Movies {
+        #[clap(subcommand)]
+        action: MovieAction,
+    },
+
+    /// Subcommand for authoring and switching the playlist.
+    #[clap(name = "playlist")]
+    Playlist {
+        #[clap(subcommand)]
+        action: PlaylistAction,
+    },
+
     /// Retrieves the device's capacity for movies.
     #[clap(name = "get-device-capacity")]
     GetDeviceCapacity,
@@ -193,25 +1371,146 @@ pub enum DeviceAction {
 }
 
 async fn handle_cli(cli: Cli) -> Result<()> {
+    let registry_path = cli.registry;
     match cli.command {
-        Commands::Discover { output, timeout } => {
-            let devices = Discovery::find_devices(Duration::from_millis(timeout)).await?;
-            match output {
-                OutputFormat::Plaintext => {
-                    Discovery::pretty_print_devices(&devices);
+        Commands::Discover {
+            output,
+            timeout,
+            watch,
+            interval,
+            missed_rounds,
+            interfaces,
+            subnets,
+            ranges,
+            hosts,
+            concurrency,
+            no_auth,
+        } => {
+            let targets: Vec<DiscoveryTarget> = interfaces
+                .into_iter()
+                .map(DiscoveryTarget::Interface)
+                .chain(subnets.into_iter().map(DiscoveryTarget::Subnet))
+                .chain(ranges.into_iter().map(DiscoveryTarget::Range))
+                .chain(hosts.into_iter().map(DiscoveryTarget::Host))
+                .collect();
+            let mut options = DiscoveryOptions::new(Duration::from_millis(timeout));
+            if !targets.is_empty() {
+                options.targets = targets;
+            }
+            options.concurrency = concurrency;
+            options.authenticate = !no_auth;
+
+            if watch {
+                let mut events = DiscoveryWatcher::new(Duration::from_millis(interval))
+                    .with_options(options)
+                    .with_missed_rounds(missed_rounds)
+                    .spawn();
+                while let Some(event) = events.recv().await {
+                    match output {
+                        OutputFormat::Plaintext => println!("{}", event),
+                        OutputFormat::Json => println!("{}", serde_json::to_string(&event)?),
+                        OutputFormat::Yaml => println!("---\n{}", serde_yaml::to_string(&event)?),
+                    }
+                    match &event {
+                        DiscoveryEvent::Appeared { device }
+                        | DiscoveryEvent::IpChanged { device, .. }
+                        | DiscoveryEvent::Renamed { device, .. } => {
+                            update_registry(&registry_path, [device])
+                        }
+                        DiscoveryEvent::Disappeared { .. } => {}
+                    }
                 }
-                OutputFormat::Json => {
-                    let json = serde_json::to_string(&devices)?;
-                    println!("{}", json);
+            } else {
+                let result = Discovery::find_new_devices_with(&options, None).await?;
+                for failure in &result.failed_devices {
+                    eprintln!("Failed to identify device {}", failure);
                 }
-                OutputFormat::Yaml => {
-                    let yaml = serde_yaml::to_string(&devices)?;
-                    println!("{}", yaml);
+                let devices = result.new_devices;
+                match output {
+                    OutputFormat::Plaintext => {
+                        Discovery::pretty_print_devices(&devices);
+                    }
+                    OutputFormat::Json => {
+                        let json = serde_json::to_string(&devices)?;
+                        println!("{}", json);
+                    }
+                    OutputFormat::Yaml => {
+                        let yaml = serde_yaml::to_string(&devices)?;
+                        println!("{}", yaml);
+                    }
                 }
+                update_registry(&registry_path, &devices);
             }
         }
-        Commands::DeviceCall { ip, mac, action } => {
-            let high_control_interface = ControlInterface::new(&ip, &mac, None).await?;
+        Commands::Layout { action } => handle_layout_file_action(action)?,
+        Commands::Movie { action } => handle_movie_file_action(action).await?,
+        Commands::Devices { action } => handle_devices_action(&registry_path, action)?,
+        Commands::Emulate {
+            bind,
+            http_port,
+            rt_port,
+            discovery_port,
+            name,
+            mac,
+            leds,
+            led_profile,
+        } => {
+            let mut device_info = ControlInterface::new_mock_device_info_response(
+                "000000".to_string(),
+                name,
+                mac,
+                leds,
+            );
+            device_info.led_profile = led_profile;
+            device_info.bytes_per_led = led_profile.bytes_per_led();
+            let mut config = EmulatorConfig::on_standard_ports(bind, device_info);
+            config.http_addr = SocketAddr::new(bind, http_port);
+            config.rt_addr = SocketAddr::new(bind, rt_port);
+            config.discovery_addr = Some(SocketAddr::new(bind, discovery_port));
+
+            let emulator = DeviceEmulator::start(config).await?;
+            println!(
+                "Emulating device {} on http://{}, real-time on {}",
+                emulator.mac(),
+                emulator.http_addr(),
+                emulator.rt_addr()
+            );
+            emulator.run().await?;
+        }
+        Commands::DeviceCall {
+            ip,
+            mac,
+            device,
+            rt_protocol,
+            trace_http,
+            white_extraction,
+            action,
+        } => {
+            let trace_hook: Option<TraceHook> = if trace_http {
+                Some(Arc::new(print_http_exchange))
+            } else {
+                None
+            };
+            let high_control_interface = match (device, ip, mac) {
+                (Some(device), _, _) => {
+                    let path = registry_file(&registry_path)?;
+                    let mut registry = DeviceRegistry::load(&path)?;
+                    let options = DiscoveryOptions::new(Duration::from_millis(5000));
+                    let control = registry.connect(&device, &options, trace_hook).await;
+                    // The registry is saved even if the device wasn't found, with the devices found meanwhile
+                    registry.save(&path)?;
+                    control?
+                }
+                (None, Some(ip), Some(mac)) => {
+                    ControlInterface::new_with_trace_hook(&ip, &mac, None, trace_hook).await?
+                }
+                _ => return Err(anyhow!("Either --device or --ip and --mac are required")),
+            };
+            let mut high_control_interface =
+                high_control_interface.with_white_extraction(white_extraction);
+            if let Some(rt_protocol) = rt_protocol {
+                high_control_interface = high_control_interface.with_rt_protocol(rt_protocol);
+            }
 
             match action {
                 DeviceAction::GetMode => {
@@ -242,11 +1541,62 @@ async fn handle_cli(cli: Cli) -> Result<()> {
                     println!("LED layout fetched: {:?}", layout);
                 }
 
+                DeviceAction::Layout { action } => {
+                    handle_layout_action(&high_control_interface, action).await?;
+                }
+
+                DeviceAction::MapLayout {
+                    sequence,
+                    brightness,
+                    interval,
+                } => {
+                    high_control_interface
+                        .play_mapping_sequence(
+                            sequence,
+                            (brightness, brightness, brightness),
+                            |step, steps| async move {
+                                match interval {
+                                    Some(interval) => {
+                                        println!("Step {}/{}: taking a photo.", step + 1, steps);
+                                        tokio::time::sleep(interval).await;
+                                    }
+                                    None => {
+                                        println!(
+                                            "Step {}/{}: take a photo, then press Enter.",
+                                            step + 1,
+                                            steps
+                                        );
+                                        tokio::task::spawn_blocking(|| {
+                                            std::io::stdin().read_line(&mut String::new())
+                                        })
+                                        .await
+                                        .expect("reading stdin panicked")?;
+                                    }
+                                }
+                                Ok(())
+                            },
+                        )
+                        .await?;
+                    println!(
+                        "Done. Map the layout with: glow-control layout map <PHOTO_DIR> <OUTPUT> --leds {} --sequence {}",
+                        high_control_interface.get_device_info().number_of_led,
+                        sequence.to_possible_value().expect("no skipped variants").get_name()
+                    );
+                }
+
                 DeviceAction::ClearMovies => {
                     high_control_interface.clear_movies().await?;
                     println!("All movies have been cleared from the device.");
                 }
 
+                DeviceAction::Movies { action } => {
+                    handle_movie_action(&high_control_interface, action).await?;
+                }
+
+                DeviceAction::Playlist { action } => {
+                    handle_playlist_action(&high_control_interface, action).await?;
+                }
+
                 DeviceAction::GetDeviceCapacity => {
                     let capacity = high_control_interface.get_device_capacity().await?;
                     println!("Device capacity for movies: {}", capacity);
@@ -291,6 +1641,7 @@ async fn handle_cli(cli: Cli) -> Result<()> {
                             time_to_fade,
                             colors,
                             frame_rate,
+                            seed,
                         } => {
                             // Convert the list of CliColors to a HashSet of RGB
                             let color_set: HashSet<RGB> =
@@ -300,17 +1651,59 @@ async fn handle_cli(cli: Cli) -> Result<()> {
                             if color_set.is_empty() {
                                 return Err(anyhow!("At least one color must be specified"));
                             }
+                            let mut shine = ShineAnimation::new(
+                                high_control_interface.get_device_info().number_of_led,
+                                color_set,
+                                time_between_glow_start,
+                                time_to_max_glow,
+                                time_to_fade,
+                                num_start_simultaneous,
+                                frame_rate,
+                            )?;
+                            if let Some(seed) = seed {
+                                shine = shine.with_seed(seed);
+                            }
                             high_control_interface
-                                .shine_leds(
-                                    time_between_glow_start,
-                                    time_to_max_glow,
-                                    time_to_fade,
-                                    color_set,
+                                .play_animation(&mut shine, frame_rate)
+                                .await?;
+                            println!("Shine effect started.");
+                        }
+                        RtEffect::Spatial {
+                            frame_rate,
+                            duration,
+                            effect,
+                        } => {
+                            high_control_interface
+                                .show_spatial_effect(
+                                    effect.into_effect().as_ref(),
                                     frame_rate,
-                                    num_start_simultaneous,
+                                    duration,
                                 )
                                 .await?;
-                            println!("Shine effect started.");
+                            println!("Spatial effect finished.");
+                        }
+                        RtEffect::Pattern {
+                            frame_rate,
+                            duration,
+                            effect,
+                        } => {
+                            let mut animation = effect.into_animation()?;
+                            match duration {
+                                Some(duration) => {
+                                    high_control_interface
+                                        .play_animation(
+                                            &mut animation.limit(duration.as_secs_f64()),
+                                            frame_rate,
+                                        )
+                                        .await?
+                                }
+                                None => {
+                                    high_control_interface
+                                        .play_animation(&mut animation, frame_rate)
+                                        .await?
+                                }
+                            }
+                            println!("Pattern effect finished.");
                         }
                     }
                 }
@@ -335,3 +1728,587 @@ async fn handle_cli(cli: Cli) -> Result<()> {
 
     Ok(())
 }
+
+async fn handle_movie_action(control: &ControlInterface, action: MovieAction) -> Result<()> {
+    match action {
+        MovieAction::List => {
+            let movies = control.get_movies().await?;
+            for movie in &movies.movies {
+                println!(
+                    "ID: {}, Name: {}, Frames: {}, FPS: {}, UUID: {}",
+                    movie.id, movie.name, movie.frames_number, movie.fps, movie.unique_id
+                );
+            }
+            println!(
+                "Available frames: {} of {}",
+                movies.available_frames, movies.max_capacity
+            );
+        }
+        MovieAction::Current => {
+            let movie = control.get_current_movie().await?;
+            println!(
+                "Current movie: ID: {}, Name: {}, UUID: {}",
+                movie.id, movie.name, movie.unique_id
+            );
+        }
+        MovieAction::Select { id } => {
+            control.set_current_movie(id).await?;
+            println!("Movie {} selected.", id);
+        }
+        MovieAction::Upload {
+            path,
+            name,
+            led_profile,
+            frame_delay,
+        } => {
+            let led_profile = led_profile.unwrap_or(control.get_device_info().led_profile);
+            let movie = Movie::load(&path, led_profile, control.white_extraction())?;
+            let stored = control
+                .upload_named_movie(&movie, &name, led_profile, frame_delay)
+                .await?;
+            println!("Movie {} uploaded with ID {}.", stored.name, stored.id);
+        }
+        MovieAction::Delete { id } => {
+            control.delete_movie(id).await?;
+            println!("Movie {} deleted.", id);
+        }
+    }
+    Ok(())
+}
+
+async fn handle_playlist_action(control: &ControlInterface, action: PlaylistAction) -> Result<()> {
+    match action {
+        PlaylistAction::Show => {
+            let playlist = control.get_playlist().await?;
+            println!("Playlist: {}", playlist.name);
+            for entry in &playlist.entries {
+                println!(
+                    "ID: {}, Movie: {} (ID {}), Duration: {}s",
+                    entry.id, entry.name, entry.handle, entry.duration
+                );
+            }
+        }
+        PlaylistAction::Set { name, entries } => {
+            let playlist = control.set_playlist(&name, &entries).await?;
+            println!("Playlist set with {} entries.", playlist.entries.len());
+        }
+        PlaylistAction::Install {
+            path,
+            led_profile,
+            play,
+        } => {
+            let led_profile = led_profile.unwrap_or(control.get_device_info().led_profile);
+            let playlist = install_playlist_file(control, &path, led_profile).await?;
+            println!(
+                "Playlist {} installed with {} entries.",
+                playlist.name,
+                playlist.entries.len()
+            );
+            if play {
+                control.set_mode(DeviceMode::Playlist).await?;
+            }
+        }
+        PlaylistAction::Current => {
+            let entry = control.get_current_playlist_entry().await?;
+            println!(
+                "Current entry: ID: {}, Movie: {}, Duration: {}s",
+                entry.id, entry.name, entry.duration
+            );
+        }
+        PlaylistAction::Select { id } => {
+            control.set_current_playlist_entry(id).await?;
+            println!("Playlist entry {} selected.", id);
+        }
+        PlaylistAction::Delete => {
+            control.delete_playlist().await?;
+            println!("Playlist deleted.");
+        }
+    }
+    Ok(())
+}
+
+/// Uploads the movies of a playlist file missing on the device, by name, and installs the playlist.
+async fn install_playlist_file(
+    control: &ControlInterface,
+    path: &Path,
+    led_profile: LedProfile,
+) -> Result<PlaylistResponse> {
+    let file: PlaylistFile = serde_yaml::from_reader(File::open(path)?)?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    let mut stored = control.get_movies().await?.movies;
+
+    let mut items = Vec::with_capacity(file.entries.len());
+    for entry in file.entries {
+        let movie_path = base_dir.join(&entry.movie);
+        let name = match entry.name {
+            Some(name) => name,
+            None => movie_path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .ok_or_else(|| anyhow!("No movie name for {}", movie_path.display()))?,
+        };
+        let movie_id = match stored.iter().find(|movie| movie.name == name) {
+            Some(movie) => movie.id,
+            None => {
+                let movie = Movie::load(&movie_path, led_profile, control.white_extraction())?;
+                let uploaded = control
+                    .upload_named_movie(
+                        &movie,
+                        &name,
+                        led_profile,
+                        entry.frame_delay.map(Duration::from_millis),
+                    )
+                    .await?;
+                println!("Movie {} uploaded with ID {}.", uploaded.name, uploaded.id);
+                let id = uploaded.id;
+                stored.push(uploaded);
+                id
+            }
+        };
+        items.push(PlaylistItem {
+            movie_id,
+            duration: Duration::from_secs(entry.duration),
+        });
+    }
+
+    Ok(control.set_playlist(&file.name, &items).await?)
+}
+
+async fn handle_layout_action(control: &ControlInterface, action: LayoutAction) -> Result<()> {
+    match action {
+        LayoutAction::Download { output, format } => {
+            let response = control.fetch_layout().await?;
+            let source = LayoutSource::from_str(&response.source, true)
+                .map_err(|e| anyhow!("Unknown layout source {}: {}", response.source, e))?;
+            let layout = Layout {
+                source,
+                coordinates: response.coordinates,
+            };
+            layout.save(
+                &output,
+                format.unwrap_or(LayoutFileFormat::for_path(&output)),
+            )?;
+            println!(
+                "Layout of {} LEDs written to {}.",
+                layout.coordinates.len(),
+                output.display()
+            );
+        }
+        LayoutAction::Upload { input, source } => {
+            let mut layout = Layout::load(&input)?;
+            if let Some(source) = source {
+                layout.source = source;
+            }
+            control.upload_layout(&layout).await?;
+            println!("Layout of {} LEDs uploaded.", layout.coordinates.len());
+        }
+    }
+    Ok(())
+}
+
+/// The registry file given by --registry, or the default one.
+fn registry_file(registry_path: &Option<PathBuf>) -> Result<PathBuf> {
+    registry_path
+        .clone()
+        .or_else(DeviceRegistry::default_path)
+        .ok_or_else(|| anyhow!("No configuration directory found, pass --registry"))
+}
+
+/**
+Adds discovered devices to the registry, or updates their IP addresses and names.
+
+Discovery works without a registry, so failing to update it only prints a warning.
+ */
+fn update_registry<'a>(
+    registry_path: &Option<PathBuf>,
+    devices: impl IntoIterator<Item = &'a DeviceIdentifier>,
+) {
+    let update = || -> Result<()> {
+        let path = registry_file(registry_path)?;
+        let mut registry = DeviceRegistry::load(&path)?;
+        for device in devices {
+            registry.update(device);
+        }
+        registry.save(&path)?;
+        Ok(())
+    };
+    if let Err(e) = update() {
+        eprintln!("Warning: failed to update the device registry: {:#}", e);
+    }
+}
+
+fn handle_devices_action(registry_path: &Option<PathBuf>, action: DevicesAction) -> Result<()> {
+    let path = registry_file(registry_path)?;
+    let mut registry = DeviceRegistry::load(&path)?;
+    match action {
+        DevicesAction::List => {
+            for device in registry.devices() {
+                let aliases: Vec<&str> = device.aliases.iter().map(String::as_str).collect();
+                println!(
+                    "{:<17}  {:<15}  {:<24}  {}",
+                    device.mac_address,
+                    device.ip_address,
+                    device.device_name,
+                    aliases.join(", ")
+                );
+            }
+            return Ok(());
+        }
+        DevicesAction::Alias { alias, device } => {
+            registry.set_alias(&alias, &device)?;
+            println!("{} is now an alias of {}", alias, device);
+        }
+        DevicesAction::Unalias { alias } => {
+            if !registry.remove_alias(&alias) {
+                return Err(anyhow!("No device has the alias {}", alias));
+            }
+            println!("Removed the alias {}", alias);
+        }
+        DevicesAction::Forget { device } => {
+            let forgotten = registry
+                .forget(&device)
+                .ok_or_else(|| anyhow!("No device {} is registered", device))?;
+            println!(
+                "Removed {} ({}) from the registry",
+                forgotten.device_name, forgotten.mac_address
+            );
+        }
+    }
+    registry.save(&path)?;
+    Ok(())
+}
+
+fn handle_layout_file_action(action: LayoutFileAction) -> Result<()> {
+    match action {
+        LayoutFileAction::Transform {
+            input,
+            output,
+            format,
+            source,
+            reorder,
+            reverse,
+            mirror,
+            rotate,
+            scale,
+            translate,
+            normalize,
+        } => {
+            let mut layout = Layout::load(&input)?;
+            if let Some(source) = source {
+                layout.source = source;
+            }
+            if let Some(order) = reorder {
+                layout.reorder(&order)?;
+            }
+            if reverse {
+                layout.reverse();
+            }
+            for axis in mirror {
+                layout.mirror(axis);
+            }
+            for (axis, degrees) in rotate {
+                layout.rotate(axis, degrees);
+            }
+            if let Some((x, y, z)) = scale {
+                layout.scale(x, y, z);
+            }
+            if let Some((x, y, z)) = translate {
+                layout.translate(x, y, z);
+            }
+            if normalize {
+                layout.normalize();
+            }
+            layout.save(
+                &output,
+                format.unwrap_or(LayoutFileFormat::for_path(&output)),
+            )?;
+            println!("Layout written to {}.", output.display());
+            if let Err(e) = layout.validate(None) {
+                println!("Warning: {}", e);
+            }
+        }
+        LayoutFileAction::Map {
+            photos,
+            output,
+            leds,
+            sequence,
+            threshold,
+            min_spot_pixels,
+            format,
+        } => {
+            let paths = photo_paths(&photos)?;
+            let options = MappingOptions {
+                threshold,
+                min_spot_pixels,
+            };
+            let mapping = LedMapping::from_photos(&paths, sequence, leds, &options)?;
+            let missing = mapping.missing();
+            if !missing.is_empty() {
+                println!(
+                    "{} LEDs weren't found and are placed between their neighbors: {:?}",
+                    missing.len(),
+                    missing
+                );
+            }
+            let layout = mapping.to_layout()?;
+            layout.save(
+                &output,
+                format.unwrap_or(LayoutFileFormat::for_path(&output)),
+            )?;
+            println!("Layout of {} LEDs written to {}.", leds, output.display());
+        }
+        LayoutFileAction::Validate { input, leds } => {
+            let layout = Layout::load(&input)?;
+            layout.validate(leds)?;
+            println!("The layout of {} LEDs is valid.", layout.coordinates.len());
+        }
+    }
+    Ok(())
+}
+
+async fn handle_movie_file_action(action: MovieFileAction) -> Result<()> {
+    match action {
+        MovieFileAction::Convert {
+            input,
+            output,
+            to,
+            led_profile,
+            title,
+            layout_uuid,
+            white_extraction,
+        } => {
+            let header = Movie::read_header(&input)?;
+            let led_profile = led_profile.unwrap_or(header.led_profile);
+            let format = to.unwrap_or(match header.format {
+                MovieFileFormat::Text => MovieFileFormat::Binary,
+                MovieFileFormat::Binary => MovieFileFormat::Text,
+            });
+
+            let mut movie = Movie::load(&input, header.led_profile, white_extraction)?;
+            if title.is_some() {
+                movie.title = title;
+            }
+            if let Some(layout_uuid) = layout_uuid {
+                movie.layout_uuid = Some(layout_uuid.parse()?);
+            }
+            movie.save(&output, format, led_profile, white_extraction)?;
+            println!(
+                "Converted {} frames of {} LEDs to {:?} format in {}.",
+                header.frame_count,
+                header.led_count,
+                format,
+                output.display()
+            );
+        }
+        MovieFileAction::ImportGif {
+            inputs,
+            output,
+            format,
+            layout,
+            ip,
+            mac,
+            horizontal_axis,
+            vertical_axis,
+            fit,
+            fps,
+            led_profile,
+            title,
+            upload,
+        } => {
+            let control = match (ip, mac) {
+                (Some(ip), Some(mac)) => Some(ControlInterface::new(&ip, &mac, None).await?),
+                _ => None,
+            };
+            let (coordinates, layout_uuid) =
+                resolve_layout(layout.as_deref(), control.as_ref()).await?;
+
+            let projection = ImageProjection {
+                horizontal: horizontal_axis,
+                vertical: vertical_axis,
+                fit,
+            };
+            let is_gif = inputs.len() == 1
+                && inputs[0]
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+            let mut movie = if is_gif {
+                Movie::from_gif(&inputs[0], &coordinates, &projection, fps)?
+            } else {
+                Movie::from_image_files(&inputs, &coordinates, &projection, fps.unwrap_or(10.0))?
+            };
+            movie.title = title;
+            movie.layout_uuid = layout_uuid
+                .map(|uuid| uuid.parse())
+                .transpose()
+                .map_err(|e| anyhow!("Invalid layout UUID: {}", e))?;
+            println!(
+                "Imported {} frames at {:.1} fps for {} LEDs.",
+                movie.frames.len(),
+                movie.fps,
+                coordinates.len()
+            );
+
+            let led_profile = led_profile
+                .or(control
+                    .as_ref()
+                    .map(|control| control.get_device_info().led_profile))
+                .unwrap_or(LedProfile::RGB);
+            if let Some(output) = output {
+                movie.save(&output, format, led_profile, WhiteExtraction::default())?;
+                println!("Movie written to {}.", output.display());
+            }
+            if let (Some(name), Some(control)) = (upload, &control) {
+                let stored = control
+                    .upload_named_movie(&movie, &name, led_profile, None)
+                    .await?;
+                println!("Movie {} uploaded with ID {}.", stored.name, stored.id);
+            }
+        }
+        MovieFileAction::Render {
+            output,
+            format,
+            duration,
+            fps,
+            leds,
+            layout,
+            ip,
+            mac,
+            led_profile,
+            title,
+            upload,
+            effect,
+        } => {
+            let control = match (ip, mac) {
+                (Some(ip), Some(mac)) => Some(ControlInterface::new(&ip, &mac, None).await?),
+                _ => None,
+            };
+            let (mut animation, led_count, layout_uuid): (Box<dyn Animation>, _, _) = match effect {
+                RenderEffect::Pattern { effect } => {
+                    let led_count = leds
+                        .or(control
+                            .as_ref()
+                            .map(|control| control.get_device_info().number_of_led))
+                        .ok_or_else(|| anyhow!("Either --leds or --ip and --mac are required"))?;
+                    (effect.into_animation()?, led_count, None)
+                }
+                RenderEffect::Spatial { effect } => {
+                    let (coordinates, layout_uuid) =
+                        resolve_layout(layout.as_deref(), control.as_ref()).await?;
+                    let led_count = coordinates.len();
+                    let animation = SpatialAnimation::new(effect.into_effect(), coordinates);
+                    (Box::new(animation), led_count, layout_uuid)
+                }
+            };
+
+            let mut movie = Movie::from_animation(
+                &mut animation,
+                led_count,
+                fps,
+                Some(duration.as_secs_f64()),
+            )?;
+            movie.title = title;
+            movie.layout_uuid = layout_uuid
+                .map(|uuid| uuid.parse())
+                .transpose()
+                .map_err(|e| anyhow!("Invalid layout UUID: {}", e))?;
+            println!(
+                "Rendered {} frames at {:.1} fps for {} LEDs.",
+                movie.frames.len(),
+                movie.fps,
+                led_count
+            );
+
+            let led_profile = led_profile
+                .or(control
+                    .as_ref()
+                    .map(|control| control.get_device_info().led_profile))
+                .unwrap_or(LedProfile::RGB);
+            if let Some(output) = output {
+                movie.save(&output, format, led_profile, WhiteExtraction::default())?;
+                println!("Movie written to {}.", output.display());
+            }
+            if let (Some(name), Some(control)) = (upload, &control) {
+                let stored = control
+                    .upload_named_movie(&movie, &name, led_profile, None)
+                    .await?;
+                println!("Movie {} uploaded with ID {}.", stored.name, stored.id);
+            }
+        }
+        MovieFileAction::Preview {
+            input,
+            output,
+            layout,
+            ip,
+            mac,
+            horizontal_axis,
+            vertical_axis,
+            width,
+            height,
+            dot_radius,
+            columns,
+            step,
+            white_extraction,
+        } => {
+            let control = match (ip, mac) {
+                (Some(ip), Some(mac)) => Some(ControlInterface::new(&ip, &mac, None).await?),
+                _ => None,
+            };
+            let (coordinates, _) = resolve_layout(layout.as_deref(), control.as_ref()).await?;
+            let header = Movie::read_header(&input)?;
+            let movie = Movie::load(&input, header.led_profile, white_extraction)?;
+            if header.led_count != coordinates.len() {
+                return Err(anyhow!(
+                    "The movie has {} LEDs, but the layout has {}",
+                    header.led_count,
+                    coordinates.len()
+                ));
+            }
+
+            let mut preview = MoviePreview {
+                width,
+                height,
+                dot_radius,
+                ..MoviePreview::default()
+            };
+            preview.projection.horizontal = horizontal_axis;
+            preview.projection.vertical = vertical_axis;
+            let is_gif = output
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+            if is_gif {
+                preview.write_gif(&movie, &coordinates, step, &output)?;
+            } else {
+                preview.write_contact_sheet(&movie, &coordinates, columns, step, &output)?;
+            }
+            println!("Preview written to {}.", output.display());
+        }
+    }
+    Ok(())
+}
+
+/**
+Reads the LED coordinates, and the layout UUID if known, from a JSON or CSV layout file or else
+from the device.
+ */
+async fn resolve_layout(
+    layout: Option<&Path>,
+    control: Option<&ControlInterface>,
+) -> Result<(Vec<LedCoordinate>, Option<String>)> {
+    match (layout, control) {
+        (Some(path), _) if LayoutFileFormat::for_path(path) == LayoutFileFormat::Csv => {
+            Ok((Layout::load(path)?.coordinates, None))
+        }
+        (Some(path), _) => {
+            let layout: LayoutFile = serde_json::from_reader(File::open(path)?)?;
+            Ok(match layout {
+                LayoutFile::Response(layout) => (layout.coordinates, Some(layout.uuid)),
+                LayoutFile::Coordinates(coordinates) => (coordinates, None),
+            })
+        }
+        (None, Some(control)) => {
+            let layout = control.fetch_layout().await?;
+            Ok((layout.coordinates, Some(layout.uuid)))
+        }
+        (None, None) => Err(anyhow!("Either --layout or --ip and --mac are required")),
+    }
+}